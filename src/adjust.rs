@@ -0,0 +1,120 @@
+//! Changing many prices at once for `adjust`: by a factor, by an amount or to a fixed price.
+
+use std::collections::HashSet;
+
+use crate::money::Money;
+use crate::row::Row;
+use crate::store::Changes;
+use crate::Failure;
+
+/// How `adjust` changes each price.
+#[derive(Clone, Copy, Debug)]
+pub enum Adjustment {
+    Multiply(f64),
+    Add(Money),
+    Set(Money),
+}
+
+impl Adjustment {
+    /// The new price; a multiplied one is rounded to the cent. `None` when it would overflow.
+    pub fn apply(self, price: Money) -> Option<Money> {
+        match self {
+            Adjustment::Multiply(f) => Money::round_f64(price.to_f64() * f).map(Money::round_to_cent),
+            Adjustment::Add(amount) => price.units().checked_add(amount.units()).map(Money::from_units),
+            Adjustment::Set(p) => Some(p),
+        }
+    }
+}
+
+/// Why a selected row was left alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Skip {
+    /// Priced 0.00, which is how an unreadable price is loaded.
+    Unpriced,
+    /// The new price would drop below zero.
+    BelowZero,
+}
+
+/// Apply `adjustment`, and `currency` when given, to the rows in `rows` whose id is in `ids`,
+/// in place. Returns the rows that changed and the ones skipped, in file order; a new price
+/// too large to hold fails the whole adjustment.
+pub fn adjust(
+    rows: &mut [Row],
+    ids: &HashSet<u64>,
+    adjustment: Adjustment,
+    currency: Option<&str>,
+) -> Result<(Changes, Vec<(Row, Skip)>), Failure> {
+    let mut changes = Changes::default();
+    let mut skipped = Vec::new();
+    for r in rows.iter_mut().filter(|r| ids.contains(&r.id)) {
+        if r.price == Money::default() {
+            skipped.push((r.clone(), Skip::Unpriced));
+            continue;
+        }
+        let price = adjustment
+            .apply(r.price)
+            .ok_or_else(|| Failure::InvalidInput(format!("New price of #{} {} is too large", r.id, r.product)))?;
+        if price.units() < 0 {
+            skipped.push((r.clone(), Skip::BelowZero));
+            continue;
+        }
+        let old = r.clone();
+        r.price = price;
+        if let Some(c) = currency {
+            r.currency = c.to_string();
+        }
+        if r.price != old.price || r.currency != old.currency {
+            changes.updated.push((old, r.clone()));
+        }
+    }
+    Ok((changes, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: u64, price: &str) -> Row {
+        Row { id, product: format!("Item {}", id), price: price.parse().unwrap(), ..Default::default() }
+    }
+
+    fn prices(rows: &[Row]) -> Vec<String> {
+        rows.iter().map(|r| r.price.to_string()).collect()
+    }
+
+    #[test]
+    fn only_selected_rows_change_and_unusable_ones_are_skipped() {
+        let mut rows = vec![row(1, "10"), row(2, "0"), row(3, "1.50"), row(4, "20"), row(5, "2")];
+        let ids: HashSet<u64> = [1, 2, 3, 5].into();
+        let (changes, skipped) = adjust(&mut rows, &ids, Adjustment::Add("-2".parse().unwrap()), Some("EUR")).unwrap();
+        assert_eq!(prices(&rows), ["8.00", "0.00", "1.50", "20.00", "0.00"]);
+        let updated: Vec<u64> = changes.updated.iter().map(|(old, _)| old.id).collect();
+        assert_eq!(updated, [1, 5]);
+        assert_eq!(rows[0].currency, "EUR");
+        assert!(rows[3].currency.is_empty());
+        let skipped: Vec<(u64, Skip)> = skipped.into_iter().map(|(r, why)| (r.id, why)).collect();
+        assert_eq!(skipped, [(2, Skip::Unpriced), (3, Skip::BelowZero)]);
+    }
+
+    #[test]
+    fn multiplying_rounds_to_the_cent_and_unchanged_rows_are_not_updates() {
+        let mut rows = vec![row(1, "3.33"), row(2, "5")];
+        let ids: HashSet<u64> = [1, 2].into();
+        let (changes, _) = adjust(&mut rows, &ids, Adjustment::Multiply(1.1), None).unwrap();
+        assert_eq!(prices(&rows), ["3.66", "5.50"]);
+        assert_eq!(changes.updated.len(), 2);
+        let (changes, _) = adjust(&mut rows, &ids, Adjustment::Set("5.50".parse().unwrap()), None).unwrap();
+        assert_eq!(changes.updated.iter().map(|(old, _)| old.id).collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn a_price_too_large_fails_the_adjustment() {
+        let mut rows = vec![row(1, "1"), row(2, "2")];
+        rows[1].price = Money::from_units(i64::MAX);
+        let ids: HashSet<u64> = [1, 2].into();
+        let Err(Failure::InvalidInput(msg)) = adjust(&mut rows, &ids, Adjustment::Add("1".parse().unwrap()), None) else {
+            panic!("an overflowing price was accepted");
+        };
+        assert_eq!(msg, "New price of #2 Item 2 is too large");
+    }
+}
@@ -0,0 +1,80 @@
+//! Alternative product names, stored next to the database, that group under one canonical name.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Failure;
+
+/// Maps normalized variant names to the canonical product name they stand for.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Aliases {
+    map: BTreeMap<String, String>,
+}
+
+/// Case-insensitive comparison key with surrounding and repeated whitespace removed.
+fn normalize(name: &str) -> String {
+    crate::text::fold(&crate::text::squeeze(name))
+}
+
+impl Aliases {
+    pub fn path(db: &str) -> String {
+        format!("{}.aliases.json", db)
+    }
+
+    /// A missing file means no aliases; a broken one is an error rather than silently ignored.
+    pub fn load(db: &str) -> Result<Self> {
+        let path = Self::path(db);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Read {}", path)),
+        };
+        serde_json::from_str(&text).map_err(|e| Failure::InvalidInput(format!("Invalid alias file {}: {}", path, e)).into())
+    }
+
+    pub fn save(&self, db: &str) -> Result<()> {
+        let path = Self::path(db);
+        fs::write(&path, serde_json::to_string_pretty(self)? + "\n").with_context(|| format!("Write {}", path))
+    }
+
+    /// Record `variant` as another name for `canonical`. Aliases of aliases resolve to the
+    /// final name, so lookups never need more than one step.
+    pub fn add(&mut self, variant: &str, canonical: &str) -> Result<()> {
+        let canonical = self.canonical(canonical).trim().to_string();
+        let key = normalize(variant);
+        if key.is_empty() || canonical.is_empty() {
+            return Err(Failure::InvalidInput("Alias and product name must not be empty".to_string()).into());
+        }
+        if key == normalize(&canonical) {
+            return Err(Failure::InvalidInput(format!("'{}' cannot be an alias of itself", variant.trim())).into());
+        }
+        for target in self.map.values_mut().filter(|t| normalize(t) == key) {
+            *target = canonical.clone();
+        }
+        self.map.insert(key, canonical);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, variant: &str) -> bool {
+        self.map.remove(&normalize(variant)).is_some()
+    }
+
+    /// The canonical name for `name`, or `name` itself when it has no alias.
+    pub fn canonical<'a>(&'a self, name: &'a str) -> &'a str {
+        self.map.get(&normalize(name)).map_or(name, String::as_str)
+    }
+
+    /// Grouping key: names that are aliases of each other share it.
+    pub fn key(&self, name: &str) -> String {
+        normalize(self.canonical(name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.map.iter()
+    }
+}
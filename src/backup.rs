@@ -0,0 +1,210 @@
+//! Rotating copies of the database in a `backups` directory next to it, taken before rows are
+//! rewritten so a bad delete or import can be undone.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDateTime};
+use log::info;
+
+use crate::filter::Filters;
+use crate::row::{record, Issue, Row};
+use crate::store::{Changes, PriceStore};
+
+/// Backup names carry the time they were taken, so they sort oldest first.
+const TIME_FORMAT: &str = "%Y-%m-%dT%H-%M-%S%.3f";
+
+/// The backups of one database.
+pub struct Backups {
+    db: PathBuf,
+    dir: PathBuf,
+    keep: usize,
+}
+
+/// One copy in the backup directory.
+pub struct Backup {
+    pub path: PathBuf,
+    /// Local time the copy was taken, from its name.
+    pub taken: NaiveDateTime,
+    pub size: u64,
+}
+
+impl Backups {
+    /// Backups of `db` in `backups/` beside it, of which the newest `keep` (at least one) are kept.
+    pub fn new(db: &str, keep: usize) -> Self {
+        let db = PathBuf::from(db);
+        let dir = db.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new(".")).join("backups");
+        Backups { db, dir, keep: keep.max(1) }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn stem_and_ext(&self) -> (String, String) {
+        stem_and_ext(&self.db)
+    }
+
+    /// Every backup of this database, newest first. Other files in the directory, including
+    /// backups of other databases, are ignored.
+    pub fn list(&self) -> Result<Vec<Backup>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Read {}", self.dir.display())),
+        };
+        let (stem, ext) = self.stem_and_ext();
+        let mut found = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let time = name.strip_prefix(&format!("{}-", stem)).and_then(|rest| rest.strip_suffix(&format!(".{}", ext)));
+            if let Some(taken) = time.and_then(|t| NaiveDateTime::parse_from_str(t, TIME_FORMAT).ok()) {
+                found.push(Backup { path: entry.path(), taken, size: entry.metadata()?.len() });
+            }
+        }
+        found.sort_by_key(|b| std::cmp::Reverse(b.taken));
+        Ok(found)
+    }
+
+    /// Copy the database into the backup directory and delete all but the newest `keep` copies.
+    /// Nothing is copied when there is no database file yet, as for a directory database, or the
+    /// newest backup already has the same contents; `None` then.
+    pub fn take(&self) -> Result<Option<PathBuf>> {
+        let Ok(current) = fs::read(&self.db) else {
+            return Ok(None);
+        };
+        let backups = self.list()?;
+        if backups.first().is_some_and(|newest| fs::read(&newest.path).is_ok_and(|b| b == current)) {
+            return Ok(None);
+        }
+        fs::create_dir_all(&self.dir).with_context(|| format!("Create {}", self.dir.display()))?;
+        let (stem, ext) = self.stem_and_ext();
+        let path = self.dir.join(format!("{}-{}.{}", stem, Local::now().format(TIME_FORMAT), ext));
+        fs::write(&path, &current).with_context(|| format!("Back up {} to {}", self.db.display(), path.display()))?;
+        info!("backed up {} to {}", self.db.display(), path.display());
+        for old in backups.iter().skip(self.keep - 1) {
+            fs::remove_file(&old.path).with_context(|| format!("Remove old backup {}", old.path.display()))?;
+            info!("removed old backup {}", old.path.display());
+        }
+        Ok(Some(path))
+    }
+}
+
+/// `prices` and `csv` for `prices.csv`, and `csv.gz` for `prices.csv.gz`, so copies named
+/// `prices-<time>.csv.gz` keep telling what they hold.
+pub fn stem_and_ext(db: &Path) -> (String, String) {
+    let name = db.file_name().map_or_else(|| "prices.csv".into(), |n| n.to_string_lossy().into_owned());
+    let (stem, ext) = match name.strip_suffix(".gz").and_then(|rest| rest.rsplit_once('.')) {
+        Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!("{}.gz", ext)),
+        _ => match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), ext.to_string()),
+            _ => (name.clone(), "csv".to_string()),
+        },
+    };
+    (stem, ext)
+}
+
+/// How restoring a backup would change the database, counted by id.
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff {
+    /// Rows only the backup has.
+    pub added: usize,
+    /// Rows only the database has.
+    pub removed: usize,
+    /// Rows both have, with different fields.
+    pub changed: usize,
+}
+
+impl Diff {
+    /// Compare the `current` rows with those of a `backup`.
+    pub fn new(current: &[Row], backup: &[Row]) -> Self {
+        let by_id: HashMap<u64, &Row> = current.iter().map(|c| (c.id, c)).collect();
+        let kept: HashSet<u64> = backup.iter().map(|r| r.id).collect();
+        Diff {
+            added: backup.iter().filter(|r| !by_id.contains_key(&r.id)).count(),
+            removed: current.iter().filter(|c| !kept.contains(&c.id)).count(),
+            changed: backup.iter().filter(|r| by_id.get(&r.id).is_some_and(|c| record(c, c.id) != record(r, r.id))).count(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added + self.removed + self.changed == 0
+    }
+}
+
+/// A store that backs the database up before every change except appends.
+pub struct BackupStore {
+    inner: Box<dyn PriceStore>,
+    backups: Backups,
+}
+
+impl BackupStore {
+    pub fn new(inner: Box<dyn PriceStore>, backups: Backups) -> Self {
+        BackupStore { inner, backups }
+    }
+}
+
+impl PriceStore for BackupStore {
+    fn all(&self) -> Result<Vec<Row>> {
+        self.inner.all()
+    }
+
+    fn filter(&self, filters: &Filters) -> Result<Vec<Row>> {
+        self.inner.filter(filters)
+    }
+
+    fn stream(&self) -> Result<Box<dyn Iterator<Item = Result<Row>> + '_>> {
+        self.inner.stream()
+    }
+
+    fn append(&self, rows: &[Row]) -> Result<()> {
+        self.inner.append(rows)
+    }
+
+    fn delete(&self, ids: &[u64]) -> Result<usize> {
+        self.backups.take()?;
+        self.inner.delete(ids)
+    }
+
+    fn overwrite(&self, rows: &[Row]) -> Result<()> {
+        self.backups.take()?;
+        self.inner.overwrite(rows)
+    }
+
+    fn issues(&self) -> Vec<Issue> {
+        self.inner.issues()
+    }
+
+    fn outdated(&self) -> Result<Option<String>> {
+        self.inner.outdated()
+    }
+
+    fn highest_id(&self) -> Result<Option<u64>> {
+        self.inner.highest_id()
+    }
+
+    fn commit(&self, rows: &[Row], changes: &Changes) -> Result<()> {
+        self.backups.take()?;
+        self.inner.commit(rows, changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: u64, product: &str) -> Row {
+        Row { id, product: product.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn a_diff_counts_rows_by_id() {
+        let current = vec![row(1, "Milk"), row(2, "Tea"), row(3, "Jam")];
+        let backup = vec![row(1, "Milk"), row(3, "Jelly"), row(4, "Bread"), row(5, "Eggs")];
+        assert_eq!(Diff::new(&current, &backup), Diff { added: 2, removed: 1, changed: 1 });
+        assert!(Diff::new(&current, &current).is_empty());
+    }
+}
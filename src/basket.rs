@@ -0,0 +1,61 @@
+//! Entries picked for a planned purchase, stored by id next to the database.
+
+use std::fs;
+use std::io::ErrorKind;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Failure;
+
+/// Entry ids in the order they were added.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Basket {
+    ids: Vec<u64>,
+}
+
+impl Basket {
+    pub fn path(db: &str) -> String {
+        format!("{}.basket.json", db)
+    }
+
+    /// A missing file means an empty basket; a broken one is an error rather than silently emptied.
+    pub fn load(db: &str) -> Result<Self> {
+        let path = Self::path(db);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Read {}", path)),
+        };
+        serde_json::from_str(&text).map_err(|e| Failure::InvalidInput(format!("Invalid basket file {}: {}", path, e)).into())
+    }
+
+    pub fn save(&self, db: &str) -> Result<()> {
+        let path = Self::path(db);
+        fs::write(&path, serde_json::to_string_pretty(self)? + "\n").with_context(|| format!("Write {}", path))
+    }
+
+    /// False when `id` was already in the basket.
+    pub fn add(&mut self, id: u64) -> bool {
+        if self.ids.contains(&id) {
+            return false;
+        }
+        self.ids.push(id);
+        true
+    }
+
+    pub fn remove(&mut self, id: u64) -> bool {
+        let before = self.ids.len();
+        self.ids.retain(|i| *i != id);
+        self.ids.len() < before
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+
+    pub fn ids(&self) -> &[u64] {
+        &self.ids
+    }
+}
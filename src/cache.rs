@@ -0,0 +1,211 @@
+//! Rows kept between reads of an unchanged database, for sessions such as the menu that read
+//! the same file for every action.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use log::debug;
+
+use crate::filter::Filters;
+use crate::row::{Issue, Row};
+use crate::store::{Changes, PriceStore};
+
+/// What identifies one version of the database file: its modification time and size. For a
+/// directory, the latest time and the total size of the files in it.
+type Stamp = (SystemTime, u64);
+
+/// A store that answers `all`, `filter` and `stream` from the rows it read last while the
+/// file's modification time and size are unchanged. Its own writes drop the copy, and so does
+/// any change made by another process, which shows up in the file's metadata.
+pub struct CachedStore<'a> {
+    inner: &'a dyn PriceStore,
+    path: PathBuf,
+    cached: RefCell<Option<Cached>>,
+}
+
+struct Cached {
+    stamp: Stamp,
+    rows: Vec<Row>,
+    issues: Vec<Issue>,
+}
+
+impl<'a> CachedStore<'a> {
+    /// Cache the rows of `inner`, the database at `db`.
+    pub fn new(inner: &'a dyn PriceStore, db: &str) -> Self {
+        CachedStore { inner, path: PathBuf::from(db), cached: RefCell::default() }
+    }
+
+    /// The file's current stamp; `None` when it can't be read, which never matches.
+    fn stamp(&self) -> Option<Stamp> {
+        let meta = fs::metadata(&self.path).ok()?;
+        if !meta.is_dir() {
+            return Some((meta.modified().ok()?, meta.len()));
+        }
+        // The directory's own time covers files added, removed or replaced, not ones edited
+        // in place.
+        let mut stamp = (meta.modified().ok()?, 0);
+        for entry in fs::read_dir(&self.path).ok()? {
+            let meta = entry.ok()?.metadata().ok()?;
+            stamp = (stamp.0.max(meta.modified().ok()?), stamp.1 + meta.len());
+        }
+        Some(stamp)
+    }
+
+    /// Whether the cached rows still describe the file, dropping them when they don't.
+    fn fresh(&self) -> bool {
+        let mut cached = self.cached.borrow_mut();
+        let fresh = cached.as_ref().is_some_and(|c| Some(c.stamp) == self.stamp());
+        if !fresh && cached.take().is_some() {
+            debug!("{} changed, reading it again", self.path.display());
+        }
+        fresh
+    }
+
+    fn invalidate(&self) {
+        self.cached.replace(None);
+    }
+}
+
+impl PriceStore for CachedStore<'_> {
+    fn all(&self) -> Result<Vec<Row>> {
+        if self.fresh() {
+            return Ok(self.cached.borrow().as_ref().map(|c| c.rows.clone()).unwrap_or_default());
+        }
+        // Stamped before reading, so a write that lands during the read is noticed next time.
+        let stamp = self.stamp();
+        let rows = self.inner.all()?;
+        if let Some(stamp) = stamp {
+            self.cached.replace(Some(Cached { stamp, rows: rows.clone(), issues: self.inner.issues() }));
+        }
+        Ok(rows)
+    }
+
+    /// Without fresh rows the inner store filters, so one that narrows rows down before loading
+    /// them, such as SQLite, still does.
+    fn filter(&self, filters: &Filters) -> Result<Vec<Row>> {
+        if self.fresh() {
+            return Ok(filters.apply(self.all()?));
+        }
+        self.inner.filter(filters)
+    }
+
+    fn stream(&self) -> Result<Box<dyn Iterator<Item = Result<Row>> + '_>> {
+        if self.fresh() {
+            return Ok(Box::new(self.all()?.into_iter().map(Ok)));
+        }
+        self.inner.stream()
+    }
+
+    fn append(&self, rows: &[Row]) -> Result<()> {
+        self.invalidate();
+        self.inner.append(rows)
+    }
+
+    fn delete(&self, ids: &[u64]) -> Result<usize> {
+        self.invalidate();
+        self.inner.delete(ids)
+    }
+
+    fn overwrite(&self, rows: &[Row]) -> Result<()> {
+        self.invalidate();
+        self.inner.overwrite(rows)
+    }
+
+    fn issues(&self) -> Vec<Issue> {
+        match &*self.cached.borrow() {
+            Some(c) => c.issues.clone(),
+            None => self.inner.issues(),
+        }
+    }
+
+    fn outdated(&self) -> Result<Option<String>> {
+        self.inner.outdated()
+    }
+
+    fn highest_id(&self) -> Result<Option<u64>> {
+        self.inner.highest_id()
+    }
+
+    fn commit(&self, rows: &[Row], changes: &Changes) -> Result<()> {
+        self.invalidate();
+        self.inner.commit(rows, changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::category::CategoryFilter;
+    use crate::store::MemoryStore;
+
+    /// A memory store counting the reads that reach it.
+    #[derive(Default)]
+    struct Counted {
+        inner: MemoryStore,
+        all: Cell<usize>,
+        filter: Cell<usize>,
+    }
+
+    impl PriceStore for Counted {
+        fn all(&self) -> Result<Vec<Row>> {
+            self.all.set(self.all.get() + 1);
+            self.inner.all()
+        }
+
+        fn filter(&self, filters: &Filters) -> Result<Vec<Row>> {
+            self.filter.set(self.filter.get() + 1);
+            Ok(filters.apply(self.inner.all()?))
+        }
+
+        fn append(&self, rows: &[Row]) -> Result<()> {
+            self.inner.append(rows)
+        }
+
+        fn overwrite(&self, rows: &[Row]) -> Result<()> {
+            self.inner.overwrite(rows)
+        }
+    }
+
+    fn row(product: &str, category: &str) -> Row {
+        Row { product: product.to_string(), category: category.to_string(), timestamp: "2024-01-01T00:00:00Z".to_string(), ..Default::default() }
+    }
+
+    fn products(rows: &[Row]) -> Vec<&str> {
+        rows.iter().map(|r| r.product.as_str()).collect()
+    }
+
+    #[test]
+    fn filter_uses_fresh_rows_and_otherwise_leaves_it_to_the_inner_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("prices.csv");
+        fs::write(&db, "one version").unwrap();
+        let inner = Counted::default();
+        inner.append(&[row("Milk", "food"), row("Ball", "toys"), row("Crisps", "food/snacks")]).unwrap();
+        let cache = CachedStore::new(&inner, &db.to_string_lossy());
+        let food = Filters { category: Some(CategoryFilter::parse("food").unwrap()), ..Default::default() };
+
+        assert_eq!(products(&cache.filter(&food).unwrap()), ["Milk", "Crisps"]);
+        assert_eq!((inner.all.get(), inner.filter.get()), (0, 1));
+
+        cache.all().unwrap();
+        assert_eq!(products(&cache.filter(&food).unwrap()), ["Milk", "Crisps"]);
+        assert_eq!(products(&cache.filter(&Filters::default()).unwrap()), ["Milk", "Ball", "Crisps"]);
+        assert_eq!((inner.all.get(), inner.filter.get()), (1, 1));
+
+        // Changed by another process.
+        fs::write(&db, "another, longer version").unwrap();
+        assert_eq!(products(&cache.filter(&food).unwrap()), ["Milk", "Crisps"]);
+        assert_eq!((inner.all.get(), inner.filter.get()), (1, 2));
+
+        // And by a write through the cache.
+        cache.all().unwrap();
+        cache.append(&[row("Juice", "food/drinks")]).unwrap();
+        assert_eq!(products(&cache.filter(&food).unwrap()), ["Milk", "Crisps", "Juice"]);
+        assert_eq!((inner.all.get(), inner.filter.get()), (2, 3));
+    }
+}
@@ -0,0 +1,186 @@
+//! The `--category` filter: a comma-separated list of categories to include, and of categories
+//! to leave out when written with a leading `!`.
+
+use std::collections::HashSet;
+
+use crate::text;
+
+/// Categories to keep and to drop. With no includes every category is kept except the excluded ones.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CategoryFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl CategoryFilter {
+    /// Parses `groceries, household` or `!misc`. Blank items and repeats are dropped; a category
+    /// both included and excluded is rejected rather than silently matching nothing.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut filter = CategoryFilter::default();
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (list, name) = match item.strip_prefix('!') {
+                Some(name) => (&mut filter.exclude, name.trim()),
+                None => (&mut filter.include, item),
+            };
+            if name.is_empty() {
+                return Err("'!' must be followed by a category".to_string());
+            }
+            if !list.iter().any(|have| path(have) == path(name)) {
+                list.push(name.to_string());
+            }
+        }
+        if filter.include.is_empty() && filter.exclude.is_empty() {
+            return Err("expected a category, e.g. groceries,household or !misc".to_string());
+        }
+        if let Some(both) = filter.include.iter().find(|i| filter.exclude.iter().any(|e| path(e) == path(i))) {
+            return Err(format!("'{}' is both included and excluded", both));
+        }
+        Ok(filter)
+    }
+
+    /// Each listed category matches itself and, unless `exact`, every subcategory below it.
+    pub fn matches(&self, category: &str, exact: bool) -> bool {
+        let have = path(category);
+        let hit = |want: &String| {
+            let want = path(want);
+            if exact {
+                have == want
+            } else {
+                have.starts_with(&want)
+            }
+        };
+        (self.include.is_empty() || self.include.iter().any(hit)) && !self.exclude.iter().any(hit)
+    }
+
+    /// The included categories as typed; empty when every category not excluded is kept.
+    pub fn includes(&self) -> &[String] {
+        &self.include
+    }
+}
+
+/// `category` moved from under `old` to under `new`, keeping the spelling of any subcategory
+/// segments: renaming `tech` to `electronics` turns `Tech/Phones` into `electronics/Phones`.
+/// `None` when `category` is neither `old` nor below it.
+pub fn renamed(category: &str, old: &str, new: &str) -> Option<String> {
+    if !within(category, old) {
+        return None;
+    }
+    let rest: Vec<&str> = category.split('/').map(str::trim).filter(|s| !s.is_empty()).skip(path(old).len()).collect();
+    Some(std::iter::once(new.trim()).chain(rest).collect::<Vec<_>>().join("/"))
+}
+
+/// Each category `categories` name once, trimmed, in the spelling first seen; blank ones are
+/// left out and case doesn't tell categories apart.
+pub fn distinct<'a>(categories: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    categories.map(str::trim).filter(|c| !c.is_empty() && seen.insert(text::fold(c))).map(str::to_string).collect()
+}
+
+/// Whether `category` is `parent` or one of its subcategories; nothing is within a blank parent.
+pub fn within(category: &str, parent: &str) -> bool {
+    let parent = path(parent);
+    !parent.is_empty() && path(category).starts_with(&parent)
+}
+
+/// The folded form categories are compared in: " Food / Snacks" becomes "food/snacks".
+pub fn key(category: &str) -> String {
+    path(category).join("/")
+}
+
+/// Folded path segments of a `/`-separated category; empty segments are dropped, so
+/// "Food/", "food" and " food / " are the same node.
+fn path(category: &str) -> Vec<String> {
+    category.split('/').map(text::fold).filter(|s| !s.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(s: &str) -> (Vec<String>, Vec<String>) {
+        let filter = CategoryFilter::parse(s).unwrap();
+        (filter.include, filter.exclude)
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn distinct_categories_keep_their_first_spelling() {
+        let categories = ["Food ", "", "toys", " food", "FOOD", "  ", "Toys", "food/snacks"];
+        assert_eq!(distinct(categories.into_iter()), names(&["Food", "toys", "food/snacks"]));
+    }
+
+    #[test]
+    fn parse_reads_comma_lists() {
+        assert_eq!(parsed("groceries"), (names(&["groceries"]), names(&[])));
+        assert_eq!(parsed(" groceries , household ,, "), (names(&["groceries", "household"]), names(&[])));
+        // Repeats are dropped whatever their case or trailing slash.
+        assert_eq!(parsed("Food,food/, FOOD ,food/snacks"), (names(&["Food", "food/snacks"]), names(&[])));
+    }
+
+    #[test]
+    fn parse_reads_exclusions() {
+        assert_eq!(parsed("!misc"), (names(&[]), names(&["misc"])));
+        assert_eq!(parsed("! misc , !Misc,!toys"), (names(&[]), names(&["misc", "toys"])));
+        let filter = CategoryFilter::parse("!misc").unwrap();
+        assert!(filter.matches("food", false) && !filter.matches("Misc", false));
+    }
+
+    #[test]
+    fn parse_reads_mixed_includes_and_exclusions() {
+        assert_eq!(parsed("food, !food/sweets, household"), (names(&["food", "household"]), names(&["food/sweets"])));
+        let filter = CategoryFilter::parse("food,!food/sweets").unwrap();
+        assert!(filter.matches("food/bread", false));
+        assert!(!filter.matches("Food/Sweets/Chocolate", false));
+        assert!(!filter.matches("toys", false));
+    }
+
+    #[test]
+    fn a_category_matches_its_subcategories_unless_exact() {
+        let food = CategoryFilter::parse("food").unwrap();
+        for category in ["food", "food/snacks", "Food/Snacks/Crisps", " FOOD / drinks "] {
+            assert!(food.matches(category, false), "{}", category);
+        }
+        for category in ["foodstuff", "seafood", "pet/food", "", "snacks"] {
+            assert!(!food.matches(category, false), "{}", category);
+        }
+        assert!(food.matches("Food", true) && !food.matches("food/snacks", true));
+        let snacks = CategoryFilter::parse("food/snacks").unwrap();
+        assert!(snacks.matches("food/snacks/crisps", false) && !snacks.matches("food", false) && !snacks.matches("food/drinks", false));
+    }
+
+    #[test]
+    fn case_spaces_and_extra_slashes_dont_change_a_category() {
+        for typed in ["food/", "FOOD", " Food ", "/food//", "Food /"] {
+            let filter = CategoryFilter::parse(typed).unwrap();
+            assert!(filter.matches("food", true), "{}", typed);
+            assert!(filter.matches("food/snacks", false), "{}", typed);
+        }
+        assert!(CategoryFilter::parse("food//snacks/").unwrap().matches("Food / Snacks", true));
+        assert!(CategoryFilter::parse("café").unwrap().matches("CAFÉ/beans", false));
+        assert_eq!(key(" Food / Snacks/ "), "food/snacks");
+        assert_eq!(key("/"), "");
+    }
+
+    #[test]
+    fn within_and_renamed_follow_the_hierarchy() {
+        assert!(within("Food/Snacks", "food/") && within("food", "FOOD"));
+        assert!(!within("foodstuff", "food") && !within("food", "food/snacks") && !within("food", " / "));
+        assert_eq!(renamed("Tech/Phones", "tech", "electronics").as_deref(), Some("electronics/Phones"));
+        assert_eq!(renamed("tech/", "TECH", " gadgets ").as_deref(), Some("gadgets"));
+        assert_eq!(renamed("Food/Snacks/Crisps", "food/snacks", "treats").as_deref(), Some("treats/Crisps"));
+        assert_eq!(renamed("technology", "tech", "electronics"), None);
+    }
+
+    #[test]
+    fn parse_rejects_empty_and_contradicting_lists() {
+        let error = |s: &str| CategoryFilter::parse(s).unwrap_err();
+        assert_eq!(error(""), "expected a category, e.g. groceries,household or !misc");
+        assert_eq!(error(" , ,"), "expected a category, e.g. groceries,household or !misc");
+        assert_eq!(error("food,!"), "'!' must be followed by a category");
+        assert_eq!(error("! "), "'!' must be followed by a category");
+        assert_eq!(error("Food,!food/"), "'Food' is both included and excluded");
+    }
+}
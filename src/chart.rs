@@ -0,0 +1,78 @@
+//! Text charts of prices: one-line sparklines and histograms.
+
+use std::env;
+
+use serde::Serialize;
+
+/// Bar heights from lowest to highest.
+pub const BLOCK_LEVELS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Stand-ins for terminals that can't show the block characters.
+pub const ASCII_LEVELS: &[char] = &['_', '.', '-', '=', '+', '*', '#'];
+
+/// One character per value, scaled between the lowest and highest value. More values than
+/// `width` are averaged in evenly sized buckets; fewer are never stretched.
+pub fn sparkline(values: &[f64], width: usize, levels: &[char]) -> String {
+    let points: Vec<f64> = if values.len() > width {
+        (0..width)
+            .map(|i| {
+                let bucket = &values[i * values.len() / width..(i + 1) * values.len() / width];
+                bucket.iter().sum::<f64>() / bucket.len().max(1) as f64
+            })
+            .collect()
+    } else {
+        values.to_vec()
+    };
+    let min = points.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = points.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let top = levels.len() - 1;
+    points
+        .iter()
+        .map(|p| {
+            // A flat series sits in the middle rather than on the floor.
+            let level = if max > min { ((p - min) / (max - min) * top as f64).round() as usize } else { top / 2 };
+            levels[level.min(top)]
+        })
+        .collect()
+}
+
+/// Prices from `from` up to `to`; the last bucket includes `to`.
+#[derive(Serialize)]
+pub struct Bucket {
+    pub from: f64,
+    pub to: f64,
+    pub count: usize,
+}
+
+/// `buckets` equal-width buckets between the lowest and highest value. When all values are
+/// equal there is nothing to split, so a single bucket holds them all.
+pub fn histogram(values: &[f64], buckets: usize) -> Vec<Bucket> {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if values.is_empty() {
+        return Vec::new();
+    }
+    if max <= min || buckets < 2 {
+        return vec![Bucket { from: min, to: max, count: values.len() }];
+    }
+    let width = (max - min) / buckets as f64;
+    // Boundaries are reported to the cent; counting uses the exact width.
+    let edge = |i: usize| if i == buckets { max } else { ((min + width * i as f64) * 100.0).round() / 100.0 };
+    let mut out: Vec<Bucket> = (0..buckets).map(|i| Bucket { from: edge(i), to: edge(i + 1), count: 0 }).collect();
+    for v in values {
+        let i = (((v - min) / width).floor() as usize).min(buckets - 1);
+        out[i].count += 1;
+    }
+    out
+}
+
+/// Whether the locale promises UTF-8 output, going by `LC_ALL`, `LC_CTYPE` and `LANG` in that order.
+pub fn unicode_locale() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|v| env::var(v).ok())
+        .find(|v| !v.is_empty())
+        .is_some_and(|v| {
+            let v = v.to_lowercase();
+            v.contains("utf-8") || v.contains("utf8")
+        })
+}
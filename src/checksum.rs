@@ -0,0 +1,320 @@
+//! Checksums of the stored rows, and the optional `<database>.sha256` file that records the
+//! checksum after every change so `verify` can tell when the file was edited elsewhere.
+
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+
+use anyhow::{Context, Result};
+use log::info;
+use sha2::digest::common::hazmat::{SerializableState, SerializedState};
+use sha2::{Digest, Sha256};
+
+use crate::csvfile;
+use crate::filter::Filters;
+use crate::row::{parse_record, record, Issue, Layout, Row, HEADER};
+use crate::store::{Changes, PriceStore};
+
+/// The checksum of the rows hashed so far, which more rows can be added to. Its state can be
+/// saved and picked up again, so appending to the database doesn't mean hashing it all again.
+struct Checksum(Sha256);
+
+impl Checksum {
+    /// Nothing but the header hashed yet.
+    fn new() -> Result<Self> {
+        let mut sum = Checksum(Sha256::new());
+        sum.write_records(std::iter::once(HEADER))?;
+        Ok(sum)
+    }
+
+    /// Hash `rows` after those already hashed, each with its id.
+    fn add<'r>(&mut self, rows: impl IntoIterator<Item = &'r Row>) -> Result<()> {
+        self.write_records(rows.into_iter().map(|r| record(r, r.id)))
+    }
+
+    fn write_records<I: IntoIterator<Item = impl AsRef<[u8]>>>(&mut self, records: impl Iterator<Item = I>) -> Result<()> {
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).terminator(csv::Terminator::Any(b'\n')).from_writer(&mut *self);
+        for rec in records {
+            wtr.write_record(rec)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// The SHA-256 of what was hashed, in hex.
+    fn hex(&self) -> String {
+        self.0.clone().finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// The state, in hex, for `resume`.
+    fn state(&self) -> String {
+        self.0.serialize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// The checksum `state` saved; `None` when it doesn't read.
+    fn resume(state: &str) -> Option<Self> {
+        let bytes: Vec<u8> = (0..state.len())
+            .step_by(2)
+            .map(|i| state.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+            .collect::<Option<_>>()?;
+        let state = SerializedState::<Sha256>::try_from(bytes.as_slice()).ok()?;
+        Sha256::deserialize(&state).ok().map(Checksum)
+    }
+}
+
+impl Write for Checksum {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// SHA-256, in hex, of `rows` written as a current-layout CSV file with `\n` line ends. It
+/// depends only on the entries, not on how the file happens to be encoded, compressed or
+/// stored, so a CSV file and its SQLite copy have the same checksum.
+pub fn checksum(rows: &[Row]) -> Result<String> {
+    let mut sum = Checksum::new()?;
+    sum.add(rows)?;
+    Ok(sum.hex())
+}
+
+/// Where the checksum of `db` is recorded.
+pub fn sidecar_path(db: &str) -> String {
+    format!("{}.sha256", db)
+}
+
+/// The checksum last recorded for `db`, or `None` when none was.
+pub fn read_sidecar(db: &str) -> Result<Option<String>> {
+    Ok(read(db)?.and_then(|text| text.split_whitespace().next().map(str::to_string)))
+}
+
+/// The checksum last recorded for `db` ready to add rows to; `None` when none was, or it was
+/// recorded without its state.
+fn resume_sidecar(db: &str) -> Result<Option<Checksum>> {
+    Ok(read(db)?.and_then(|text| text.split_whitespace().nth(1).and_then(Checksum::resume)))
+}
+
+fn read(db: &str) -> Result<Option<String>> {
+    let path = sidecar_path(db);
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Read {}", path)),
+    }
+}
+
+/// Record the checksum of `rows` as that of `db`.
+pub fn write_sidecar(db: &str, rows: &[Row]) -> Result<()> {
+    let mut sum = Checksum::new()?;
+    sum.add(rows)?;
+    record_sum(db, &sum)
+}
+
+/// Write `sum` to the sidecar of `db`: the checksum on the first line, its state on the second.
+/// The file is replaced like the database, so a crash midway leaves the old one.
+fn record_sum(db: &str, sum: &Checksum) -> Result<()> {
+    let path = sidecar_path(db);
+    let hex = sum.hex();
+    csvfile::replace(&path, |tmp| writeln!(tmp, "{}\n{}", hex, sum.state()).with_context(|| format!("Write {}", path)))?;
+    info!("recorded checksum {} in {}", hex, path);
+    Ok(())
+}
+
+/// A store that records the checksum of the database after every change.
+pub struct ChecksumStore {
+    inner: Box<dyn PriceStore>,
+    db: String,
+}
+
+impl ChecksumStore {
+    pub fn new(inner: Box<dyn PriceStore>, db: &str) -> Self {
+        ChecksumStore { inner, db: db.to_string() }
+    }
+
+    fn record(&self) -> Result<()> {
+        write_sidecar(&self.db, &self.inner.all()?)
+    }
+
+    /// Add the rows just appended to the recorded checksum, when the backend can tell the ids
+    /// they were given; otherwise hash every row again. They are hashed as they read back, with
+    /// defaults such as the source filled in.
+    fn record_appended(&self, rows: &[Row]) -> Result<()> {
+        let highest = match self.inner.highest_id()? {
+            Some(highest) if rows.iter().all(|r| r.id == 0) => highest,
+            _ => return self.record(),
+        };
+        let (Some(first), Some(mut sum)) = ((highest + 1).checked_sub(rows.len() as u64), resume_sidecar(&self.db)?) else {
+            return self.record();
+        };
+        let stored: Vec<Row> =
+            rows.iter().zip(first..).map(|(r, id)| parse_record(&csv::StringRecord::from(record(r, id).to_vec()), Layout::CURRENT).0).collect();
+        sum.add(&stored)?;
+        record_sum(&self.db, &sum)
+    }
+}
+
+impl PriceStore for ChecksumStore {
+    fn all(&self) -> Result<Vec<Row>> {
+        self.inner.all()
+    }
+
+    fn filter(&self, filters: &Filters) -> Result<Vec<Row>> {
+        self.inner.filter(filters)
+    }
+
+    fn stream(&self) -> Result<Box<dyn Iterator<Item = Result<Row>> + '_>> {
+        self.inner.stream()
+    }
+
+    /// Only the new rows are hashed, on top of the recorded checksum's state.
+    fn append(&self, rows: &[Row]) -> Result<()> {
+        self.inner.append(rows)?;
+        self.record_appended(rows)
+    }
+
+    fn delete(&self, ids: &[u64]) -> Result<usize> {
+        let removed = self.inner.delete(ids)?;
+        self.record()?;
+        Ok(removed)
+    }
+
+    fn overwrite(&self, rows: &[Row]) -> Result<()> {
+        self.inner.overwrite(rows)?;
+        self.record()
+    }
+
+    fn issues(&self) -> Vec<Issue> {
+        self.inner.issues()
+    }
+
+    fn outdated(&self) -> Result<Option<String>> {
+        self.inner.outdated()
+    }
+
+    fn highest_id(&self) -> Result<Option<u64>> {
+        self.inner.highest_id()
+    }
+
+    fn commit(&self, rows: &[Row], changes: &Changes) -> Result<()> {
+        self.inner.commit(rows, changes)?;
+        self.record()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::csvfile::CsvStore;
+    use crate::dirstore::DirStore;
+    use crate::sqlite::SqliteStore;
+
+    /// A store counting the reads of every row.
+    struct Counted {
+        inner: CsvStore,
+        reads: Rc<Cell<usize>>,
+    }
+
+    impl PriceStore for Counted {
+        fn all(&self) -> Result<Vec<Row>> {
+            self.reads.set(self.reads.get() + 1);
+            self.inner.all()
+        }
+
+        fn append(&self, rows: &[Row]) -> Result<()> {
+            self.inner.append(rows)
+        }
+
+        fn overwrite(&self, rows: &[Row]) -> Result<()> {
+            self.inner.overwrite(rows)
+        }
+
+        fn highest_id(&self) -> Result<Option<u64>> {
+            self.inner.highest_id()
+        }
+    }
+
+    fn row(product: &str, notes: &str) -> Row {
+        Row {
+            product: product.to_string(),
+            category: "food".to_string(),
+            price: "1.5".parse().unwrap(),
+            notes: notes.to_string(),
+            tags: vec!["a".to_string(), "b".to_string()],
+            timestamp: "2024-01-01T00:00:00Z".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn the_checksum_is_that_of_the_csv_text() {
+        let r = Row { id: 2, product: "Tea".to_string(), ..Default::default() };
+        let text = format!("{}\n{}\n", HEADER.join(","), record(&r, 2).join(","));
+        assert_eq!(checksum(&[r]).unwrap(), hex(&Sha256::digest(text.as_bytes())));
+        assert_eq!(checksum(&[]).unwrap(), hex(&Sha256::digest(format!("{}\n", HEADER.join(",")).as_bytes())));
+    }
+
+    #[test]
+    fn appends_add_to_the_recorded_state_without_reading_the_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let reads = Rc::new(Cell::new(0));
+        let inner = Counted { inner: CsvStore::open(&db).unwrap(), reads: reads.clone() };
+        inner.append(&[row("Milk", "")]).unwrap();
+        let store = ChecksumStore::new(Box::new(inner), &db);
+        store.delete(&[1]).unwrap();
+        store.append(&[row("Bread", "")]).unwrap();
+        let after_delete = reads.get();
+        for notes in ["plain", "with, comma", "with \"quotes\"", "two\nlines", "ünïcødé"] {
+            store.append(&[row("Tea", notes), row("Rice", "")]).unwrap();
+        }
+        store.append(&[]).unwrap();
+        assert_eq!(reads.get(), after_delete);
+        let rows = store.all().unwrap();
+        assert_eq!(rows.iter().map(|r| r.id).collect::<Vec<_>>(), (2..=12).collect::<Vec<_>>());
+        assert_eq!(read_sidecar(&db).unwrap(), Some(checksum(&rows).unwrap()));
+    }
+
+    #[test]
+    fn every_backend_records_what_verify_computes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let backends: Vec<(String, Box<dyn PriceStore>)> = vec![
+            (path("prices.csv"), Box::new(CsvStore::open(&path("prices.csv")).unwrap())),
+            (path("prices.csv.gz"), Box::new(CsvStore::open(&path("prices.csv.gz")).unwrap())),
+            (path("prices.sqlite"), Box::new(SqliteStore::open(&path("prices.sqlite")).unwrap())),
+            (path("prices.d/"), Box::new(DirStore::open(&path("prices.d/")).unwrap())),
+        ];
+        for (db, inner) in backends {
+            let store = ChecksumStore::new(inner, &db);
+            store.append(&[row("Milk", ""), row("Bread", "a, b")]).unwrap();
+            store.delete(&[2]).unwrap();
+            store.append(&[row("Tea", "x\ny")]).unwrap();
+            store.append(&[row("Rice", "")]).unwrap();
+            assert_eq!(read_sidecar(&db).unwrap(), Some(checksum(&store.all().unwrap()).unwrap()), "{}", db);
+        }
+    }
+
+    #[test]
+    fn a_sidecar_without_its_state_is_recorded_again_in_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let store = ChecksumStore::new(Box::new(CsvStore::open(&db).unwrap()), &db);
+        store.append(&[row("Milk", "")]).unwrap();
+        fs::write(sidecar_path(&db), "0123abcd\n").unwrap();
+        store.append(&[row("Tea", "")]).unwrap();
+        assert_eq!(read_sidecar(&db).unwrap(), Some(checksum(&store.all().unwrap()).unwrap()));
+        fs::write(sidecar_path(&db), "0123abcd\nnot-hex\n").unwrap();
+        store.append(&[row("Rice", "")]).unwrap();
+        assert_eq!(read_sidecar(&db).unwrap(), Some(checksum(&store.all().unwrap()).unwrap()));
+    }
+}
@@ -0,0 +1,134 @@
+//! Optional user configuration from `config.toml` in the platform config directory
+//! (`~/.config/pricepeek/config.toml` on Linux). Command-line flags take precedence.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use chrono::format::{Item, StrftimeItems};
+use serde::Deserialize;
+
+use crate::{format_price, format_time, Failure, Row};
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Database path used when neither `--db` nor `PRICEPEEK_DB` is set.
+    pub db_path: Option<String>,
+    /// Category offered when adding and no previous category is remembered.
+    pub default_category: String,
+    /// Export filename used when none is given.
+    pub default_export_file: String,
+    /// Printed in front of prices that have no currency code, e.g. "$" or "€".
+    pub currency_symbol: String,
+    /// ISO 4217 code offered when adding, and assumed for rows without one.
+    pub default_currency: String,
+    /// Ask before deleting entries.
+    pub confirm_deletes: bool,
+    /// Mark listed entries older than this many days with `*`, and the default age for `stale`.
+    pub stale_after_days: Option<u32>,
+    /// Warn before saving a price this many times above or below the product's median; 1 or
+    /// less turns the check off.
+    pub anomaly_factor: f64,
+    /// Query parameters removed by `add --normalize-url` and `normalize-urls`; a trailing `*`
+    /// matches any suffix.
+    pub strip_url_params: Vec<String>,
+    /// Copy the database into `backups/` next to it before each rewrite.
+    pub auto_backup: bool,
+    /// How many of those copies to keep; older ones are deleted.
+    pub backup_keep: usize,
+    /// Record the checksum of the entries in `<database>.sha256` after every change, for
+    /// `verify` to compare against.
+    pub checksum_file: bool,
+    /// Keep the database encrypted, as `--encrypted` does for one run.
+    pub encrypted: bool,
+    /// Never change the database, as `--read-only` does for one run.
+    pub read_only: bool,
+    /// Wait for every write to reach the disk, as `--sync-writes` does for one run.
+    pub sync_writes: bool,
+    /// strftime format timestamps are shown in; exports keep the stored value.
+    pub time_format: String,
+    /// Show timestamps in UTC instead of the local timezone, as `--utc` does for one run.
+    pub utc: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            db_path: None,
+            default_category: String::new(),
+            default_export_file: "export.csv".to_string(),
+            currency_symbol: String::new(),
+            default_currency: String::new(),
+            confirm_deletes: true,
+            stale_after_days: None,
+            anomaly_factor: 5.0,
+            strip_url_params: ["utm_*", "ref", "ref_", "tag", "fbclid", "gclid"].map(String::from).to_vec(),
+            auto_backup: true,
+            backup_keep: 10,
+            checksum_file: false,
+            encrypted: false,
+            read_only: false,
+            sync_writes: false,
+            time_format: "%Y-%m-%d %H:%M".to_string(),
+            utc: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        directories::ProjectDirs::from("", "", "pricepeek").map(|d| d.config_dir().join("config.toml"))
+    }
+
+    /// Load the config file, or defaults if there is none.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path().filter(|p| p.exists()) else {
+            return Ok(Config::default());
+        };
+        let text = fs::read_to_string(&path)?;
+        let mut cfg: Config = toml::from_str(&text)
+            .map_err(|e| Failure::InvalidInput(format!("Invalid config file {}: {}", path.display(), e)))?;
+        cfg.db_path = cfg.db_path.map(|p| expand_home(&p));
+        if StrftimeItems::new(&cfg.time_format).any(|item| item == Item::Error) {
+            return Err(Failure::InvalidInput(format!(
+                "Invalid config file {}: time_format '{}' is not a valid strftime format",
+                path.display(),
+                cfg.time_format
+            ))
+            .into());
+        }
+        Ok(cfg)
+    }
+
+    pub fn price(&self, r: &Row) -> String {
+        format_price(r.price.to_f64(), &r.currency, &self.currency_symbol)
+    }
+
+    /// A stored timestamp in the local timezone, or UTC with `utc`, formatted with `time_format`.
+    /// Timestamps that don't parse are shown as stored.
+    pub fn time(&self, timestamp: &str) -> String {
+        format_time(timestamp, &self.time_format, self.utc).unwrap_or_else(|| timestamp.to_string())
+    }
+
+    /// The date part of `time`, or "unknown" for a blank timestamp.
+    pub fn date(&self, timestamp: &str) -> String {
+        match format_time(timestamp, "%Y-%m-%d", self.utc) {
+            Some(date) => date,
+            None if timestamp.trim().is_empty() => "unknown".to_string(),
+            None => timestamp.to_string(),
+        }
+    }
+
+    /// Unit price such as "6.98/kg", when the row has a quantity.
+    pub fn unit_price(&self, r: &Row) -> Option<String> {
+        r.unit_price().map(|(p, unit)| format!("{}/{}", format_price(p, &r.currency, &self.currency_symbol), unit))
+    }
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), directories::BaseDirs::new()) {
+        (Some(rest), Some(dirs)) => dirs.home_dir().join(rest).to_string_lossy().into_owned(),
+        _ => path.to_string(),
+    }
+}
@@ -0,0 +1,92 @@
+//! Encryption of the database file at rest. An encrypted file holds the whole CSV (gzipped
+//! first for `.gz` names) sealed with XChaCha20-Poly1305 under a key derived from a
+//! passphrase with Argon2id.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use crate::Failure;
+
+/// What an encrypted file starts with, so it is told apart from CSV and gzip.
+const MAGIC: &[u8] = b"PRICEPEEK-ENCRYPTED-1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Whether `data`, the start of a file, is that of an encrypted database.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Whether the file at `path` is an encrypted database; `false` when it can't be read.
+pub fn file_is_encrypted(path: &str) -> bool {
+    let mut head = [0; MAGIC.len()];
+    fs::File::open(path).and_then(|mut f| f.read_exact(&mut head)).is_ok() && is_encrypted(&head)
+}
+
+/// Seals and opens database files with one passphrase. The key is derived once per salt, so a
+/// run that reads and rewrites the database pays for the derivation only once.
+pub struct Cipher {
+    passphrase: String,
+    derived: RefCell<Option<([u8; SALT_LEN], Key)>>,
+}
+
+impl Cipher {
+    pub fn new(passphrase: String) -> Self {
+        Cipher { passphrase, derived: RefCell::default() }
+    }
+
+    fn key(&self, salt: [u8; SALT_LEN]) -> Result<Key> {
+        if let Some((s, key)) = &*self.derived.borrow() {
+            if *s == salt {
+                return Ok(*key);
+            }
+        }
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("Derive the key: {}", e))?;
+        self.derived.replace(Some((salt, key)));
+        Ok(key)
+    }
+
+    /// `plain` as the contents of an encrypted file. The salt of the last file read or written
+    /// is kept, with a fresh nonce.
+    pub fn encrypt(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let salt = self.derived.borrow().as_ref().map(|(salt, _)| *salt).unwrap_or_else(|| {
+            let mut salt = [0; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            salt
+        });
+        let key = self.key(salt)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut out = [MAGIC, &salt].concat();
+        let sealed = XChaCha20Poly1305::new(&key)
+            .encrypt(&nonce, Payload { msg: plain, aad: &out })
+            .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    /// The plain contents of `data`, the encrypted file at `path`. A wrong passphrase and a
+    /// damaged file can't be told apart; both fail with `Failure::InvalidInput`.
+    pub fn decrypt(&self, data: &[u8], path: &str) -> Result<Vec<u8>> {
+        let header = MAGIC.len() + SALT_LEN;
+        if !is_encrypted(data) || data.len() < header + NONCE_LEN {
+            return Err(Failure::InvalidInput(format!("{} is not an encrypted database or is cut short", path)).into());
+        }
+        let salt: [u8; SALT_LEN] = data[MAGIC.len()..header].try_into().context("Read the salt")?;
+        let key = self.key(salt)?;
+        let nonce = XNonce::from_slice(&data[header..header + NONCE_LEN]);
+        XChaCha20Poly1305::new(&key)
+            .decrypt(nonce, Payload { msg: &data[header + NONCE_LEN..], aad: &data[..header] })
+            .map_err(|_| Failure::InvalidInput(format!("Wrong passphrase for {}, or the file is damaged", path)).into())
+    }
+}
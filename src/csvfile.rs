@@ -0,0 +1,763 @@
+//! The CSV file backend: rows are read in full and the file is replaced on every change
+//! except appends. Files named `*.gz` are kept gzip-compressed, and stores opened with a
+//! `Cipher` keep the file encrypted.
+
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+
+use csv::StringRecord;
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::crypt::{self, Cipher};
+use crate::ids;
+use crate::row::{parse_record, record, Issue, Layout, Row, HEADER};
+use crate::store::{with_ids, PriceStore};
+use crate::Failure;
+
+/// Whether writes wait until the data is on disk; see `set_sync_writes`.
+static SYNC_WRITES: AtomicBool = AtomicBool::new(false);
+
+/// Make every database write and export wait until the data has reached the disk, so it
+/// survives a power loss right after the command ends. Off by default: the wait can take
+/// tens of milliseconds per file, more on network or USB drives.
+pub fn set_sync_writes(on: bool) {
+    SYNC_WRITES.store(on, Ordering::Relaxed);
+}
+
+/// Complete `out`, the file at `path`, and with `set_sync_writes` on wait until it and the
+/// directory entry naming it are on disk. Every writer of database and export files ends
+/// here.
+pub fn close(out: Output<fs::File>, path: &str) -> Result<fs::File> {
+    let file = out.finish().with_context(|| format!("Write {}", path))?;
+    if SYNC_WRITES.load(Ordering::Relaxed) {
+        file.sync_all().with_context(|| format!("Sync {}", path))?;
+        sync_dir(path)?;
+    }
+    Ok(file)
+}
+
+/// With `set_sync_writes` on, wait until the directory holding `path` is on disk, which makes
+/// a file just created or renamed there durable. Directories can only be synced on Unix.
+fn sync_dir(path: &str) -> Result<()> {
+    if cfg!(unix) && SYNC_WRITES.load(Ordering::Relaxed) {
+        let dir = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::File::open(dir).and_then(|d| d.sync_all()).with_context(|| format!("Sync {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// The database as one CSV file.
+pub struct CsvStore {
+    path: String,
+    /// From the last read.
+    issues: RefCell<Vec<Issue>>,
+    cipher: Option<Rc<Cipher>>,
+}
+
+impl CsvStore {
+    pub fn open(path: &str) -> Result<Self> {
+        ensure_db(path, None)?;
+        Ok(CsvStore { path: path.to_string(), issues: RefCell::default(), cipher: None })
+    }
+
+    /// Open the database at `path` encrypted with `cipher`. Plain files are read as well, and
+    /// encrypted by the first write.
+    pub fn encrypted(path: &str, cipher: Rc<Cipher>) -> Result<Self> {
+        ensure_db(path, Some(&cipher))?;
+        Ok(CsvStore { path: path.to_string(), issues: RefCell::default(), cipher: Some(cipher) })
+    }
+}
+
+impl PriceStore for CsvStore {
+    fn all(&self) -> Result<Vec<Row>> {
+        self.issues.borrow_mut().clear();
+        let next = ids::read(&self.path)? + 1;
+        read_rows(&self.path, self.cipher.as_deref(), next, |issue| self.issues.borrow_mut().push(issue))
+    }
+
+    fn stream(&self) -> Result<Box<dyn Iterator<Item = Result<Row>> + '_>> {
+        self.issues.borrow_mut().clear();
+        Ok(Box::new(stream_rows(&self.path, self.cipher.as_deref(), |issue| self.issues.borrow_mut().push(issue))?))
+    }
+
+    fn issues(&self) -> Vec<Issue> {
+        self.issues.borrow().clone()
+    }
+
+    fn append(&self, rows: &[Row]) -> Result<()> {
+        append_rows(&self.path, rows, self.cipher.as_deref())
+    }
+
+    fn overwrite(&self, rows: &[Row]) -> Result<()> {
+        save(&self.path, rows.to_vec(), self.cipher.as_deref())
+    }
+
+    fn outdated(&self) -> Result<Option<String>> {
+        let layout = layout(&self.path, self.cipher.as_deref())?;
+        Ok((layout != Layout::CURRENT).then(|| layout.to_string()))
+    }
+
+    fn highest_id(&self) -> Result<Option<u64>> {
+        Ok(Some(ids::read(&self.path)?))
+    }
+}
+
+fn ensure_db(path: &str, cipher: Option<&Cipher>) -> Result<()> {
+    if !Path::new(path).exists() {
+        if let Some(dir) = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Create directory {} for database {}", dir.display(), path))?;
+        }
+        write_rows(path, &[], cipher)?;
+    }
+    Ok(())
+}
+
+/// Replace the database with `rows`, giving those without an id the next ones never given out,
+/// and record the highest id.
+fn save(path: &str, rows: Vec<Row>, cipher: Option<&Cipher>) -> Result<()> {
+    let rows = with_ids(rows, ids::read(path)? + 1);
+    write_rows(path, &rows, cipher)?;
+    ids::record(path, ids::highest(&rows), file_length(path)?)
+}
+
+fn file_length(path: &str) -> Result<u64> {
+    Ok(fs::metadata(path).with_context(|| format!("Read {}", path))?.len())
+}
+
+/// Add `new` at the end of the file with the next ids never given out, leaving the existing
+/// rows untouched. Only the header is read, so appends take the same time however long the
+/// file is, unless something other than pricepeek has changed it since it was last written;
+/// then the id column is read as well, for ids above those recorded. A file in an older layout,
+/// or with rows from before the `id` column, is rewritten instead, which brings it up to date
+/// and stores the ids `read_rows` made up. Encrypted files are always rewritten, as they are
+/// sealed as a whole.
+fn append_rows(path: &str, new: &[Row], cipher: Option<&Cipher>) -> Result<()> {
+    ensure_db(path, cipher)?;
+    let (given, length) = ids::read_with_length(path)?;
+    let rewrite = || -> Result<()> {
+        let mut rows = read_rows(path, cipher, given + 1, |_| {})?;
+        rows.extend(new.iter().map(|r| Row { id: 0, ..r.clone() }));
+        save(path, rows, cipher)
+    };
+    if cipher.is_some() {
+        return rewrite();
+    }
+    let (mut rdr, layout) = open_reader(path, None)?;
+    if layout != Layout::CURRENT || rdr.headers()?.iter().ne(HEADER) {
+        return rewrite();
+    }
+    let mut next = given + 1;
+    let mut newline = false;
+    // Pricepeek leaves every row with an id no higher than the recorded one, and a final newline.
+    if length != Some(file_length(path)?) {
+        for rec in rdr.records() {
+            match rec?.get(5).and_then(|v| v.parse::<u64>().ok()).filter(|id| *id > 0) {
+                Some(id) => next = next.max(id + 1),
+                None => return rewrite(),
+            }
+        }
+        // A file last saved by another program may lack the final newline; the record must not
+        // end up glued to the last row.
+        newline = rdr.get_ref().last.is_some_and(|b| b != b'\n');
+    }
+    let file = OpenOptions::new().append(true).open(path).with_context(|| format!("Open {}", path))?;
+    // Gzip files take the rows as one more member, which readers continue into.
+    let mut out = Output::new(file, path);
+    if newline {
+        out.write_all(b"\n")?;
+    }
+    let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(&mut out);
+    for (id, r) in (next..).zip(new) {
+        wtr.write_record(record(r, id))?;
+    }
+    wtr.flush().with_context(|| format!("Write {}", path))?;
+    drop(wtr);
+    let length = close(out, path)?.metadata().with_context(|| format!("Read {}", path))?.len();
+    info!("appended {} rows to {}", new.len(), path);
+    ids::record(path, next + new.len() as u64 - 1, length)
+}
+
+/// All rows, with IDs from `next` on filled in for rows from files written before the `id`
+/// column existed. Assignment is deterministic, so the IDs shown now are the ones the next
+/// write persists.
+fn read_rows(path: &str, cipher: Option<&Cipher>, next: u64, report: impl FnMut(Issue)) -> Result<Vec<Row>> {
+    let rows: Vec<Row> = stream_rows(path, cipher, report)?.collect::<Result<_>>()?;
+    Ok(with_ids(rows, next))
+}
+
+fn next_id(rows: &[Row]) -> u64 {
+    rows.iter().map(|r| r.id).max().unwrap_or(0) + 1
+}
+
+/// Rows in file order, parsed one at a time as the iterator advances. Records that needed
+/// made-up fields are still yielded, and also passed to `report`. Records with only empty
+/// fields, such as the `,,,,` spreadsheets leave below the data, are skipped, and so are
+/// further header rows, as in two files joined into one; rows after such a header are read
+/// in the layout it names, which is reported when it differs.
+fn stream_rows<'a>(
+    path: &str,
+    cipher: Option<&Cipher>,
+    mut report: impl FnMut(Issue) + 'a,
+) -> Result<impl Iterator<Item = Result<Row>> + 'a> {
+    ensure_db(path, cipher)?;
+    let (rdr, mut layout) = open_reader(path, cipher)?;
+    Ok(rdr.into_records().filter_map(move |rec| {
+        let rec = match rec {
+            Ok(rec) => trim_end(&rec)?,
+            Err(e) => return Some(Err(e.into())),
+        };
+        // Every layout's header starts with `product`; only such lines need a closer look.
+        let header = rec.get(0).is_some_and(|f| f.trim().eq_ignore_ascii_case(HEADER[0])).then(|| Layout::detect(&rec));
+        if let Some(Ok(header)) = header {
+            let problem = if header == layout {
+                "repeated header, skipped".to_string()
+            } else {
+                format!("header of a {} file, the rows below it read in that layout", header)
+            };
+            layout = header;
+            let issue = Issue::new(&rec, vec![problem]);
+            debug!("line {}: {}", issue.line, issue.problems.join("; "));
+            report(issue);
+            return None;
+        }
+        let (row, problems) = parse_record(&rec, layout);
+        if !problems.is_empty() {
+            let issue = Issue::new(&rec, problems);
+            debug!("line {}: {}", issue.line, issue.problems.join("; "));
+            report(issue);
+        }
+        Some(Ok(row))
+    }))
+}
+
+/// `rec` with trailing whitespace cut from every field, or `None` when nothing is left.
+fn trim_end(rec: &StringRecord) -> Option<StringRecord> {
+    if rec.iter().all(|f| f.trim().is_empty()) {
+        return None;
+    }
+    let mut trimmed: StringRecord = rec.iter().map(str::trim_end).collect();
+    trimmed.set_position(rec.position().cloned());
+    Some(trimmed)
+}
+
+/// Records of a database file, plain, decrypted or decompressed.
+type CsvReader = csv::Reader<LfReader<Box<dyn BufRead>>>;
+
+/// A reader positioned after the header, and the layout the header names. An encrypted file
+/// is decrypted into memory as a whole, which needs `cipher`.
+fn open_reader(path: &str, cipher: Option<&Cipher>) -> Result<(CsvReader, Layout)> {
+    let mut file = BufReader::new(fs::File::open(path).with_context(|| format!("Open {}", path))?);
+    // Encrypted and compressed files are recognized by their contents, so backups and renamed
+    // copies read too.
+    let mut input: Box<dyn BufRead> = if crypt::is_encrypted(file.fill_buf().with_context(|| format!("Read {}", path))?) {
+        let cipher = cipher.ok_or_else(|| Failure::InvalidInput(format!("{} is encrypted and no passphrase was given", path)))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).with_context(|| format!("Read {}", path))?;
+        Box::new(Cursor::new(cipher.decrypt(&data, path)?))
+    } else {
+        Box::new(file)
+    };
+    if input.fill_buf().with_context(|| format!("Read {}", path))?.starts_with(&[0x1f, 0x8b]) {
+        input = Box::new(BufReader::new(MultiGzDecoder::new(input)));
+    }
+    // Flexible, so a record with the wrong number of fields is reported rather than failing
+    // the whole file. Blank lines are skipped by the reader itself.
+    let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(LfReader::new(input));
+    let layout = Layout::detect(rdr.headers().with_context(|| format!("Read {}", path))?)
+        .with_context(|| format!("Can't read {}", path))?;
+    Ok((rdr, layout))
+}
+
+/// Reads `inner` without a leading UTF-8 byte order mark and with CRLF line ends turned into
+/// LF, as files saved by spreadsheet programs need. The csv reader accepts CRLF by itself but
+/// then counts lines from the wrong end, so issues would point at the line above.
+struct LfReader<R> {
+    inner: R,
+    line: Vec<u8>,
+    pos: usize,
+    first: bool,
+    /// The last byte read so far.
+    last: Option<u8>,
+}
+
+impl<R: BufRead> LfReader<R> {
+    fn new(inner: R) -> Self {
+        LfReader { inner, line: Vec::new(), pos: 0, first: true, last: None }
+    }
+}
+
+impl<R: BufRead> Read for LfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            if self.inner.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(0);
+            }
+            if self.line.ends_with(b"\r\n") {
+                self.line.truncate(self.line.len() - 2);
+                self.line.push(b'\n');
+            }
+            if std::mem::take(&mut self.first) && self.line.starts_with("\u{feff}".as_bytes()) {
+                self.line.drain(.."\u{feff}".len());
+            }
+            self.last = self.line.last().copied().or(self.last);
+        }
+        let n = (&self.line[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// The layout of the file at `path`, from its header row.
+pub fn layout(path: &str, cipher: Option<&Cipher>) -> Result<Layout> {
+    ensure_db(path, cipher)?;
+    Ok(open_reader(path, cipher)?.1)
+}
+
+/// Replace the database with `rows`, encrypted with `cipher` if given, through `replace`.
+pub fn write_rows(path: &str, rows: &[Row], cipher: Option<&Cipher>) -> Result<()> {
+    replace(path, |tmp| {
+        match cipher {
+            Some(cipher) => {
+                let sealed = cipher.encrypt(&encode(Vec::new(), path, rows)?)?;
+                tmp.write_all(&sealed).with_context(|| format!("Write {}", path))?;
+            }
+            None => {
+                encode(tmp, path, rows)?;
+            }
+        }
+        Ok(())
+    })?;
+    info!("wrote {} rows to {}", rows.len(), path);
+    Ok(())
+}
+
+/// Replace the file at `path` with what `write` puts into a temporary file next to it, which
+/// is synced and renamed over the original, so a crash or full disk midway leaves the old file
+/// intact. When `write` or the rename fails the temporary file is removed. It is synced even
+/// without `set_sync_writes`, as the rename is only safe after it; that setting also waits
+/// for the rename itself to reach the disk.
+pub(crate) fn replace(path: &str, write: impl FnOnce(&mut tempfile::NamedTempFile) -> Result<()>) -> Result<()> {
+    let dir = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut tmp = tempfile::Builder::new()
+        .prefix(".pricepeek-")
+        .suffix(".tmp")
+        .tempfile_in(dir)
+        .with_context(|| format!("Create a temporary file in {}", dir.display()))?;
+    if let Ok(meta) = fs::metadata(path) {
+        fs::set_permissions(tmp.path(), meta.permissions()).with_context(|| format!("Write {}", path))?;
+    }
+    write(&mut tmp)?;
+    tmp.as_file().sync_all().with_context(|| format!("Write {}", path))?;
+    // `persist` replaces an existing file on Windows as well. Its error holds on to the
+    // temporary file, which is only deleted once that is dropped; the process may exit first.
+    tmp.persist(path).map_err(|e| e.error).with_context(|| format!("Replace {}", path))?;
+    sync_dir(path)
+}
+
+/// The file contents for `rows` written to `inner`, compressed if `path` asks for it.
+fn encode<W: Write>(inner: W, path: &str, rows: &[Row]) -> Result<W> {
+    let mut wtr = csv::Writer::from_writer(Output::new(inner, path));
+    write_records(&mut wtr, rows).with_context(|| format!("Write {}", path))?;
+    let out = wtr.into_inner().map_err(|e| e.into_error()).with_context(|| format!("Write {}", path))?;
+    out.finish().with_context(|| format!("Write {}", path))
+}
+
+/// The header and `rows` in CSV form, giving rows without an id the next free ones.
+fn write_records(wtr: &mut csv::Writer<impl Write>, rows: &[Row]) -> Result<()> {
+    wtr.write_record(HEADER)?;
+    // New rows arrive without an id; give them the next free ones.
+    let mut next = next_id(rows);
+    for r in rows {
+        let id = if r.id == 0 {
+            next += 1;
+            next - 1
+        } else {
+            r.id
+        };
+        wtr.write_record(record(r, id))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// A file being written, gzip-compressed when its name ends in `.gz`.
+pub enum Output<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> Output<W> {
+    /// Write to `inner`, which is the file at `path`.
+    pub fn new(inner: W, path: &str) -> Self {
+        if path.ends_with(".gz") {
+            Output::Gzip(GzEncoder::new(inner, Compression::default()))
+        } else {
+            Output::Plain(inner)
+        }
+    }
+
+    /// Complete the gzip stream and flush; the file is only valid after this.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Output::Plain(mut w) => {
+                w.flush()?;
+                Ok(w)
+            }
+            Output::Gzip(gz) => gz.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(w) => w.write(buf),
+            Output::Gzip(gz) => gz.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(w) => w.flush(),
+            Output::Gzip(gz) => gz.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Money;
+
+    /// A database file holding `text` in a temporary directory, which must outlive its use.
+    fn fixture(name: &str, text: &str) -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name).to_string_lossy().into_owned();
+        fs::write(&path, text).unwrap();
+        (dir, path)
+    }
+
+    fn row(product: &str, price: &str) -> Row {
+        let source = crate::row::SOURCE_MANUAL.to_string();
+        Row { product: product.to_string(), price: price.parse().unwrap(), source, ..Default::default() }
+    }
+
+    #[test]
+    fn an_append_keeps_four_decimal_prices() {
+        let (_dir, path) = fixture("prices.csv", "product,category,price,url,timestamp\nSugar,food,0.1795,,2024-01-01T00:00:00Z\n");
+        let store = CsvStore::open(&path).unwrap();
+        assert_eq!(store.all().unwrap()[0].price, "0.1795".parse::<Money>().unwrap());
+        store.append(&[row("Salt", "1.2")]).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.lines().nth(1).unwrap().starts_with("Sugar,food,0.1795,"), "{}", text);
+        assert!(text.lines().nth(2).unwrap().starts_with("Salt,,1.20,"), "{}", text);
+    }
+
+    #[test]
+    fn a_hundred_read_write_cycles_leave_the_file_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let store = CsvStore::open(&path).unwrap();
+        let rows: Vec<Row> = ["19.99", "19.999", "0.1795", "0.1", "-3", "1234567.8901", "0"]
+            .iter()
+            .map(|p| Row { notes: "a, \"quoted\"\nnote".to_string(), ..row("Item", p) })
+            .collect();
+        store.overwrite(&rows).unwrap();
+        let first = fs::read_to_string(&path).unwrap();
+        for _ in 0..100 {
+            store.overwrite(&store.all().unwrap()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), first);
+        let prices: Vec<String> = store.all().unwrap().iter().map(|r| r.price.to_string()).collect();
+        assert_eq!(prices, ["19.99", "19.999", "0.1795", "0.10", "-3.00", "1234567.8901", "0.00"]);
+    }
+
+    #[test]
+    fn notes_with_commas_quotes_and_newlines_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let store = CsvStore::open(&path).unwrap();
+        let notes = ["price is for the 2-pack, needs coupon \"SAVE10\"", "line one\nline two\n\nline four", "trailing comma,", "\"", ""];
+        let rows: Vec<Row> = notes.iter().map(|n| Row { notes: n.to_string(), ..row("Cable", "9.99") }).collect();
+        store.overwrite(&rows[..2]).unwrap();
+        store.append(&rows[2..]).unwrap();
+        let read: Vec<String> = store.all().unwrap().into_iter().map(|r| r.notes).collect();
+        assert_eq!(read, notes);
+        assert!(store.issues().is_empty(), "{:?}", store.issues());
+
+        // A broken row below a multi-line note is reported on its own line of the file.
+        let mut text = fs::read_to_string(&path).unwrap();
+        text.push_str("Tea,,abc\n");
+        fs::write(&path, &text).unwrap();
+        store.all().unwrap();
+        assert_eq!(store.issues()[0].line, text.lines().count() as u64);
+    }
+
+    #[test]
+    fn appending_ten_thousand_rows_one_at_a_time_takes_linear_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let store = CsvStore::open(&path).unwrap();
+        let mut rounds = Vec::new();
+        for _ in 0..10 {
+            let start = std::time::Instant::now();
+            for _ in 0..1000 {
+                store.append(&[row("Cable", "9.99")]).unwrap();
+            }
+            rounds.push(start.elapsed());
+        }
+        let ids: Vec<u64> = store.all().unwrap().iter().map(|r| r.id).collect();
+        assert_eq!(ids, (1..=10_000).collect::<Vec<_>>());
+        // Reading the file on every append would make the last thousand take about twenty times
+        // as long as the first.
+        let first = rounds[..2].iter().min().unwrap();
+        assert!(rounds[9] < *first * 4, "{:?}", rounds);
+    }
+
+    #[test]
+    fn an_append_reads_the_ids_of_a_file_changed_elsewhere() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let store = CsvStore::open(&path).unwrap();
+        store.append(&[row("Milk", "1.19")]).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"Tea,,3.00,,,50,,,,,,,,0.00,unknown,,manual,,0,,,").unwrap();
+        store.append(&[row("Rice", "2")]).unwrap();
+        let read: Vec<(u64, String)> = store.all().unwrap().into_iter().map(|r| (r.id, r.product)).collect();
+        assert_eq!(read, [(1, "Milk".to_string()), (50, "Tea".to_string()), (51, "Rice".to_string())]);
+        assert!(store.issues().is_empty());
+    }
+
+    #[test]
+    fn a_hundred_thousand_rows_stream_one_at_a_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let rows: Vec<Row> = (1..=100_000).map(|id| Row { id, ..row(&format!("Item {}", id), "1.5") }).collect();
+        write_rows(&path, &rows, None).unwrap();
+        drop(rows);
+        let store = CsvStore::open(&path).unwrap();
+        let mut expected = 1..;
+        assert!(store.stream().unwrap().all(|r| r.unwrap().id == expected.next().unwrap()));
+        assert_eq!(expected.next(), Some(100_001));
+
+        // Rows come from the file as the stream advances, so emptying it midway ends the stream.
+        let mut rows = store.stream().unwrap();
+        let first: Vec<Row> = rows.by_ref().take(10).collect::<Result<_>>().unwrap();
+        assert_eq!(first[9].product, "Item 10");
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(0).unwrap();
+        let rest = rows.count();
+        assert!(rest < 1000, "{} rows were read ahead", rest);
+    }
+
+    /// The names of the files in `dir`.
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn a_failed_write_leaves_the_original_and_no_temporary_file() {
+        let (dir, path) = fixture("prices.csv", "product,category,price,url,timestamp\nMilk,food,1.19,,\n");
+        let before = fs::read(&path).unwrap();
+        let err = replace(&path, |tmp| {
+            tmp.write_all(b"product,category\nhalf a ro")?;
+            Err(io::Error::new(io::ErrorKind::StorageFull, "disk full").into())
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("disk full"));
+        assert_eq!(fs::read(&path).unwrap(), before);
+        assert_eq!(files(dir.path()), ["prices.csv"]);
+        assert_eq!(CsvStore::open(&path).unwrap().all().unwrap()[0].product, "Milk");
+    }
+
+    #[test]
+    fn a_failed_rename_leaves_the_original_and_no_temporary_file() {
+        // A directory in the way can't be replaced by a file.
+        let (dir, _) = fixture("keep.txt", "kept");
+        let path = dir.path().join("prices.csv");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inside.txt"), "kept").unwrap();
+        let err = write_rows(&path.to_string_lossy(), &[row("Milk", "1.19")], None).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Replace"), "{:#}", err);
+        assert_eq!(files(dir.path()), ["keep.txt", "prices.csv"]);
+        assert_eq!(files(&path), ["inside.txt"]);
+    }
+
+    /// Products and prices of every row in the file at `path`, and the lines of its issues.
+    fn read_back(path: &str) -> (Vec<(String, String)>, Vec<u64>) {
+        let store = CsvStore::open(path).unwrap();
+        let rows = store.all().unwrap().into_iter().map(|r| (r.product, r.price.to_string())).collect();
+        (rows, store.issues().iter().map(|i| i.line).collect())
+    }
+
+    fn pairs(rows: &[(&str, &str)]) -> Vec<(String, String)> {
+        rows.iter().map(|(p, price)| (p.to_string(), price.to_string())).collect()
+    }
+
+    #[test]
+    fn a_repeated_header_is_skipped_and_a_different_one_switches_layout() {
+        let text = "product,category,price,url,timestamp\nMilk,food,1.19,,\nproduct,category,price,url,timestamp\nBread,food,2.49,,\n\
+                    product,price,url,timestamp\nTea,3,,\nProduct,food,4,,\n";
+        let (_dir, path) = fixture("prices.csv", text);
+        let store = CsvStore::open(&path).unwrap();
+        let rows = store.all().unwrap();
+        let read: Vec<(&str, String)> = rows.iter().map(|r| (r.product.as_str(), r.price.to_string())).collect();
+        // The last line isn't a header, so it is a legacy row with an unreadable price.
+        assert_eq!(read, [("Milk", "1.19".into()), ("Bread", "2.49".into()), ("Tea", "3.00".into()), ("Product", "0.00".into())]);
+        let issues = store.issues();
+        let problems: Vec<(u64, &str)> = issues.iter().map(|i| (i.line, i.problems[0].as_str())).collect();
+        assert_eq!(
+            problems,
+            [
+                (3, "repeated header, skipped"),
+                (5, "header of a legacy product,price,url,timestamp file, the rows below it read in that layout"),
+                (7, "5 fields instead of 4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fields_past_the_end_of_a_row_are_kept_in_extra() {
+        let text = "product,category,price,url,timestamp\nMilk,food,1.19,,2024-01-01,99,EUR,, shop\n";
+        let (_dir, path) = fixture("prices.csv", text);
+        let store = CsvStore::open(&path).unwrap();
+        let rows = store.all().unwrap();
+        assert_eq!((rows[0].id, rows[0].currency.as_str()), (1, ""));
+        assert_eq!(rows[0].extra, "column6=99;column7=EUR;column9=shop");
+        assert_eq!(store.issues()[0].problems, ["9 fields instead of 5, the rest kept in extra as column6, column7, column9"]);
+        // They survive the rewrite that upgrades the file.
+        store.overwrite(&rows).unwrap();
+        assert_eq!(store.all().unwrap()[0].extra, "column6=99;column7=EUR;column9=shop");
+        assert!(store.issues().is_empty());
+    }
+
+    #[test]
+    fn a_byte_order_mark_is_skipped_and_not_written_back() {
+        let (_dir, path) = fixture("prices.csv", "\u{feff}product,category,price,url,timestamp\nMilk,food,1.19,,\n");
+        assert_eq!(layout(&path, None).unwrap(), Layout::Columns(5));
+        assert_eq!(read_back(&path), (pairs(&[("Milk", "1.19")]), vec![]));
+        let store = CsvStore::open(&path).unwrap();
+        store.overwrite(&store.all().unwrap()).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().starts_with("product,"));
+    }
+
+    #[test]
+    fn crlf_line_ends_read_and_issues_name_their_own_line() {
+        let text = "product,category,price,url,timestamp\r\nMilk,food,1.19,,2024-01-01\r\nBread,food,abc,,\r\nTea,food,3,,\r\n";
+        let (_dir, path) = fixture("prices.csv", text);
+        assert_eq!(read_back(&path), (pairs(&[("Milk", "1.19"), ("Bread", "0.00"), ("Tea", "3.00")]), vec![3]));
+        let store = CsvStore::open(&path).unwrap();
+        assert_eq!(store.all().unwrap()[0].timestamp, "2024-01-01");
+        store.overwrite(&store.all().unwrap()).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains('\r'));
+    }
+
+    #[test]
+    fn blank_and_empty_lines_are_skipped() {
+        let text = "product,category,price,url,timestamp\n\nMilk,food,1.19,,\n\n\n,,,,\nBread,food,abc,,\n  ,, ,,\n\n";
+        let (_dir, path) = fixture("prices.csv", text);
+        assert_eq!(read_back(&path), (pairs(&[("Milk", "1.19"), ("Bread", "0.00")]), vec![7]));
+    }
+
+    #[test]
+    fn a_gz_file_is_kept_compressed_through_writes_and_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.csv.gz").to_string_lossy().into_owned();
+        let store = CsvStore::open(&path).unwrap();
+        store.overwrite(&[row("Milk", "1.19"), Row { notes: "a, \"b\"\nc".to_string(), ..row("Bread", "2.49") }]).unwrap();
+        store.append(&[row("Tea", "3")]).unwrap();
+        assert!(fs::read(&path).unwrap().starts_with(&[0x1f, 0x8b]));
+        let rows = store.all().unwrap();
+        let read: Vec<(u64, &str, String)> = rows.iter().map(|r| (r.id, r.product.as_str(), r.price.to_string())).collect();
+        assert_eq!(read, [(1, "Milk", "1.19".into()), (2, "Bread", "2.49".into()), (3, "Tea", "3.00".into())]);
+        assert_eq!(rows[1].notes, "a, \"b\"\nc");
+
+        let mut text = String::new();
+        MultiGzDecoder::new(&fs::read(&path).unwrap()[..]).read_to_string(&mut text).unwrap();
+        assert!(text.starts_with(&HEADER.join(",")) && text.contains("\nTea,,3.00,"), "{}", text);
+    }
+
+    #[test]
+    fn a_plain_csv_file_is_not_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let store = CsvStore::open(&path).unwrap();
+        store.overwrite(&[row("Milk", "1.19")]).unwrap();
+        store.append(&[row("Tea", "3")]).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("product,") && text.ends_with("Tea,,3.00,,,2,,,,,,,,0.00,unknown,,manual,,0,,,\n"), "{}", text);
+    }
+
+    /// Fields of one row with every column set, in `HEADER` order.
+    fn full_record() -> [&'static str; HEADER.len()] {
+        [
+            "Milk", "food", "1.19", "https://shop.example/milk", "2024-01-02T03:04:05Z", "7", "EUR", "Corner Shop", "fresh",
+            "dairy;organic", "1.5", "l", "0.99", "0.50", "out_of_stock", "1", "import", "1.49", "2", "batch=3", "4001",
+            "2024-01-03T00:00:00Z",
+        ]
+    }
+
+    /// Read the file at `path`, which holds `expected` in an older layout, then upgrade it.
+    fn assert_upgrades(path: &str, expected: [String; HEADER.len()], old: &str) {
+        let store = CsvStore::open(path).unwrap();
+        assert_eq!(store.outdated().unwrap().as_deref(), Some(old));
+        let rows = store.all().unwrap();
+        assert!(store.issues().is_empty(), "{}: {:?}", old, store.issues());
+        assert_eq!(rows.len(), 1, "{}", old);
+        assert_eq!(record(&rows[0], rows[0].id), expected, "{}", old);
+
+        // What `upgrade` does.
+        store.overwrite(&rows).unwrap();
+        assert_eq!(layout(path, None).unwrap(), Layout::CURRENT, "{}", old);
+        assert_eq!(fs::read_to_string(path).unwrap().lines().next(), Some(HEADER.join(",").as_str()));
+        assert_eq!(store.outdated().unwrap(), None);
+        let rows = store.all().unwrap();
+        assert_eq!(record(&rows[0], rows[0].id), expected, "{}", old);
+    }
+
+    #[test]
+    fn every_column_layout_reads_and_upgrades() {
+        let full = full_record();
+        for n in crate::row::LAYOUTS {
+            let (_dir, path) = fixture("prices.csv", &format!("{}\n{}\n", HEADER[..n].join(","), full[..n].join(",")));
+            // Columns the layout lacks read as empty; rows from before the id column get id 1.
+            let blank = record(&Row { source: crate::row::SOURCE_MANUAL.to_string(), ..Default::default() }, 1);
+            let expected = std::array::from_fn(|i| if i < n { full[i].to_string() } else { blank[i].clone() });
+            if n == HEADER.len() {
+                let store = CsvStore::open(&path).unwrap();
+                assert_eq!(store.outdated().unwrap(), None);
+                assert_eq!(record(&store.all().unwrap()[0], 7), expected);
+            } else {
+                assert_upgrades(&path, expected, &Layout::Columns(n).to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn the_legacy_layout_reads_and_upgrades() {
+        let (_dir, path) = fixture(
+            "prices.csv",
+            "product,price,url,timestamp\nMilk,1.19,https://shop.example/milk,2024-01-02T03:04:05Z\n",
+        );
+        let row = Row {
+            id: 1,
+            url: "https://shop.example/milk".to_string(),
+            timestamp: "2024-01-02T03:04:05Z".to_string(),
+            ..row("Milk", "1.19")
+        };
+        assert_upgrades(&path, record(&row, 1), &Layout::Legacy.to_string());
+    }
+}
@@ -0,0 +1,178 @@
+//! Finding rows that record the same observation twice, for `dupes` and `dedupe`.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, FixedOffset};
+use clap::ValueEnum;
+
+use crate::aliases::Aliases;
+use crate::report::{compare_timestamps, product_identity};
+use crate::row::Row;
+use crate::text;
+
+/// Fields `dupes` compares. Text is compared trimmed and case-insensitively.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum DupeKey {
+    /// Product name, aliases resolved; the SKU when both entries have one
+    Product,
+    Url,
+    Price,
+    Category,
+    Store,
+    Currency,
+}
+
+/// What `dedupe` does with a group of duplicates.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum DedupeStrategy {
+    /// Keep the most recently recorded entry
+    #[value(name = "keep-newest")]
+    Newest,
+    /// Keep the first recorded entry
+    #[value(name = "keep-oldest")]
+    Oldest,
+    /// Leave the group alone
+    #[value(name = "keep-all")]
+    All,
+}
+
+/// A row with its 0-based position in the file.
+pub type Entry = (usize, Row);
+
+/// Groups of two or more rows agreeing on every key, each row with its 0-based file position,
+/// in file order of their first row.
+pub fn find_dupes(rows: &[Row], keys: &[DupeKey], aliases: &Aliases) -> Vec<Vec<Entry>> {
+    let identity = product_identity(rows, aliases);
+    let key = |r: &Row| {
+        let parts: Vec<String> = keys
+            .iter()
+            .map(|k| match k {
+                DupeKey::Product => identity(r),
+                DupeKey::Url => text::fold(&r.url),
+                DupeKey::Price => r.price.to_string(),
+                DupeKey::Category => text::fold(&r.category),
+                DupeKey::Store => text::fold(&r.store),
+                DupeKey::Currency => text::fold(&r.currency),
+            })
+            .collect();
+        parts.join("\0")
+    };
+    let mut groups: HashMap<String, Vec<Entry>> = HashMap::new();
+    for (i, r) in rows.iter().enumerate() {
+        groups.entry(key(r)).or_default().push((i, r.clone()));
+    }
+    let mut dupes: Vec<Vec<Entry>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    dupes.sort_by_key(|g| g[0].0);
+    dupes
+}
+
+/// Groups for `dedupe`, each flagged whether it is exact: first rows agreeing on product, URL
+/// and price however far apart they were recorded, then among the rest rows with the same
+/// product and URL recorded within an hour of each other. Timestamps are compared as times, so
+/// their formatting doesn't matter; rows whose timestamp can't be read only form exact groups.
+pub fn dedupe_groups(rows: &[Row], aliases: &Aliases) -> Vec<(bool, Vec<Entry>)> {
+    let exact = find_dupes(rows, &[DupeKey::Product, DupeKey::Url, DupeKey::Price], aliases);
+    let taken: HashSet<usize> = exact.iter().flatten().map(|(i, _)| *i).collect();
+    let mut groups: Vec<(bool, Vec<Entry>)> = exact.into_iter().map(|g| (true, g)).collect();
+    for g in find_dupes(rows, &[DupeKey::Product, DupeKey::Url], aliases) {
+        let mut timed: Vec<(DateTime<FixedOffset>, Entry)> = g
+            .into_iter()
+            .filter(|(i, _)| !taken.contains(i))
+            .filter_map(|(i, r)| DateTime::parse_from_rfc3339(&r.timestamp).ok().map(|t| (t, (i, r))))
+            .collect();
+        timed.sort_by_key(|(t, _)| *t);
+        let mut cluster: Vec<Entry> = Vec::new();
+        let mut last: Option<DateTime<FixedOffset>> = None;
+        for (t, entry) in timed {
+            if last.is_some_and(|l| t - l > chrono::Duration::hours(1)) {
+                if cluster.len() > 1 {
+                    groups.push((false, std::mem::take(&mut cluster)));
+                }
+                cluster.clear();
+            }
+            cluster.push(entry);
+            last = Some(t);
+        }
+        if cluster.len() > 1 {
+            groups.push((false, cluster));
+        }
+    }
+    groups
+}
+
+/// Positions in `group` that `strategy` removes: all but the newest or the oldest entry, by
+/// recorded time, or none.
+pub fn removals(group: &[Entry], strategy: DedupeStrategy) -> Vec<usize> {
+    let by_time = |a: &&Entry, b: &&Entry| compare_timestamps(&a.1.timestamp, &b.1.timestamp);
+    let keep = match strategy {
+        DedupeStrategy::All => None,
+        DedupeStrategy::Newest => group.iter().max_by(by_time),
+        DedupeStrategy::Oldest => group.iter().min_by(by_time),
+    };
+    match keep {
+        Some(&(keep, _)) => group.iter().map(|(i, _)| *i).filter(|i| *i != keep).collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::row::SOURCE_MANUAL;
+
+    fn row(product: &str, url: &str, price: &str, timestamp: &str) -> Row {
+        Row {
+            product: product.to_string(),
+            url: url.to_string(),
+            price: price.parse().unwrap(),
+            timestamp: timestamp.to_string(),
+            source: SOURCE_MANUAL.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn positions(groups: &[Vec<Entry>]) -> Vec<Vec<usize>> {
+        groups.iter().map(|g| g.iter().map(|(i, _)| *i).collect()).collect()
+    }
+
+    #[test]
+    fn dupes_agree_on_every_key_and_come_in_file_order() {
+        let rows = vec![
+            row("Tea", "https://a.example", "3", ""),
+            row("Milk", "", "1", ""),
+            row(" TEA ", "https://A.example", "3", ""),
+            row("milk", "", "2", ""),
+            row("Tea", "https://a.example", "4", ""),
+        ];
+        let aliases = Aliases::default();
+        assert_eq!(positions(&find_dupes(&rows, &[DupeKey::Product], &aliases)), [vec![0, 2, 4], vec![1, 3]]);
+        assert_eq!(positions(&find_dupes(&rows, &[DupeKey::Product, DupeKey::Url, DupeKey::Price], &aliases)), [vec![0, 2]]);
+    }
+
+    #[test]
+    fn near_duplicates_are_recorded_within_an_hour_of_each_other() {
+        let rows = vec![
+            row("Tea", "u", "3", "2024-01-01T10:00:00Z"),
+            row("Tea", "u", "3", "2024-05-01T10:00:00Z"),
+            row("Tea", "u", "4", "2024-01-01T11:30:00+01:00"),
+            row("Tea", "u", "5", "2024-01-01T10:50:00Z"),
+            row("Tea", "u", "6", "2024-01-01T13:00:00Z"),
+            row("Tea", "u", "7", "yesterday"),
+        ];
+        let groups = dedupe_groups(&rows, &Aliases::default());
+        let shown: Vec<(bool, Vec<usize>)> = groups.iter().map(|(exact, g)| (*exact, g.iter().map(|(i, _)| *i).collect())).collect();
+        assert_eq!(shown, [(true, vec![0, 1]), (false, vec![2, 3])]);
+    }
+
+    #[test]
+    fn strategies_keep_the_newest_the_oldest_or_everything() {
+        let group: Vec<Entry> = vec![
+            (4, row("Tea", "", "3", "2024-02-01T00:00:00Z")),
+            (7, row("Tea", "", "3", "2024-03-01T00:00:00Z")),
+            (9, row("Tea", "", "3", "not a time")),
+        ];
+        assert_eq!(removals(&group, DedupeStrategy::Newest), [4, 9]);
+        assert_eq!(removals(&group, DedupeStrategy::Oldest), [4, 7]);
+        assert!(removals(&group, DedupeStrategy::All).is_empty());
+    }
+}
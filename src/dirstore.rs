@@ -0,0 +1,165 @@
+//! The directory backend: one CSV file per category, e.g. `prices.d/groceries.csv`, read
+//! together as one database. Writes only touch the files whose rows change.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::info;
+
+use crate::category;
+use crate::csvfile::{self, CsvStore};
+use crate::ids;
+use crate::row::{record, Issue, Row};
+use crate::store::{with_ids, PriceStore};
+
+/// Name of the file for entries without a category.
+const UNCATEGORIZED: &str = "uncategorized";
+
+/// Every `*.csv` file in a directory, merged in id order. Each row is remembered with the
+/// file it came from, so changed rows are written back there, and new ones and ones whose
+/// category changed go to the file that holds their category, created when there is none.
+pub struct DirStore {
+    dir: PathBuf,
+    /// The path the directory was opened by, which `ids` records the highest id for.
+    db: String,
+    /// From the last read, each with the name of its file as first problem.
+    issues: RefCell<Vec<Issue>>,
+}
+
+/// One file of the directory as last read.
+struct Part {
+    path: PathBuf,
+    rows: Vec<Row>,
+    /// The file's layout when it predates the current one.
+    outdated: Option<String>,
+}
+
+impl DirStore {
+    /// Open the directory `dir`, creating it if needed.
+    pub fn open(dir: &str) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Create directory {}", dir))?;
+        Ok(DirStore { dir: PathBuf::from(dir), db: dir.to_string(), issues: RefCell::default() })
+    }
+
+    /// The CSV files, by name, with their rows. Rows without an id, or with one an earlier
+    /// file already uses, get the next ones never given out, in the same order on every read.
+    fn read(&self) -> Result<Vec<Part>> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.dir)
+            .with_context(|| format!("Read {}", self.dir.display()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<_>>()
+            .with_context(|| format!("Read {}", self.dir.display()))?;
+        paths.retain(|p| p.is_file() && p.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv")));
+        paths.sort();
+        let mut issues = Vec::new();
+        let mut parts = Vec::new();
+        for path in paths {
+            let name = path.to_string_lossy().into_owned();
+            let file = CsvStore::open(&name)?;
+            let rows = file.all()?;
+            for mut issue in file.issues() {
+                issue.problems.insert(0, format!("in {}", file_name(&path)));
+                issues.push(issue);
+            }
+            parts.push(Part { path, rows, outdated: file.outdated()? });
+        }
+        let mut seen = HashSet::new();
+        let highest = parts.iter().flat_map(|p| &p.rows).map(|r| r.id).max().unwrap_or(0);
+        let mut next = highest.max(ids::read(&self.db)?) + 1;
+        for r in parts.iter_mut().flat_map(|p| p.rows.iter_mut()) {
+            if !seen.insert(r.id) {
+                r.id = next;
+                seen.insert(next);
+                next += 1;
+            }
+        }
+        self.issues.replace(issues);
+        Ok(parts)
+    }
+
+    /// Where a new file for `category` goes: its folded name with anything but letters,
+    /// digits, `-` and `_` replaced, and subcategories joined with `-`.
+    fn path_for(&self, category: &str) -> PathBuf {
+        let key = category::key(category);
+        let name: String = key
+            .chars()
+            .map(|c| match c {
+                '/' => '-',
+                c if c.is_alphanumeric() || c == '-' || c == '_' => c,
+                _ => '_',
+            })
+            .collect();
+        self.dir.join(format!("{}.csv", if name.is_empty() { UNCATEGORIZED } else { &name }))
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(String::new, |n| n.to_string_lossy().into_owned())
+}
+
+impl PriceStore for DirStore {
+    fn all(&self) -> Result<Vec<Row>> {
+        let mut rows: Vec<Row> = self.read()?.into_iter().flat_map(|p| p.rows).collect();
+        rows.sort_by_key(|r| r.id);
+        Ok(rows)
+    }
+
+    fn append(&self, rows: &[Row]) -> Result<()> {
+        let mut all = self.all()?;
+        all.extend(rows.iter().map(|r| Row { id: 0, ..r.clone() }));
+        self.overwrite(&all)
+    }
+
+    /// Sort `rows` into files and rewrite the files whose rows differ from what they hold, or
+    /// that are in an older layout. A file left without rows is removed.
+    fn overwrite(&self, rows: &[Row]) -> Result<()> {
+        let parts = self.read()?;
+        let mut home: HashMap<u64, (&Path, String)> = HashMap::new();
+        let mut by_category: HashMap<String, PathBuf> = HashMap::new();
+        for part in &parts {
+            for r in &part.rows {
+                home.insert(r.id, (&part.path, category::key(&r.category)));
+                by_category.entry(category::key(&r.category)).or_insert_with(|| part.path.clone());
+            }
+        }
+        let mut grouped: BTreeMap<PathBuf, Vec<Row>> = parts.iter().map(|p| (p.path.clone(), Vec::new())).collect();
+        let rows = with_ids(rows.to_vec(), ids::read(&self.db)? + 1);
+        let highest = ids::highest(&rows);
+        for r in rows {
+            let key = category::key(&r.category);
+            let path = match home.get(&r.id) {
+                Some((path, old)) if *old == key => path.to_path_buf(),
+                _ => by_category.entry(key).or_insert_with(|| self.path_for(&r.category)).clone(),
+            };
+            grouped.entry(path).or_default().push(r);
+        }
+        for (path, rows) in grouped {
+            let part = parts.iter().find(|p| p.path == path);
+            let unchanged = part.is_some_and(|p| {
+                p.outdated.is_none() && p.rows.len() == rows.len() && p.rows.iter().zip(&rows).all(|(a, b)| record(a, a.id) == record(b, b.id))
+            });
+            if unchanged {
+                continue;
+            }
+            let name = path.to_string_lossy();
+            if rows.is_empty() && part.is_some_and(|p| !p.rows.is_empty()) {
+                fs::remove_file(&path).with_context(|| format!("Remove {}", name))?;
+                info!("removed {}, which has no entries left", name);
+            } else {
+                csvfile::write_rows(&name, &rows, None)?;
+            }
+        }
+        ids::raise(&self.db, highest)
+    }
+
+    fn issues(&self) -> Vec<Issue> {
+        self.issues.borrow().clone()
+    }
+
+    fn outdated(&self) -> Result<Option<String>> {
+        Ok(self.read()?.into_iter().find_map(|p| p.outdated.map(|l| format!("{} ({})", l, file_name(&p.path)))))
+    }
+}
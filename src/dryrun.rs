@@ -0,0 +1,111 @@
+//! `--dry-run`: a store that reports the changes a command would make instead of making them.
+
+use anyhow::Result;
+
+use pricepeek::filter::Filters;
+use pricepeek::row::{record, Issue};
+use pricepeek::{Changes, PriceStore, Row};
+
+use crate::config::Config;
+use crate::format_row;
+
+/// Passes reads through and prints every change as "would add", "would delete" or "would
+/// rewrite" lines and a summary, leaving `inner` untouched. It goes around every other store,
+/// so nothing below it, such as backups or the journal, sees the change either.
+pub struct DryRunStore<'a> {
+    inner: &'a dyn PriceStore,
+    cfg: &'a Config,
+}
+
+impl<'a> DryRunStore<'a> {
+    pub fn new(inner: &'a dyn PriceStore, cfg: &'a Config) -> Self {
+        DryRunStore { inner, cfg }
+    }
+
+    /// Print what saving `rows` as the new contents would do; `changes` lists the removed and
+    /// rewritten rows, and rows of `rows` without an id are new.
+    fn report(&self, rows: &[Row], changes: &Changes) {
+        for r in &changes.removed {
+            println!("would delete: {}", format_row(r, self.cfg));
+        }
+        for (old, new) in &changes.updated {
+            println!("would rewrite: {}", format_row(old, self.cfg));
+            println!("           to: {}", format_row(new, self.cfg));
+        }
+        let added: Vec<&Row> = rows.iter().filter(|r| r.id == 0).collect();
+        for r in &added {
+            println!("would add: {}", format_row(r, self.cfg));
+        }
+        let counts = [("add", added.len()), ("delete", changes.removed.len()), ("rewrite", changes.updated.len())];
+        let parts: Vec<String> = counts.iter().filter(|(_, n)| *n > 0).map(|(verb, n)| format!("{} {} rows", verb, n)).collect();
+        let unchanged = rows.len() - added.len() - changes.updated.len();
+        if parts.is_empty() {
+            println!("would change nothing, keep {} rows", unchanged);
+        } else {
+            println!("would {}, keep {} unchanged", parts.join(", "), unchanged);
+        }
+    }
+}
+
+impl PriceStore for DryRunStore<'_> {
+    fn all(&self) -> Result<Vec<Row>> {
+        self.inner.all()
+    }
+
+    fn filter(&self, filters: &Filters) -> Result<Vec<Row>> {
+        self.inner.filter(filters)
+    }
+
+    fn stream(&self) -> Result<Box<dyn Iterator<Item = Result<Row>> + '_>> {
+        self.inner.stream()
+    }
+
+    fn append(&self, rows: &[Row]) -> Result<()> {
+        let kept = self.inner.all()?;
+        let added: Vec<Row> = rows.iter().map(|r| Row { id: 0, ..r.clone() }).collect();
+        self.report(&[kept, added].concat(), &Changes::default());
+        Ok(())
+    }
+
+    fn delete(&self, ids: &[u64]) -> Result<usize> {
+        let (removed, kept): (Vec<Row>, Vec<Row>) = self.inner.all()?.into_iter().partition(|r| ids.contains(&r.id));
+        let count = removed.len();
+        self.report(&kept, &Changes { removed, ..Default::default() });
+        Ok(count)
+    }
+
+    /// Reported against the current rows: those whose id is gone are deleted, those that read
+    /// differently rewritten, and those with an id not stored yet added.
+    fn overwrite(&self, rows: &[Row]) -> Result<()> {
+        let current = self.inner.all()?;
+        let mut changes = Changes::default();
+        let mut rows = rows.to_vec();
+        for r in rows.iter_mut() {
+            match current.iter().find(|c| c.id == r.id) {
+                Some(c) if record(c, c.id) != record(r, r.id) => changes.updated.push((c.clone(), r.clone())),
+                Some(_) => {}
+                None => r.id = 0,
+            }
+        }
+        changes.removed = current.into_iter().filter(|c| !rows.iter().any(|r| r.id == c.id)).collect();
+        self.report(&rows, &changes);
+        Ok(())
+    }
+
+    fn issues(&self) -> Vec<Issue> {
+        self.inner.issues()
+    }
+
+    fn outdated(&self) -> Result<Option<String>> {
+        self.inner.outdated()
+    }
+
+    fn highest_id(&self) -> Result<Option<u64>> {
+        self.inner.highest_id()
+    }
+
+    fn commit(&self, rows: &[Row], changes: &Changes) -> Result<()> {
+        self.report(rows, changes);
+        Ok(())
+    }
+}
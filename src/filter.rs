@@ -0,0 +1,260 @@
+//! The row filters every listing command accepts, and the date and price parsing they share.
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use clap::Args;
+
+use crate::category::CategoryFilter;
+use crate::money::Money;
+use crate::row::{normalize_domain, url_domain, Row, Status};
+use crate::text::matches_ignore_case;
+use crate::Failure;
+
+/// Row filters shared by the listing, cheapest and export commands. Archived rows are
+/// hidden unless asked for, so every path built on this treats them the same way.
+#[derive(Args, Clone, Default)]
+pub struct Filters {
+    /// Only entries in these comma-separated categories or their subcategories (`food` includes
+    /// `food/snacks`); a leading `!` leaves a category out, e.g. `groceries,household` or `!misc`
+    #[arg(long, value_parser = CategoryFilter::parse)]
+    pub category: Option<CategoryFilter>,
+    /// Match --category exactly, without subcategories
+    #[arg(long, requires = "category")]
+    pub exact_category: bool,
+    /// Only entries from this store
+    #[arg(long)]
+    pub store: Option<String>,
+    /// Only entries whose URL is on this domain, e.g. amazon.de (`www.` is ignored)
+    #[arg(long)]
+    pub domain: Option<String>,
+    /// Only entries carrying this tag
+    #[arg(long)]
+    pub tag: Option<String>,
+    /// Only entries with this availability
+    #[arg(long, value_enum)]
+    pub status: Option<Status>,
+    /// Only entries from this source, e.g. "manual" or "import" (matches any "import:<file>")
+    #[arg(long)]
+    pub source: Option<String>,
+    /// Only entries whose custom field matches, e.g. extra.size=XL; repeat to require several
+    #[arg(long = "where", value_name = "extra.KEY=VALUE", value_parser = parse_where)]
+    pub conditions: Vec<(String, String)>,
+    /// Only entries priced at least this much
+    #[arg(long, value_parser = parse_price, allow_negative_numbers = true)]
+    pub min_price: Option<Money>,
+    /// Only entries priced at most this much
+    #[arg(long, value_parser = parse_price, allow_negative_numbers = true)]
+    pub max_price: Option<Money>,
+    /// Only entries recorded at or after this time, e.g. 2024-05-01 or 7d (seven days ago)
+    #[arg(long, value_parser = parse_date)]
+    pub since: Option<DateTime<FixedOffset>>,
+    /// Only entries recorded before this time; a bare date includes that whole day
+    #[arg(long, value_parser = parse_until)]
+    pub until: Option<DateTime<FixedOffset>>,
+    /// Only archived entries
+    #[arg(long, conflicts_with = "all")]
+    pub archived: bool,
+    /// Include archived entries
+    #[arg(long)]
+    pub all: bool,
+}
+
+impl Filters {
+    pub fn matches(&self, r: &Row) -> bool {
+        self.matches_fields(r) && self.in_date_range(r) == Some(true)
+    }
+
+    /// Keep the matching rows. Rows whose timestamp can't be read never pass a date filter;
+    /// how many were left out for that reason is reported on stderr.
+    pub fn apply(&self, rows: Vec<Row>) -> Vec<Row> {
+        let mut undated = 0;
+        let kept = rows.into_iter().filter(|r| self.keep(r, &mut undated)).collect();
+        warn_undated(undated);
+        kept
+    }
+
+    /// The matching rows of `rows` as they are read, like `apply` without holding them all.
+    /// Call `Matching::finish` once done to report rows left out for their timestamp.
+    pub fn stream<I: Iterator<Item = Result<Row>>>(&self, rows: I) -> Matching<'_, I> {
+        Matching { filters: self, rows, undated: 0 }
+    }
+
+    /// Whether `r` matches, counting it in `undated` when only its timestamp is in the way.
+    fn keep(&self, r: &Row, undated: &mut usize) -> bool {
+        self.matches_fields(r)
+            && self.in_date_range(r).unwrap_or_else(|| {
+                *undated += 1;
+                false
+            })
+    }
+
+    pub fn has_dates(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+
+    /// `None` when a date filter is set but the row's timestamp does not parse.
+    fn in_date_range(&self, r: &Row) -> Option<bool> {
+        if !self.has_dates() {
+            return Some(true);
+        }
+        let t = DateTime::parse_from_rfc3339(&r.timestamp).ok()?;
+        Some(self.since.is_none_or(|s| t >= s) && self.until.is_none_or(|u| t < u))
+    }
+
+    fn matches_fields(&self, r: &Row) -> bool {
+        let eq = |want: &Option<String>, have: &str| want.as_deref().is_none_or(|w| matches_ignore_case(have, w));
+        self.category.as_ref().is_none_or(|c| c.matches(&r.category, self.exact_category))
+            && eq(&self.store, &r.store)
+            && self.domain.as_deref().is_none_or(|d| url_domain(&r.url).is_some_and(|have| have == normalize_domain(d)))
+            && self.tag.as_deref().is_none_or(|t| r.tags.iter().any(|have| matches_ignore_case(have, t)))
+            && self.status.is_none_or(|s| r.status == s)
+            && self.source.as_deref().is_none_or(|s| source_matches(&r.source, s))
+            && (self.all || r.archived == self.archived)
+            && self.conditions.iter().all(|(k, v)| r.extra(k).is_some_and(|have| matches_ignore_case(have, v)))
+            && self.min_price.is_none_or(|p| r.price >= p)
+            && self.max_price.is_none_or(|p| r.price <= p)
+    }
+}
+
+/// The rows of a stream that `Filters::stream` keeps. Read errors are passed through.
+pub struct Matching<'a, I> {
+    filters: &'a Filters,
+    rows: I,
+    undated: usize,
+}
+
+impl<I> Matching<'_, I> {
+    /// Report on stderr how many rows were left out because their timestamp can't be read.
+    pub fn finish(&self) {
+        warn_undated(self.undated);
+    }
+}
+
+impl<I: Iterator<Item = Result<Row>>> Iterator for Matching<'_, I> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        loop {
+            match self.rows.next()? {
+                Ok(r) if !self.filters.keep(&r, &mut self.undated) => continue,
+                item => return Some(item),
+            }
+        }
+    }
+}
+
+fn warn_undated(undated: usize) {
+    if undated > 0 {
+        eprintln!("warning: left out {} entries whose timestamp could not be read", undated);
+    }
+}
+
+/// `KEY=VALUE` for `add --extra`. Keys are limited to letters, digits and `_`, and values may
+/// not contain `;`, which separates pairs in the CSV field.
+pub fn parse_extra(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, got '{}'", s))?;
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("key '{}' may only contain letters, digits and '_'", key));
+    }
+    if value.contains(';') {
+        return Err(format!("value '{}' must not contain ';'", value));
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
+/// `extra.KEY=VALUE` for `--where`.
+fn parse_where(s: &str) -> Result<(String, String), String> {
+    let rest = s.strip_prefix("extra.").ok_or_else(|| format!("expected extra.KEY=VALUE, got '{}'", s))?;
+    parse_extra(rest)
+}
+
+/// `want` matches the whole source or its kind, so "import" matches "import:prices.csv".
+fn source_matches(have: &str, want: &str) -> bool {
+    matches_ignore_case(have, want) || have.split(':').next().is_some_and(|kind| matches_ignore_case(kind, want))
+}
+
+pub fn parse_price(s: &str) -> Result<Money> {
+    s.parse().map_err(|e| Failure::InvalidInput(format!("Invalid price '{}': {}", s.trim(), e)).into())
+}
+
+/// A date or date and time as people type them: RFC 3339, `2026-10-15`, `2026/10/15`,
+/// `15.10.2026`, `2026-10-15 14:30[:00]`, `now`, `today`, `yesterday`, or an age such as `7d`,
+/// `2w` or `12h`. Without an offset the local time zone applies and a bare date means midnight.
+pub fn parse_date(s: &str) -> Result<DateTime<FixedOffset>> {
+    let s = s.trim();
+    if let Ok(d) = DateTime::parse_from_rfc3339(s) {
+        return Ok(d);
+    }
+    let now = Local::now().fixed_offset();
+    if s.eq_ignore_ascii_case("now") {
+        return Ok(now);
+    }
+    if let Some(age) = parse_age(s) {
+        return now.checked_sub_signed(age).ok_or_else(|| Failure::InvalidInput(format!("Date '{}' is out of range", s)).into());
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+        .or_else(|| parse_day(s).map(|d| d.and_time(NaiveTime::MIN)))
+        .and_then(|n| Local.from_local_datetime(&n).earliest())
+        .map(|d| d.fixed_offset())
+        .ok_or_else(|| Failure::InvalidInput(format!("Invalid date '{}', expected e.g. 2026-10-15, 2026-10-15 14:30 or 7d", s)).into())
+}
+
+/// The end of a date range: a bare date such as `2026-10-15` covers that whole day.
+pub fn parse_until(s: &str) -> Result<DateTime<FixedOffset>> {
+    match parse_day(s.trim()).and_then(|d| d.succ_opt()) {
+        Some(next) => Local
+            .from_local_datetime(&next.and_time(NaiveTime::MIN))
+            .earliest()
+            .map(|d| d.fixed_offset())
+            .ok_or_else(|| Failure::InvalidInput(format!("Invalid date '{}'", s.trim())).into()),
+        None => parse_date(s),
+    }
+}
+
+/// A calendar day without a time: `today`, `yesterday` or one of the date-only formats.
+fn parse_day(s: &str) -> Option<NaiveDate> {
+    let today = Local::now().date_naive();
+    match s.to_lowercase().as_str() {
+        "today" => Some(today),
+        "yesterday" => today.pred_opt(),
+        _ => ["%Y-%m-%d", "%Y/%m/%d", "%d.%m.%Y"].iter().find_map(|f| NaiveDate::parse_from_str(s, f).ok()),
+    }
+}
+
+/// `7d`, `2w` or `12h` as a duration.
+fn parse_age(s: &str) -> Option<chrono::Duration> {
+    let unit = s.chars().last()?;
+    let n: i64 = s[..s.len() - unit.len_utf8()].parse().ok().filter(|n| *n >= 0)?;
+    match unit.to_ascii_lowercase() {
+        'h' => chrono::Duration::try_hours(n),
+        'd' => chrono::Duration::try_days(n),
+        'w' => chrono::Duration::try_weeks(n),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages_take_hours_days_or_weeks_in_either_case() {
+        assert_eq!(parse_age("12h"), chrono::Duration::try_hours(12));
+        assert_eq!(parse_age("7d"), chrono::Duration::try_days(7));
+        assert_eq!(parse_age("2W"), chrono::Duration::try_weeks(2));
+        for bad in ["", "d", "-1d", "7", "7y", "7é", "é", "7日"] {
+            assert_eq!(parse_age(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn a_date_ending_in_a_multibyte_character_is_invalid_input() {
+        for s in ["7é", "é", "2026-10-1５"] {
+            let err = parse_date(s).unwrap_err();
+            assert!(matches!(err.downcast_ref::<Failure>(), Some(Failure::InvalidInput(_))), "{}: {}", s, err);
+        }
+    }
+}
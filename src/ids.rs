@@ -0,0 +1,63 @@
+//! The `<database>.ids` file, which records the highest id ever given out so that new rows
+//! never get the id of a deleted one. Without it, as for databases written before it existed,
+//! new ids continue after the highest one in use. For CSV files it also records the length of
+//! the file after the write, which tells appends that nothing else has changed it since.
+
+use std::fs;
+use std::io::ErrorKind;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+/// Where the highest id given out for `db` is recorded.
+pub fn path(db: &str) -> String {
+    format!("{}.ids", db)
+}
+
+/// The highest id given out for `db`, or 0 when none was recorded.
+pub fn read(db: &str) -> Result<u64> {
+    Ok(read_with_length(db)?.0)
+}
+
+/// The highest id given out for `db` and, when recorded with it, the length of the file.
+pub fn read_with_length(db: &str) -> Result<(u64, Option<u64>)> {
+    let path = path(db);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((0, None)),
+        Err(e) => return Err(e).with_context(|| format!("Read {}", path)),
+    };
+    let mut fields = text.split_whitespace().map(str::parse::<u64>);
+    match (fields.next(), fields.next()) {
+        (Some(Ok(high)), None) => Ok((high, None)),
+        (Some(Ok(high)), Some(Ok(length))) => Ok((high, Some(length))),
+        _ => {
+            warn!("{} doesn't hold an id, ignored", path);
+            Ok((0, None))
+        }
+    }
+}
+
+/// Record `high` as the highest id given out for `db`, unless a higher one is recorded.
+pub fn raise(db: &str, high: u64) -> Result<()> {
+    if high <= read(db)? {
+        return Ok(());
+    }
+    let path = path(db);
+    fs::write(&path, format!("{}\n", high)).with_context(|| format!("Write {}", path))?;
+    info!("recorded {} as the highest id in {}", high, path);
+    Ok(())
+}
+
+/// Record `high`, or the higher id already recorded, for the CSV file `db`, which is `length`
+/// bytes long now that it has been written.
+pub fn record(db: &str, high: u64, length: u64) -> Result<()> {
+    let high = high.max(read(db)?);
+    let path = path(db);
+    fs::write(&path, format!("{} {}\n", high, length)).with_context(|| format!("Write {}", path))
+}
+
+/// The highest id in `rows`, or 0 for none.
+pub fn highest(rows: &[crate::Row]) -> u64 {
+    rows.iter().map(|r| r.id).max().unwrap_or(0)
+}
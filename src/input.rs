@@ -0,0 +1,128 @@
+//! Interactive prompts: line editing, history and completion on a terminal,
+//! plain `read_line` when stdin is piped.
+
+use std::io::{self, IsTerminal, Write};
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Context, Editor, Helper, Highlighter, Hinter, Validator};
+
+/// Tab-completes whole answers from values already in the database.
+#[derive(Default, Helper, Hinter, Highlighter, Validator)]
+struct KnownValues {
+    values: Vec<String>,
+}
+
+impl Completer for KnownValues {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, _pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = line.to_lowercase();
+        let matches = self
+            .values
+            .iter()
+            .filter(|v| v.to_lowercase().starts_with(&prefix))
+            .cloned()
+            .collect();
+        Ok((0, matches))
+    }
+}
+
+pub struct Input {
+    editor: Option<Editor<KnownValues, DefaultHistory>>,
+}
+
+impl Input {
+    /// Uses line editing when stdin is a terminal. `known` seeds both history and completion.
+    pub fn new(known: Vec<String>) -> Self {
+        let editor = if io::stdin().is_terminal() {
+            Editor::new().ok().map(|mut ed: Editor<KnownValues, DefaultHistory>| {
+                for v in &known {
+                    let _ = ed.add_history_entry(v.as_str());
+                }
+                ed.set_helper(Some(KnownValues { values: known }));
+                ed
+            })
+        } else {
+            None
+        };
+        Input { editor }
+    }
+
+    /// Make `value` available to history and completion for later prompts.
+    pub fn remember(&mut self, value: &str) {
+        if value.is_empty() {
+            return;
+        }
+        if let Some(ed) = &mut self.editor {
+            if let Some(helper) = ed.helper_mut() {
+                if !helper.values.iter().any(|v| v == value) {
+                    helper.values.push(value.to_string());
+                }
+            }
+        }
+    }
+
+    /// Read one trimmed line. Ctrl-C yields `ErrorKind::Interrupted`, end of input `UnexpectedEof`.
+    pub fn line(&mut self, prompt: &str) -> io::Result<String> {
+        let Some(ed) = &mut self.editor else {
+            return read_plain(prompt);
+        };
+        match ed.readline(prompt) {
+            Ok(line) => {
+                let line = line.trim().to_string();
+                if !line.is_empty() {
+                    let _ = ed.add_history_entry(line.as_str());
+                }
+                Ok(line)
+            }
+            Err(ReadlineError::Interrupted) => Err(io::ErrorKind::Interrupted.into()),
+            Err(ReadlineError::Eof) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Err(ReadlineError::Io(e)) => Err(e),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    /// Prompt showing `default` in brackets; an empty answer returns the default.
+    pub fn with_default(&mut self, label: &str, default: &str) -> io::Result<String> {
+        if default.is_empty() {
+            return self.line(&format!("{}: ", label));
+        }
+        let answer = self.line(&format!("{} [{}]: ", label, default))?;
+        Ok(if answer.is_empty() { default.to_string() } else { answer })
+    }
+
+    /// Ask until `check` accepts the answer, printing why each rejected one was refused.
+    pub fn valid<T>(&mut self, label: &str, default: &str, check: impl Fn(&str) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        loop {
+            let answer = self.with_default(label, default)?;
+            match check(&answer) {
+                Ok(v) => return Ok(v),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    pub fn confirm(&mut self, prompt: &str) -> io::Result<bool> {
+        let answer = self.line(prompt)?;
+        Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+    }
+}
+
+fn read_plain(prompt: &str) -> io::Result<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut buf = String::new();
+    if io::stdin().read_line(&mut buf)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf.trim().to_string())
+}
+
+/// Whether `err` came from the user pressing Ctrl-C or closing input at a prompt.
+pub fn cancel_kind(err: &anyhow::Error) -> Option<io::ErrorKind> {
+    err.downcast_ref::<io::Error>()
+        .map(|e| e.kind())
+        .filter(|k| matches!(k, io::ErrorKind::Interrupted | io::ErrorKind::UnexpectedEof))
+}
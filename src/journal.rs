@@ -0,0 +1,185 @@
+//! The journal of changes, one JSON line per write in `<name>.log.jsonl` next to the database
+//! (`prices.log.jsonl` for `prices.csv`), so it can be told later when and how the data
+//! changed. Lines are only ever added.
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::filter::Filters;
+use crate::row::{Issue, Row};
+use crate::store::{Changes, PriceStore};
+
+/// Product names a description lists before it only counts the rest.
+const NAMES: usize = 5;
+
+/// One change to the database.
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// RFC 3339, UTC, like row timestamps.
+    pub time: String,
+    /// The command that made the change, e.g. `edit` or `menu add`.
+    pub op: String,
+    /// Entries added, removed or rewritten.
+    pub rows: usize,
+    pub description: String,
+}
+
+/// The journal of `db`: its name with the extension swapped for `.log.jsonl`.
+pub fn path(db: &str) -> String {
+    Path::new(db.trim_end_matches(std::path::is_separator)).with_extension("log.jsonl").to_string_lossy().into_owned()
+}
+
+/// Every entry of the journal of `db`, oldest first; none when there is no journal. Lines
+/// that don't read, such as one cut short by a crash, are left out.
+pub fn read(db: &str) -> Result<Vec<Entry>> {
+    let path = path(db);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Read {}", path)),
+    };
+    Ok(text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                debug!("{} line {}: {}", path, i + 1, e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Add one line for a change to the journal of `db`.
+fn record(db: &str, op: &str, rows: usize, description: String) -> Result<()> {
+    let path = path(db);
+    let entry = Entry { time: Utc::now().to_rfc3339(), op: op.to_string(), rows, description };
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(&path).with_context(|| format!("Open {}", path))?;
+    file.write_all(line.as_bytes()).with_context(|| format!("Write {}", path))
+}
+
+/// `verb` and the distinct product names of `rows`, the first few of them: "added Milk, Bread
+/// and 3 more".
+fn describe<'r>(verb: &str, rows: impl Iterator<Item = &'r Row>) -> Option<String> {
+    let mut names: Vec<&str> = Vec::new();
+    for r in rows {
+        if !names.contains(&r.product.as_str()) {
+            names.push(&r.product);
+        }
+    }
+    match names.len() {
+        0 => None,
+        n if n > NAMES => Some(format!("{} {} and {} more", verb, names[..NAMES].join(", "), n - NAMES)),
+        _ => Some(format!("{} {}", verb, names.join(", "))),
+    }
+}
+
+/// A store that adds a journal line for every write that succeeded. A journal that can't be
+/// written is warned about; the change itself stands.
+pub struct JournalStore<'a> {
+    inner: &'a dyn PriceStore,
+    db: &'a str,
+    op: &'a str,
+}
+
+impl<'a> JournalStore<'a> {
+    /// Journal the writes `op` makes to `inner`, the database at `db`.
+    pub fn new(inner: &'a dyn PriceStore, db: &'a str, op: &'a str) -> Self {
+        JournalStore { inner, db, op }
+    }
+
+    fn record(&self, rows: usize, description: String) {
+        if let Err(e) = record(self.db, self.op, rows, description) {
+            eprintln!("warning: the change was saved but not journaled: {:#}", e);
+        }
+    }
+}
+
+impl PriceStore for JournalStore<'_> {
+    fn all(&self) -> Result<Vec<Row>> {
+        self.inner.all()
+    }
+
+    fn filter(&self, filters: &Filters) -> Result<Vec<Row>> {
+        self.inner.filter(filters)
+    }
+
+    fn stream(&self) -> Result<Box<dyn Iterator<Item = Result<Row>> + '_>> {
+        self.inner.stream()
+    }
+
+    fn append(&self, rows: &[Row]) -> Result<()> {
+        self.inner.append(rows)?;
+        self.record(rows.len(), describe("added", rows.iter()).unwrap_or_default());
+        Ok(())
+    }
+
+    fn delete(&self, ids: &[u64]) -> Result<usize> {
+        let removed = self.inner.delete(ids)?;
+        if removed > 0 {
+            let ids: Vec<String> = ids.iter().map(|id| format!("#{}", id)).collect();
+            self.record(removed, format!("deleted {}", ids.join(", ")));
+        }
+        Ok(removed)
+    }
+
+    fn overwrite(&self, rows: &[Row]) -> Result<()> {
+        self.inner.overwrite(rows)?;
+        self.record(rows.len(), format!("replaced the contents with {} entries", rows.len()));
+        Ok(())
+    }
+
+    fn issues(&self) -> Vec<Issue> {
+        self.inner.issues()
+    }
+
+    fn outdated(&self) -> Result<Option<String>> {
+        self.inner.outdated()
+    }
+
+    fn highest_id(&self) -> Result<Option<u64>> {
+        self.inner.highest_id()
+    }
+
+    fn commit(&self, rows: &[Row], changes: &Changes) -> Result<()> {
+        self.inner.commit(rows, changes)?;
+        let added: Vec<&Row> = rows.iter().filter(|r| r.id == 0).collect();
+        let parts: Vec<String> = [
+            describe("changed", changes.updated.iter().map(|(_, new)| new)),
+            describe("deleted", changes.removed.iter()),
+            describe("added", added.iter().copied()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let count = changes.updated.len() + changes.removed.len() + added.len();
+        if count > 0 {
+            self.record(count, parts.join("; "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_journal_takes_the_database_name_without_its_extension() {
+        assert_eq!(path("prices.csv"), "prices.log.jsonl");
+        assert_eq!(path("data/prices.sqlite"), "data/prices.log.jsonl");
+        assert_eq!(path("data/prices.csv.gz"), "data/prices.csv.log.jsonl");
+        assert_eq!(path("data/prices/"), "data/prices.log.jsonl");
+        assert_eq!(path("prices"), "prices.log.jsonl");
+    }
+}
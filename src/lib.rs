@@ -0,0 +1,53 @@
+//! PricePeek's data layer: price rows, the filters listings apply, the calculations behind the
+//! reports, and the stores that keep them, a CSV file, a directory of them, an SQLite database or
+//! plain memory. The `pricepeek` binary adds the command line and the menu on top; other tools can
+//! embed the same logic.
+
+pub mod adjust;
+pub mod aliases;
+pub mod backup;
+pub mod cache;
+pub mod category;
+pub mod checksum;
+pub mod crypt;
+pub mod csvfile;
+pub mod dedupe;
+pub mod dirstore;
+pub mod filter;
+pub mod ids;
+pub mod journal;
+pub mod lock;
+pub mod money;
+pub mod report;
+pub mod row;
+pub mod search;
+pub mod select;
+pub mod sqlite;
+pub mod store;
+pub mod text;
+pub mod validate;
+
+pub use aliases::Aliases;
+pub use csvfile::CsvStore;
+pub use filter::Filters;
+pub use money::Money;
+pub use row::{Row, Status};
+pub use sqlite::SqliteStore;
+pub use store::{Backend, Changes, MemoryStore, PriceStore};
+
+/// Failures that map to a specific exit code; anything else is treated as I/O.
+#[derive(Debug)]
+pub enum Failure {
+    NoMatch(String),
+    InvalidInput(String),
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::NoMatch(msg) | Failure::InvalidInput(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
@@ -0,0 +1,98 @@
+//! Advisory locking of the database against other pricepeek processes, through a `.lock` file
+//! next to it. The database itself is replaced on every rewrite, so it can't carry the lock.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+
+/// How long to wait for another process to finish before giving up.
+const WAIT: Duration = Duration::from_secs(5);
+const POLL: Duration = Duration::from_millis(50);
+
+/// Held until dropped.
+pub struct DbLock {
+    _file: File,
+}
+
+/// For commands that change the database: no other process may read or write meanwhile.
+pub fn exclusive(db: &str) -> Result<DbLock> {
+    acquire(db, File::try_lock)
+}
+
+/// For commands that only read: other readers may hold it too, writers wait.
+pub fn shared(db: &str) -> Result<DbLock> {
+    acquire(db, File::try_lock_shared)
+}
+
+fn acquire(db: &str, try_lock: fn(&File) -> Result<(), TryLockError>) -> Result<DbLock> {
+    let path = format!("{}.lock", db);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Open {}", path))?;
+    let start = Instant::now();
+    loop {
+        match try_lock(&file) {
+            Ok(()) => return Ok(DbLock { _file: file }),
+            Err(TryLockError::WouldBlock) if start.elapsed() < WAIT => thread::sleep(POLL),
+            Err(TryLockError::WouldBlock) => {
+                return Err(anyhow!("Database {} is locked by another process; try again when it has finished", db))
+            }
+            Err(TryLockError::Error(e)) => return Err(e).with_context(|| format!("Lock {}", path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csvfile::CsvStore;
+    use crate::store::PriceStore;
+    use crate::Row;
+
+    #[test]
+    fn writers_holding_the_lock_lose_no_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let writers: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for i in 0..20 {
+                        let _lock = exclusive(&db).unwrap();
+                        // Read, then rewrite everything: without the lock the other writer's
+                        // row could land in between and be overwritten.
+                        let store = CsvStore::open(&db).unwrap();
+                        let mut rows = store.all().unwrap();
+                        rows.push(Row { product: format!("{}{}", name, i), ..Default::default() });
+                        store.overwrite(&rows).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        let rows = CsvStore::open(&db).unwrap().all().unwrap();
+        assert_eq!(rows.len(), 40);
+        for name in ["a0", "a19", "b0", "b19"] {
+            assert!(rows.iter().any(|r| r.product == name), "{} is missing", name);
+        }
+    }
+
+    #[test]
+    fn a_second_writer_gets_an_error_instead_of_waiting_forever() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("prices.csv").to_string_lossy().into_owned();
+        let _held = exclusive(&db).unwrap();
+        let start = Instant::now();
+        let err = exclusive(&db).err().expect("the lock is held");
+        assert!(err.to_string().contains("is locked by another process"), "{}", err);
+        assert!(start.elapsed() >= WAIT && start.elapsed() < WAIT * 2);
+    }
+}
@@ -1,232 +1,4009 @@
 use anyhow::{Context, Result};
-use chrono::Utc;
-use std::io::{self, Write};
-use std::path::Path;
+use chrono::{DateTime, Datelike, FixedOffset, Local, Utc};
+use log::info;
+use clap::{ArgAction, ArgGroup, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-const HEADER: [&str; 5] = ["product", "category", "price", "url", "timestamp"];
+mod basket;
+mod chart;
+mod config;
+mod dryrun;
+mod input;
+mod menu;
+mod pager;
+mod table;
+mod watch;
 
-#[derive(Debug, Clone)]
-struct Row {
-    product: String,
-    category: String,
-    price: f64,
-    url: String,
-    timestamp: String,
+use pricepeek::{adjust, aliases, backup, cache, checksum, crypt, journal, category, lock, money, search, select, store, text, validate};
+use pricepeek::adjust::{Adjustment, Skip};
+use pricepeek::dedupe::{dedupe_groups, find_dupes, removals, DedupeStrategy, DupeKey};
+use pricepeek::report::{
+    self, cheapest, cheapest_by, compare_offers, compare_timestamps, currency_of, group_by, group_history, in_stock,
+    latest_observations, not_purchased, product_identity, product_rows,
+};
+use pricepeek::filter::{parse_date, parse_extra, parse_price};
+use pricepeek::row::{parse_tags, record, url_domain, HEADER, SOURCE_MANUAL};
+use pricepeek::csvfile::{self, Output};
+use pricepeek::{Changes, CsvStore, Failure, Filters, Row, Status};
+
+use aliases::Aliases;
+use backup::{BackupStore, Backups};
+use basket::Basket;
+use category::CategoryFilter;
+use config::Config;
+use crypt::Cipher;
+use dryrun::DryRunStore;
+use input::Input;
+use journal::JournalStore;
+use money::Money;
+use search::{Matcher, SearchField};
+use store::{Backend, PriceStore};
+use table::TableOpts;
+use text::matches_ignore_case;
+use validate::UrlCheck;
+
+const LEGACY_DB: &str = "prices.csv";
+/// Most rows `search --fuzzy` and the no-match suggestions show.
+const FUZZY_LIMIT: usize = 5;
+
+#[derive(Parser)]
+#[command(name = "pricepeek", version, about = "Track and compare product prices in a CSV file")]
+struct Cli {
+    /// Path to the price database [default: platform data directory]
+    #[arg(long, global = true, env = "PRICEPEEK_DB")]
+    db: Option<String>,
+
+    /// Storage format of the database [default: dir for directories and paths ending in /, sqlite
+    /// for .sqlite, .sqlite3 and .db files, else csv]
+    #[arg(long, global = true, value_enum)]
+    backend: Option<Backend>,
+
+    /// Print only the requested data; errors still go to stderr
+    #[arg(long, short, global = true)]
+    quiet: bool,
+
+    /// Show what a destructive command would change without writing anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Show times in UTC instead of the local timezone
+    #[arg(long, global = true)]
+    utc: bool,
+
+    /// Keep the database encrypted with a passphrase; files that already are encrypted are
+    /// opened that way without it
+    #[arg(long, global = true)]
+    encrypted: bool,
+
+    /// Only read the database: commands that change it are refused, and nothing is created,
+    /// locked or upgraded
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Wait after each write of the database or an export until the data is on disk, so a
+    /// power loss right after a command can't lose it; every write takes longer, noticeably on
+    /// network and USB drives. SQLite databases are always written this way
+    #[arg(long, global = true)]
+    sync_writes: bool,
+
+    /// Log to stderr: -v for writes, -vv for per-row parsing details
+    #[arg(long, short, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Add a product price
+    Add {
+        #[arg(long, required_unless_present = "stdin")]
+        product: Option<String>,
+        /// Category [default: `default_category` from the config file]
+        #[arg(long)]
+        category: Option<String>,
+        #[arg(long, required_unless_present = "stdin", allow_negative_numbers = true)]
+        price: Option<String>,
+        #[arg(long, default_value = "")]
+        url: String,
+        /// ISO 4217 currency code [default: `default_currency` from the config file]
+        #[arg(long)]
+        currency: Option<String>,
+        /// Store or vendor [default: the URL's host]
+        #[arg(long, default_value = "")]
+        store: String,
+        /// Free-text notes; may span several lines
+        #[arg(long, default_value = "")]
+        notes: String,
+        /// Comma-separated labels, e.g. "gift,electronics"
+        #[arg(long, default_value = "")]
+        tags: String,
+        /// Amount the price buys, e.g. 0.5 for a 500 g pack given with --unit kg
+        #[arg(long)]
+        quantity: Option<String>,
+        /// Unit of --quantity, e.g. g, kg, ml, l or pcs
+        #[arg(long, default_value = "", requires = "quantity")]
+        unit: String,
+        /// Shipping cost on top of the price
+        #[arg(long, default_value = "0")]
+        shipping: String,
+        /// Availability
+        #[arg(long, value_enum, default_value_t = Status::Unknown)]
+        status: Status,
+        /// Regular price when --price is a sale price
+        #[arg(long)]
+        original_price: Option<String>,
+        /// How much you want the item; higher comes first in the wishlist
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        priority: i64,
+        /// Custom field such as size=XL; repeat for more
+        #[arg(long = "extra", value_name = "KEY=VALUE", value_parser = parse_extra)]
+        extras: Vec<(String, String)>,
+        /// Store code or barcode, e.g. the EAN printed under the bars
+        #[arg(long, default_value = "")]
+        sku: String,
+        /// Accept negative prices, e.g. for refunds
+        #[arg(long)]
+        allow_negative: bool,
+        /// Read `product,category,price,url[,currency[,store]]` lines from stdin instead
+        #[arg(long, conflicts_with_all = ["product", "price"])]
+        stdin: bool,
+        /// Remove tracking parameters such as utm_source (see `strip_url_params` in the config file),
+        /// lower-case the host and drop trailing slashes
+        #[arg(long, conflicts_with = "stdin")]
+        normalize_url: bool,
+        /// Reject a price far from the product's usual one instead of asking; without a terminal,
+        /// and with --stdin, the check only runs with this flag
+        #[arg(long)]
+        strict: bool,
+    },
+    /// List all prices
+    List {
+        #[command(flatten)]
+        filters: Filters,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+        /// Shorthand for --format json
+        #[arg(long, conflicts_with = "format")]
+        json: bool,
+        /// Don't truncate long URLs to the terminal width
+        #[arg(long)]
+        full: bool,
+        /// Never pipe long listings through a pager
+        #[arg(long)]
+        no_pager: bool,
+        /// Order of the rows [default: file order]
+        #[arg(long, value_enum)]
+        sort: Option<SortKey>,
+        /// Reverse the --sort order
+        #[arg(long, requires = "sort")]
+        desc: bool,
+        /// Fail when a row could only be read by making up fields, instead of warning
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Show the best price of each product, most wanted first
+    Wishlist,
+    /// Show the cheapest option
+    Cheapest {
+        #[command(flatten)]
+        filters: Filters,
+        /// Compare price per unit among entries with compatible units
+        #[arg(long)]
+        per_unit: bool,
+        /// Show the N cheapest options per currency, with their distance from the cheapest
+        #[arg(long, value_name = "N", default_value_t = 1, conflicts_with = "per_unit",
+              value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        top: usize,
+        /// Only consider the most recent price of each product at each store
+        #[arg(long)]
+        latest_only: bool,
+        /// Also consider entries marked out of stock
+        #[arg(long)]
+        include_oos: bool,
+        /// Also consider entries already marked as purchased
+        #[arg(long)]
+        include_purchased: bool,
+        /// Show the cheapest options separately for each URL domain
+        #[arg(long)]
+        by_domain: bool,
+    },
+    /// Show for each product which store is cheapest and how much the runner-up costs more
+    Compare {
+        #[command(flatten)]
+        filters: Filters,
+        /// Compare by URL domain instead of store
+        #[arg(long)]
+        by_domain: bool,
+    },
+    /// Show the lowest and highest price of each product, how far apart they are and when they were seen
+    Spread {
+        #[command(flatten)]
+        filters: Filters,
+    },
+    /// Compare each product's latest price with the highest it has been, with totals per currency
+    Savings {
+        #[command(flatten)]
+        filters: Filters,
+        /// Only products at least this far below their peak, e.g. 10%
+        #[arg(long, value_parser = parse_percent, default_value = "0")]
+        min_savings: f64,
+    },
+    /// Rank entries by price per unit, converting g to kg and ml to l
+    Value {
+        #[command(flatten)]
+        filters: Filters,
+    },
+    /// List entries whose price was recorded more than N days ago, oldest first
+    Stale {
+        /// Age in days [default: `stale_after_days` from the config file, or 30]
+        #[arg(long)]
+        days: Option<u32>,
+    },
+    /// Show the most recently recorded entries, newest first
+    Recent {
+        /// How many to show; 0 shows all
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        /// Show every entry
+        #[arg(long, conflicts_with = "limit")]
+        all: bool,
+    },
+    /// Export data to CSV
+    Export {
+        /// Output file [default: `default_export_file` from the config file, or export.csv]
+        #[arg(long, short)]
+        output: Option<String>,
+        #[command(flatten)]
+        filters: Filters,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+    },
+    /// Delete entries by number, product name, category, age or search text
+    #[command(group(ArgGroup::new("target").required(true).multiple(true).args(["id", "index", "product", "category", "older_than", "pattern"])))]
+    Delete {
+        /// Entry id, as shown by `list`
+        #[arg(long, conflicts_with_all = ["index", "product", "category", "older_than", "pattern"])]
+        id: Option<u64>,
+        /// Positions in file order, starting at 1: one number, a list such as 1,3,5-8, or all
+        #[arg(long, value_name = "LIST", conflicts_with_all = ["product", "category", "older_than", "pattern"])]
+        index: Option<String>,
+        /// Product name (case-insensitive exact match)
+        #[arg(long)]
+        product: Option<String>,
+        /// Every entry in these categories and their subcategories, as `list --category` selects them
+        #[arg(long, value_parser = CategoryFilter::parse)]
+        category: Option<CategoryFilter>,
+        /// Every entry recorded before this age or date, e.g. 90d or 2024-01-01; entries whose
+        /// timestamp can't be read are kept
+        #[arg(long, value_name = "AGE", value_parser = parse_date)]
+        older_than: Option<DateTime<FixedOffset>>,
+        /// Only entries whose product, category, store or notes contain this text, as `search` finds them
+        #[arg(long = "match", value_name = "QUERY")]
+        pattern: Option<String>,
+        /// Treat --match as a regular expression
+        #[arg(long, requires = "pattern")]
+        regex: bool,
+        /// Delete every matching entry instead of refusing when several match; implied by
+        /// --index, --category and --older-than
+        #[arg(long)]
+        all: bool,
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Delete every entry after saving a timestamped copy of the database; asks you to type "clear"
+    Clear {
+        /// Keep one zero-priced placeholder entry per category
+        #[arg(long)]
+        keep_categories: bool,
+    },
+    /// Upgrade a database written by an older version to the current layout, keeping a copy of
+    /// the old file; or, given DEST, copy every entry into a new database, e.g. from prices.csv
+    /// to prices.sqlite or back, in the format the extension of DEST names
+    Migrate {
+        dest: Option<String>,
+    },
+    /// Encrypt the database with a passphrase, which later runs ask for or take from
+    /// PRICEPEEK_PASSPHRASE
+    Encrypt,
+    /// Store an encrypted database as plain CSV again
+    Decrypt,
+    /// Show the latest changes from the journal kept next to the database, `prices.log.jsonl`
+    /// for `prices.csv`, oldest first
+    Log {
+        /// How many changes to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Keep the listing on screen and redraw it whenever the database changes
+    Watch {
+        #[command(flatten)]
+        filters: Filters,
+    },
+    /// Multiply, shift or replace the price of every matching entry, e.g. after a VAT change
+    #[command(group(ArgGroup::new("operation").required(true).args(["multiply", "add", "set"])))]
+    Adjust {
+        #[command(flatten)]
+        filters: Filters,
+        /// Only entries for this product (case-insensitive, aliases and SKUs resolved)
+        #[arg(long)]
+        product: Option<String>,
+        /// Multiply each price by this factor, e.g. 1.02 for +2%
+        #[arg(long, value_name = "FACTOR", value_parser = parse_factor)]
+        multiply: Option<f64>,
+        /// Add this amount to each price; negative amounts subtract
+        #[arg(long, value_name = "AMOUNT", value_parser = parse_price, allow_negative_numbers = true)]
+        add: Option<Money>,
+        /// Replace each price with this one
+        #[arg(long, value_name = "PRICE", value_parser = parse_price)]
+        set: Option<Money>,
+        /// Also switch the adjusted entries to this currency, e.g. EUR after converting from GBP
+        #[arg(long, value_parser = parse_currency)]
+        currency: Option<String>,
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Set or clear the target price of every entry for a product
+    Target {
+        #[command(subcommand)]
+        action: TargetAction,
+    },
+    /// List entries on sale, biggest discount first
+    Deals,
+    /// List products whose latest price is at or below their target
+    UnderTarget,
+    /// Hide entries from list, cheapest and export while keeping their history
+    Archive {
+        /// Entry ids, as shown by `list`
+        #[arg(required = true)]
+        ids: Vec<u64>,
+    },
+    /// Restore archived entries
+    Unarchive {
+        /// Entry ids, as shown by `list --archived`
+        #[arg(required = true)]
+        ids: Vec<u64>,
+    },
+    /// Change the availability of one entry
+    SetStatus {
+        /// Entry id, as shown by `list`
+        id: u64,
+        #[arg(value_enum)]
+        status: Status,
+    },
+    /// Change the wishlist priority of one entry
+    SetPriority {
+        /// Entry id, as shown by `list`
+        id: u64,
+        #[arg(allow_negative_numbers = true)]
+        priority: i64,
+    },
+    /// Record that you bought an entry, at its recorded price
+    MarkPurchased {
+        /// Entry id, as shown by `list`
+        id: u64,
+        /// When you bought it, e.g. 2026-10-15, "2026-10-15 14:30" or yesterday [default: now]
+        #[arg(long)]
+        date: Option<String>,
+    },
+    /// List what you bought, oldest first, with the total spend per month
+    Purchases,
+    /// Show how a product's price changed over time
+    History {
+        /// Product name (case-insensitive exact match)
+        product: String,
+        /// Keep a separate history per store
+        #[arg(long)]
+        by_store: bool,
+    },
+    /// Show each price change of a product and the overall change from first to latest
+    Trend {
+        /// Product name (case-insensitive exact match)
+        product: String,
+    },
+    /// Draw a product's price history as a one-line chart
+    Chart {
+        /// Product name (case-insensitive exact match)
+        product: String,
+        /// Most characters the chart may use [default: terminal width]
+        #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(2..))]
+        width: Option<usize>,
+        /// Draw with plain ASCII characters [default: when the locale is not UTF-8]
+        #[arg(long)]
+        ascii: bool,
+    },
+    /// Replace the database with a backup; without FILE, pick one of the automatic backups
+    Restore {
+        file: Option<String>,
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Back up the database now, or list its backups
+    Backup {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+    /// Manage alternative names that group with a canonical product name
+    Alias {
+        #[command(subcommand)]
+        action: AliasAction,
+    },
+    /// List every entry with this SKU or barcode, however its product name was typed
+    Lookup {
+        sku: String,
+    },
+    /// Find entries whose product, category, store or notes contain QUERY (case-insensitive)
+    Search {
+        query: String,
+        /// Treat QUERY as a regular expression
+        #[arg(long)]
+        regex: bool,
+        /// Only search this field; repeat for several [default: all four]
+        #[arg(long = "field", value_enum)]
+        fields: Vec<SearchField>,
+        /// Rank product names by similarity instead, tolerating typos
+        #[arg(long, conflicts_with_all = ["regex", "fields"])]
+        fuzzy: bool,
+    },
+    /// Plan a purchase: collect entries and total their cheapest prices
+    Basket {
+        #[command(subcommand)]
+        action: BasketAction,
+    },
+    /// Show every field of one entry, including its notes
+    Show {
+        /// Entry id, as shown by `list`
+        id: u64,
+    },
+    /// Record a new price for a product, copying its latest entry; the old entry is kept
+    Update {
+        product: String,
+        #[arg(allow_negative_numbers = true)]
+        price: String,
+        /// The offer to update when the product is sold at several URLs
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// Trim and collapse whitespace in every text field, optionally re-casing product and
+    /// category names; lists each changed field
+    Tidy {
+        /// Rewrite product names in this case
+        #[arg(long, value_enum, value_name = "CASE")]
+        product_case: Option<NameCase>,
+        /// Rewrite categories in this case
+        #[arg(long, value_enum, value_name = "CASE")]
+        category_case: Option<NameCase>,
+    },
+    /// Remove tracking parameters and trailing slashes from every stored URL
+    NormalizeUrls,
+    /// Change the fields of one entry, each prompt offering the current value
+    #[command(group(ArgGroup::new("entry").required(true).args(["id", "index"])))]
+    Edit {
+        /// Entry id, as shown by `list`
+        id: Option<u64>,
+        /// 1-based position in the file instead of an id
+        #[arg(long, conflicts_with = "id")]
+        index: Option<usize>,
+        /// Set the entry's time to now instead of keeping when it was recorded
+        #[arg(long)]
+        touch: bool,
+    },
+    /// Set the time of an entry to now, e.g. after checking its price is still current
+    #[command(group(ArgGroup::new("target").required(true).args(["id", "index", "product"])))]
+    Touch {
+        /// Entry id, as shown by `list`
+        id: Option<u64>,
+        /// 1-based position in the file instead of an id
+        #[arg(long)]
+        index: Option<usize>,
+        /// Every entry for this product (case-insensitive, aliases resolved); asks which ones when
+        /// several match, unless --all is given
+        #[arg(long)]
+        product: Option<String>,
+        /// Touch every entry --product matches
+        #[arg(long, requires = "product")]
+        all: bool,
+    },
+    /// Count and average price per URL domain (`www.` removed)
+    Domains {
+        #[command(flatten)]
+        filters: Filters,
+    },
+    /// Count, cheapest, average and most expensive price per category
+    Summary {
+        #[command(flatten)]
+        filters: Filters,
+    },
+    /// Report groups of entries that look like duplicates; nothing is changed
+    Dupes {
+        /// Fields that must agree, comma-separated
+        #[arg(long = "key", value_enum, value_delimiter = ',', default_value = "product,url,price")]
+        keys: Vec<DupeKey>,
+    },
+    /// Remove duplicate entries, keeping the newest or oldest of each group
+    Dedupe {
+        /// What to do with every group instead of asking for each
+        #[arg(long, value_enum)]
+        strategy: Option<DedupeStrategy>,
+        /// Don't ask for confirmation before removing
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// List entries with missing or unreadable fields; exits with 2 when any are found
+    #[command(visible_alias = "lint")]
+    Doctor {
+        #[command(flatten)]
+        checks: DoctorChecks,
+    },
+    /// Check that every line reads exactly as written and print counts and a checksum of the
+    /// entries; exits with 2 on a bad line or a checksum that differs from the one recorded
+    Verify {
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Median, quartiles and standard deviation of the matching prices, per currency
+    Stats {
+        #[command(flatten)]
+        filters: Filters,
+        /// Only entries for this product (case-insensitive, aliases resolved)
+        #[arg(long)]
+        product: Option<String>,
+        /// Count entries priced 0.00, which usually mean an unreadable price
+        #[arg(long)]
+        include_zero: bool,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Count, average and lowest price per calendar month, oldest first
+    Monthly {
+        #[command(flatten)]
+        filters: Filters,
+        /// Only entries for this product (case-insensitive, aliases and SKUs resolved)
+        #[arg(long)]
+        product: Option<String>,
+        /// Also list months without entries
+        #[arg(long)]
+        fill_gaps: bool,
+    },
+    /// Draw how the matching prices are distributed, per currency
+    Hist {
+        #[command(flatten)]
+        filters: Filters,
+        /// Number of equal-width price ranges
+        #[arg(long, default_value_t = 10, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        buckets: usize,
+        /// Print the bucket boundaries and counts as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Change categories across all entries
+    Category {
+        #[command(subcommand)]
+        action: CategoryAction,
+    },
+    /// Go through uncategorized entries (or those in --from) one at a time, typing a category for
+    /// each; all changes are saved in one write at the end
+    Recategorize {
+        /// Entries in this category and its subcategories instead of uncategorized ones
+        #[arg(long)]
+        from: Option<String>,
+        /// Move every entry in --from here without asking for each, like `category rename --merge`
+        #[arg(long, requires = "from")]
+        to: Option<String>,
+        /// Don't ask for confirmation with --to
+        #[arg(long, short, requires = "to")]
+        yes: bool,
+    },
+    /// List the distinct categories
+    Categories {
+        /// Print only the names, one per line
+        #[arg(long)]
+        plain: bool,
+        /// Print the `/`-separated hierarchy, counting subcategories into their parents
+        #[arg(long, conflicts_with = "plain")]
+        tree: bool,
+    },
+    /// List the distinct tags with their counts
+    Tags {
+        /// Print only the names, one per line
+        #[arg(long)]
+        plain: bool,
+    },
+    /// Print a shell completion script
+    Completions {
+        shell: Shell,
+    },
+}
+
+impl Command {
+    /// Whether the command may change the database or the files kept next to it, and so needs
+    /// the exclusive lock. Anything not listed as a pure read counts as a write.
+    fn writes(&self) -> bool {
+        !matches!(
+            self,
+            Command::List { .. }
+                | Command::Wishlist
+                | Command::Cheapest { .. }
+                | Command::Compare { .. }
+                | Command::Spread { .. }
+                | Command::Savings { .. }
+                | Command::Value { .. }
+                | Command::Stale { .. }
+                | Command::Recent { .. }
+                | Command::Export { .. }
+                | Command::Target { action: TargetAction::List }
+                | Command::Deals
+                | Command::UnderTarget
+                | Command::Purchases
+                | Command::History { .. }
+                | Command::Trend { .. }
+                | Command::Chart { .. }
+                | Command::Alias { action: AliasAction::List }
+                | Command::Backup { action: BackupAction::List }
+                | Command::Lookup { .. }
+                | Command::Search { .. }
+                | Command::Basket { action: BasketAction::List }
+                | Command::Show { .. }
+                | Command::Domains { .. }
+                | Command::Summary { .. }
+                | Command::Dupes { .. }
+                | Command::Doctor { .. }
+                | Command::Verify { .. }
+                | Command::Log { .. }
+                | Command::Watch { .. }
+                | Command::Stats { .. }
+                | Command::Monthly { .. }
+                | Command::Hist { .. }
+                | Command::Categories { .. }
+                | Command::Tags { .. }
+                | Command::Completions { .. }
+        )
+    }
+}
+
+#[derive(Subcommand)]
+enum CategoryAction {
+    /// Rename OLD (case-insensitive, subcategories included) to NEW in every entry
+    Rename {
+        old: String,
+        new: String,
+        /// Allow NEW to be a category that already has entries, merging OLD into it
+        #[arg(long)]
+        merge: bool,
+        /// Don't ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TargetAction {
+    /// Set the target price on every entry for PRODUCT (case-insensitive exact match)
+    Set {
+        product: String,
+        #[arg(value_parser = parse_price)]
+        price: Money,
+    },
+    /// Remove the target price from every entry for PRODUCT
+    Clear { product: String },
+    /// Show each product with a target, its best current price and how far that is from the target
+    List,
+}
+
+#[derive(Subcommand)]
+enum AliasAction {
+    /// Treat VARIANT as another name for CANONICAL
+    Add { variant: String, canonical: String },
+    /// Forget an alias
+    Remove { variant: String },
+    /// Show every alias and its canonical name
+    List,
+}
+
+#[derive(Subcommand)]
+enum BackupAction {
+    /// Copy the database into the backup directory, unless the newest backup is identical
+    Now,
+    /// Show the backups, newest first
+    List,
+}
+
+#[derive(Subcommand)]
+enum BasketAction {
+    /// Put entries in the basket
+    Add {
+        /// Entry ids, as shown by `list`
+        #[arg(required = true)]
+        ids: Vec<u64>,
+    },
+    /// Take entries out of the basket
+    Remove {
+        #[arg(required = true)]
+        ids: Vec<u64>,
+    },
+    /// Show each product at its cheapest available price, with the total per currency
+    List,
+    /// Empty the basket
+    Clear,
+}
+
+/// Checks run by `doctor`; each can be switched off.
+#[derive(Args)]
+struct DoctorChecks {
+    /// Don't report entries without a category
+    #[arg(long)]
+    no_check_category: bool,
+    /// Don't report entries whose URL is empty or not a valid http(s) URL
+    #[arg(long)]
+    no_check_url: bool,
+    /// Don't report entries priced at 0
+    #[arg(long)]
+    no_check_price: bool,
+    /// Don't report entries whose timestamp can't be read
+    #[arg(long)]
+    no_check_timestamp: bool,
+}
+
+impl DoctorChecks {
+    fn problems(&self, r: &Row) -> Vec<&'static str> {
+        let mut found = Vec::new();
+        if !self.no_check_category && r.category.trim().is_empty() {
+            found.push("no category");
+        }
+        if !self.no_check_url {
+            if r.url.trim().is_empty() {
+                found.push("no URL");
+            } else if !validate::is_web_url(r.url.trim()) {
+                found.push("invalid URL");
+            }
+        }
+        if !self.no_check_price && r.price == Money::default() {
+            found.push("price is 0");
+        }
+        if !self.no_check_timestamp && DateTime::parse_from_rfc3339(&r.timestamp).is_err() {
+            found.push("unreadable timestamp");
+        }
+        found
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum SortKey {
+    /// Cheapest first; equal prices keep their file order
+    Price,
+    /// Product name, case-insensitive
+    Name,
+    /// Category, case-insensitive
+    Category,
+    /// Oldest first, by the recorded time
+    Date,
+    /// Highest priority first, then cheapest
+    Priority,
+}
+
+/// Casing `tidy` can apply to product and category names.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum NameCase {
+    /// Capitalize each word: "Usb-C Cable"
+    Title,
+    /// All lower-case: "usb-c cable"
+    Lower,
+}
+
+impl NameCase {
+    fn apply(self, s: &str) -> String {
+        match self {
+            NameCase::Title => text::title_case(s),
+            NameCase::Lower => s.to_lowercase(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ListFormat {
+    /// Aligned table
+    Table,
+    /// One JSON array
+    Json,
+    /// One JSON object per line, streamed
+    Ndjson,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+/// Appended to the generated bash script so `--category` completes from the database.
+const BASH_CATEGORY_COMPLETION: &str = r#"
+_pricepeek_categories() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ "$prev" == "--category" ]]; then
+        local IFS=$'\n'
+        COMPREPLY=($(compgen -W "$(pricepeek categories --plain 2>/dev/null)" -- "$cur"))
+        return 0
+    fi
+    _pricepeek "$@"
+}
+complete -F _pricepeek_categories -o bashdefault -o default pricepeek
+"#;
+
+/// Exit codes: 0 success, 1 nothing matched, 2 invalid input, 3 I/O or data file failure.
+const EXIT_NO_MATCH: i32 = 1;
+const EXIT_INVALID: i32 = 2;
+const EXIT_IO: i32 = 3;
+
+fn exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<Failure>() {
+        Some(Failure::NoMatch(_)) => EXIT_NO_MATCH,
+        Some(Failure::InvalidInput(_)) => EXIT_INVALID,
+        None => EXIT_IO,
+    }
+}
+
+/// Settings shared by every command for one run.
+struct Ctx<'a> {
+    db: &'a str,
+    /// As given with `--backend`; other databases opened during the run use it too.
+    backend: Option<Backend>,
+    store: &'a dyn PriceStore,
+    cfg: &'a Config,
+    aliases: &'a Aliases,
+    /// Set when the database is encrypted; databases the run creates are encrypted with it too.
+    cipher: Option<&'a Rc<Cipher>>,
+    /// Opened with `--read-only`: the store refuses changes and no lock file is created.
+    read_only: bool,
+    quiet: bool,
+    dry_run: bool,
+}
+
+impl Ctx<'_> {
+    /// Whether to ask before a change: not when `yes` was given, nor in a dry run, which changes
+    /// nothing anyway.
+    fn asks(&self, yes: bool) -> bool {
+        !yes && !self.dry_run
+    }
+
+    /// Whether to say what a change did: not with `--quiet`, nor in a dry run, whose store has
+    /// already printed what it would do.
+    fn tells(&self) -> bool {
+        !self.quiet && !self.dry_run
+    }
+
+    /// Whether to write a file the store doesn't cover, such as a backup copy, the aliases or
+    /// another database. A dry run prints "would <what>" instead.
+    fn may_write(&self, what: impl std::fmt::Display) -> bool {
+        if self.dry_run {
+            println!("would {}", what);
+        }
+        !self.dry_run
+    }
+}
+
+/// Default database location, e.g. `$XDG_DATA_HOME/pricepeek/prices.csv` on Linux.
+fn default_db_path() -> String {
+    directories::ProjectDirs::from("", "", "pricepeek")
+        .map(|dirs| dirs.data_dir().join(LEGACY_DB).to_string_lossy().into_owned())
+        .unwrap_or_else(|| LEGACY_DB.to_string())
+}
+
+/// `db` without a trailing `/`, which asks for a directory database even before it exists, and
+/// the backend that implies. Files kept next to the database, such as its lock and aliases,
+/// are then named the same however the path was typed.
+fn dir_slash(db: &str, backend: Option<Backend>) -> (&str, Option<Backend>) {
+    let trimmed = db.trim_end_matches(std::path::is_separator);
+    if trimmed.is_empty() || trimmed == db {
+        return (db, backend);
+    }
+    (trimmed, backend.or(Some(Backend::Dir)))
+}
+
+/// Variable the passphrase of an encrypted database is taken from instead of asking.
+const PASSPHRASE_VAR: &str = "PRICEPEEK_PASSPHRASE";
+
+/// The passphrase for `db`, from `PRICEPEEK_PASSPHRASE` or typed without echo on the terminal.
+/// A `new` one is asked for twice.
+fn passphrase(db: &str, new: bool) -> Result<String> {
+    if let Some(passphrase) = std::env::var(PASSPHRASE_VAR).ok().filter(|p| !p.is_empty()) {
+        return Ok(passphrase);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(Failure::InvalidInput(format!("No passphrase for {}; set {} or run from a terminal", db, PASSPHRASE_VAR)).into());
+    }
+    let prompt = if new { "New passphrase for" } else { "Passphrase for" };
+    let passphrase = rpassword::prompt_password(format!("{} {}: ", prompt, db)).context("Read the passphrase")?;
+    if passphrase.is_empty() {
+        return Err(Failure::InvalidInput("The passphrase can't be empty".to_string()).into());
+    }
+    if new && rpassword::prompt_password("Repeat it: ").context("Read the passphrase")? != passphrase {
+        return Err(Failure::InvalidInput("The passphrases don't match".to_string()).into());
+    }
+    Ok(passphrase)
 }
 
-fn ensure_db(path: &str) -> Result<()> {
-    if !Path::new(path).exists() {
-        let mut wtr = csv::Writer::from_path(path)
-            .with_context(|| format!("Create {}", path))?;
-        wtr.write_record(HEADER)?;
-        wtr.flush()?;
+/// Offer to copy a `prices.csv` from the working directory into the default location.
+/// Only asked once: declining still creates the new database, so the prompt won't return.
+fn migrate_legacy_db(db: &str, cipher: Option<&Rc<Cipher>>) -> Result<()> {
+    if db == LEGACY_DB || Path::new(db).exists() || !Path::new(LEGACY_DB).exists() || !io::stdin().is_terminal() {
+        return Ok(());
+    }
+    println!("Found {} in the current directory, but the database now lives at {}.", LEGACY_DB, db);
+    if Input::new(Vec::new()).confirm("Copy existing entries there? (y/N): ")? {
+        let rows = CsvStore::open(LEGACY_DB)?.all()?;
+        store::open(db, None, cipher.cloned())?.overwrite(&rows)?;
+        println!("Copied {} entries.", rows.len());
     }
     Ok(())
 }
 
-fn append_row(path: &str, r: &Row) -> Result<()> {
-    ensure_db(path)?;
-    // Append by reading existing rows and rewriting (simple and safe).
-    let mut rows = read_rows(path)?;
-    rows.push(r.clone());
-    write_rows(path, &rows)?;
+/// Write one JSON object per row and line, as rows are produced.
+fn write_ndjson(out: &mut impl Write, rows: impl Iterator<Item = Result<Row>>) -> Result<()> {
+    for row in rows {
+        serde_json::to_writer(&mut *out, &row?)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
     Ok(())
 }
 
-fn read_rows(path: &str) -> Result<Vec<Row>> {
-    ensure_db(path)?;
-    let mut rdr = csv::Reader::from_path(path)?;
-    let mut out = Vec::new();
+/// `rows` as a pretty-printed JSON array, written as they are read.
+fn write_json(out: &mut impl Write, rows: impl Iterator<Item = Result<Row>>) -> Result<()> {
+    let mut failed = None;
+    let rows = rows.map_while(|r| r.map_err(|e| failed = Some(e)).ok());
+    serde_json::Serializer::pretty(&mut *out).collect_seq(rows)?;
+    if let Some(e) = failed {
+        return Err(e);
+    }
+    out.write_all(b"\n")?;
+    out.flush()?;
+    Ok(())
+}
 
-    for rec in rdr.records() {
-        let rec = rec?;
-        // Support both old 4-column files and new 5-column files.
-        if rec.len() >= 5 {
-            let price: f64 = rec.get(2).unwrap_or("0").parse().unwrap_or(0.0);
-            out.push(Row {
-                product: rec.get(0).unwrap_or("").to_string(),
-                category: rec.get(1).unwrap_or("").to_string(),
-                price,
-                url: rec.get(3).unwrap_or("").to_string(),
-                timestamp: rec.get(4).unwrap_or("").to_string(),
-            });
-        } else {
-            let price: f64 = rec.get(1).unwrap_or("0").parse().unwrap_or(0.0);
-            out.push(Row {
-                product: rec.get(0).unwrap_or("").to_string(),
-                category: "".to_string(),
-                price,
-                url: rec.get(2).unwrap_or("").to_string(),
-                timestamp: rec.get(3).unwrap_or("").to_string(),
-            });
-        }
+/// Refuse to export over a database opened read-only, however the path is spelled.
+fn check_export_path(ctx: &Ctx, out: &str) -> Result<()> {
+    let same = match (fs::canonicalize(out), fs::canonicalize(ctx.db)) {
+        (Ok(out), Ok(db)) => out == db,
+        _ => Path::new(out) == Path::new(ctx.db),
+    };
+    if ctx.read_only && same {
+        return Err(Failure::InvalidInput(format!("{} is open read-only; export to another file", ctx.db)).into());
     }
-    Ok(out)
+    Ok(())
 }
 
-fn write_rows(path: &str, rows: &[Row]) -> Result<()> {
-    let mut wtr = csv::Writer::from_path(path)?; // from_path truncates then writes [web:21]
+/// Write `rows` to the CSV file `out` as they are read, compressed when it is named `*.gz`.
+fn export_rows(out: &str, rows: impl Iterator<Item = Result<Row>>) -> Result<()> {
+    let file = fs::File::create(out).with_context(|| format!("Create {}", out))?;
+    let mut wtr = csv::Writer::from_writer(Output::new(file, out));
     wtr.write_record(HEADER)?;
+    let mut count = 0;
     for r in rows {
-        wtr.write_record([
-            r.product.as_str(),
-            r.category.as_str(),
-            &format!("{:.2}", r.price),
-            r.url.as_str(),
-            r.timestamp.as_str(),
-        ])?;
+        let r = r?;
+        wtr.write_record(record(&r, r.id))?;
+        count += 1;
+    }
+    csvfile::close(wtr.into_inner().map_err(|e| e.into_error())?, out)?;
+    info!("exported {} rows to {}", count, out);
+    Ok(())
+}
+
+fn new_row(product: String, category: String, price: Money, url: String) -> Row {
+    let timestamp = Utc::now().to_rfc3339();
+    let store = store_from_url(&url);
+    let source = SOURCE_MANUAL.to_string();
+    Row { product, category, price, url, store, source, timestamp, ..Default::default() }
+}
+
+/// Parse `product,category,price,url[,currency[,store]]` records and append them in a single write.
+/// Bad lines are reported on stderr and skipped; the rest are still saved.
+fn add_batch(ctx: &Ctx, input: impl io::Read, allow_negative: bool, strict: bool) -> Result<()> {
+    let mut rdr = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(input);
+    let mut added = Vec::new();
+    let mut rejected = 0;
+    // Only `--strict` checks prices against earlier ones; nobody is there to confirm an outlier.
+    let existing = if strict { ctx.store.all()? } else { Vec::new() };
+
+    for rec in rdr.records() {
+        let rec = rec.context("Read stdin")?;
+        let line = rec.position().map_or(0, |p| p.line());
+        let checked = batch_row(ctx, &rec, allow_negative).and_then(|row| match price_anomaly(&existing, &row, ctx) {
+            Some(warning) => Err(Failure::InvalidInput(warning).into()),
+            None => Ok(row),
+        });
+        match checked {
+            Ok(row) => added.push(row),
+            Err(e) => {
+                eprintln!("line {}: {}", line, e);
+                rejected += 1;
+            }
+        }
+    }
+
+    let count = added.len();
+    if count > 0 {
+        ctx.store.append(&added)?;
+    }
+    if ctx.tells() {
+        println!("Added {} rows, rejected {}.", count, rejected);
+    }
+    if rejected > 0 {
+        return Err(Failure::InvalidInput(format!("{} lines rejected", rejected)).into());
     }
-    wtr.flush()?;
     Ok(())
 }
 
-fn print_row(r: &Row) {
-    println!("{} | {} | {:.2} | {} | {}", r.product, r.category, r.price, r.url, r.timestamp);
+/// Optional regular price; it must not be lower than the sale price.
+fn parse_original_price(s: &str, price: Money) -> Result<Option<Money>> {
+    if s.trim().is_empty() {
+        return Ok(None);
+    }
+    let orig = parse_price(s)?;
+    if orig < price {
+        return Err(Failure::InvalidInput(format!(
+            "Original price {} is lower than the sale price {}; swap them or leave the original price empty",
+            orig, price
+        ))
+        .into());
+    }
+    Ok(Some(orig))
 }
 
-fn prompt_input(prompt: &str) -> io::Result<String> {
-    print!("{}", prompt);
-    io::stdout().flush()?;
-    let mut buf = String::new();
-    io::stdin().read_line(&mut buf)?;
-    Ok(buf.trim().to_string())
+/// Shipping cost; empty input means free shipping. Together with `price` it must still fit in
+/// an amount, so the landed price can be computed.
+fn parse_shipping(s: &str, price: Money) -> Result<Money> {
+    if s.trim().is_empty() {
+        return Ok(Money::default());
+    }
+    let shipping: Money = s.parse().map_err(|e| Failure::InvalidInput(format!("Invalid shipping cost '{}': {}", s.trim(), e)))?;
+    if price.checked_add(shipping).is_none() {
+        return Err(Failure::InvalidInput(format!("Price {} plus shipping {} is too large", price, shipping)).into());
+    }
+    Ok(shipping)
 }
 
-fn main() -> Result<()> {
-    let db = "prices.csv";
-    ensure_db(db)?;
+/// An optional positive quantity; empty input means none was given.
+fn parse_quantity(s: &str) -> Result<Option<f64>> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+    match s.replace(',', ".").parse::<f64>() {
+        Ok(q) if q > 0.0 && q.is_finite() => Ok(Some(q)),
+        _ => Err(Failure::InvalidInput(format!("Invalid quantity '{}'", s)).into()),
+    }
+}
 
-    loop {
-        println!("\n== Price Tracker ==");
-        println!("1) Add product price");
-        println!("2) List all prices");
-        println!("3) Show cheapest option");
-        println!("4) Export data to CSV");
-        println!("5) Delete a product");
-        println!("6) Exit");
-
-        let choice = prompt_input("Select an option: ")?;
-        match choice.as_str() {
-            "1" => {
-                let product = prompt_input("Product name: ")?;
-                let category = prompt_input("Category: ")?;
-                let price_s = prompt_input("Price: ")?;
-                let url = prompt_input("Product link (URL): ")?;
-                let price: f64 = price_s.replace(',', ".").parse().context("Invalid price")?;
-                let timestamp = Utc::now().to_rfc3339();
-                let row = Row { product, category, price, url, timestamp };
-                append_row(db, &row)?;
-                println!("Saved.");
-            }
-
-            "2" => {
-                let rows = read_rows(db)?;
-                if rows.is_empty() {
-                    println!("No entries.");
-                } else {
-                    for r in rows {
-                        print_row(&r);
-                    }
-                }
-            }
+/// One validated row from an `add --stdin` record. There is nobody to confirm URL fixes,
+/// so a URL without a scheme is rejected with the suggested spelling.
+fn batch_row(ctx: &Ctx, rec: &csv::StringRecord, allow_negative: bool) -> Result<Row> {
+    if !(4..=6).contains(&rec.len()) {
+        return Err(Failure::InvalidInput(format!(
+            "expected 4 to 6 fields (product,category,price,url[,currency[,store]]), got {}",
+            rec.len()
+        ))
+        .into());
+    }
+    let product = validate::product(&rec[0])?;
+    let price = validate::price(&rec[2], allow_negative)?;
+    let url = match validate::url(&rec[3])? {
+        UrlCheck::Valid(url) => url,
+        UrlCheck::MissingScheme(fixed) => {
+            return Err(Failure::InvalidInput(format!("URL '{}' has no scheme; use '{}'", rec[3].trim(), fixed)).into())
+        }
+    };
+    let mut row = new_row(product, rec[1].trim().to_string(), price, url);
+    row.currency = parse_currency(rec.get(4).unwrap_or(&ctx.cfg.default_currency))?;
+    row.source = "import:stdin".to_string();
+    if let Some(store) = rec.get(5).map(str::trim).filter(|s| !s.is_empty()) {
+        row.store = store.to_string();
+    }
+    Ok(row)
+}
 
-            "3" => {
-                let rows = read_rows(db)?;
-                if rows.is_empty() {
-                    println!("No entries.");
-                } else {
-                    let cat = prompt_input("Category to search (leave empty for all): ")?;
-                    let filtered: Vec<Row> = if cat.is_empty() {
-                        rows
-                    } else {
-                        rows.into_iter().filter(|r| r.category.eq_ignore_ascii_case(&cat)).collect()
-                    };
-                    if filtered.is_empty() {
-                        println!("No entries for that category.");
-                    } else {
-                        let best = filtered.into_iter().min_by(|a, b| a.price.partial_cmp(&b.price).unwrap_or(std::cmp::Ordering::Equal));
-                        if let Some(b) = best {
-                            println!("Cheapest option:");
-                            print_row(&b);
-                        }
-                    }
-                }
+/// `key=value;...` from `--extra` pairs; a repeated key keeps its last value.
+fn join_extras(pairs: &[(String, String)]) -> String {
+    let mut kept: Vec<&(String, String)> = Vec::new();
+    for pair in pairs {
+        kept.retain(|(k, _)| *k != pair.0);
+        kept.push(pair);
+    }
+    kept.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(";")
+}
+
+/// A percentage from 0 to 100, with or without the `%` sign.
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let n = s.trim().trim_end_matches('%').trim();
+    match n.parse::<f64>() {
+        Ok(p) if (0.0..=100.0).contains(&p) => Ok(p),
+        _ => Err(format!("expected a percentage from 0 to 100, got '{}'", s)),
+    }
+}
+
+/// A positive multiplier such as `1.02`.
+fn parse_factor(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(f) if f.is_finite() && f > 0.0 => Ok(f),
+        _ => Err(format!("expected a positive number such as 1.02, got '{}'", s)),
+    }
+}
+
+/// The URL to store for `add`: a missing `https://` is added after confirmation on a terminal,
+/// and reported as an error otherwise.
+fn checked_url(url: &str) -> Result<String> {
+    match validate::url(url)? {
+        UrlCheck::Valid(url) => Ok(url),
+        UrlCheck::MissingScheme(fixed) => {
+            if io::stdin().is_terminal() && Input::new(Vec::new()).confirm(&format!("Use '{}'? (y/N): ", fixed))? {
+                Ok(fixed)
+            } else {
+                Err(Failure::InvalidInput(format!("URL '{}' has no scheme; use '{}'", url.trim(), fixed)).into())
             }
+        }
+    }
+}
 
-            "4" => {
-                let confirm = prompt_input("Export data to CSV? (y/N): ")?;
-                if matches!(confirm.to_lowercase().as_str(), "y" | "yes") {
-                    let out = prompt_input("Filename (default export.csv): ")?;
-                    let out = if out.is_empty() { "export.csv" } else { &out };
-                    let cat = prompt_input("Category to export (leave empty for all): ")?;
-                    // write current rows to `out`
-                    let rows = read_rows(db)?;
-                    let rows: Vec<Row> = if cat.is_empty() {
-                        rows
-                    } else {
-                        rows.into_iter().filter(|r| r.category.eq_ignore_ascii_case(&cat)).collect()
-                    };
-                    let mut wtr = csv::Writer::from_path(out).with_context(|| format!("Create {}", out))?;
-                    wtr.write_record(HEADER)?;
-                    for r in rows {
-                        wtr.write_record([
-                            r.product.as_str(),
-                            r.category.as_str(),
-                            &format!("{:.2}", r.price),
-                            r.url.as_str(),
-                            r.timestamp.as_str(),
-                        ])?;
-                    }
-                    wtr.flush()?;
-                    println!("Exported to {}", out);
-                } else {
-                    println!("Export canceled.");
-                }
+/// Normalize an ISO 4217 code to upper case; empty input stays empty (default currency).
+fn parse_currency(s: &str) -> Result<String> {
+    let s = s.trim();
+    if s.is_empty() || (s.len() == 3 && s.chars().all(|c| c.is_ascii_alphabetic())) {
+        Ok(s.to_ascii_uppercase())
+    } else {
+        Err(Failure::InvalidInput(format!("Invalid currency '{}', expected a 3-letter code like EUR", s)).into())
+    }
+}
+
+/// Store name guessed from the URL host, e.g. `amazon` for `https://www.amazon.co.uk/...`.
+fn store_from_url(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    let host = host.rsplit_once('@').map_or(host, |(_, h)| h);
+    let host = host.split(':').next().unwrap_or("");
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    host.split('.').next().unwrap_or("").to_lowercase()
+}
+
+/// Shown for rows whose URL is empty or has no host.
+const NO_DOMAIN: &str = "(no domain)";
+
+fn domain_label(r: &Row) -> String {
+    url_domain(&r.url).unwrap_or_else(|| NO_DOMAIN.to_string())
+}
+
+/// `price` with its currency code, or with the configured symbol when the row has none.
+fn format_price(price: f64, currency: &str, symbol: &str) -> String {
+    if currency.is_empty() {
+        format!("{}{:.2}", symbol, price)
+    } else {
+        format!("{:.2} {}", price, currency)
+    }
+}
+
+/// An RFC 3339 `timestamp` in the local timezone, or in UTC when `utc`, formatted with the
+/// strftime `format`; `None` when it doesn't parse.
+fn format_time(timestamp: &str, format: &str, utc: bool) -> Option<String> {
+    let t = DateTime::parse_from_rfc3339(timestamp).ok()?;
+    Some(if utc {
+        t.with_timezone(&Utc).format(format).to_string()
+    } else {
+        t.with_timezone(&Local).format(format).to_string()
+    })
+}
+
+/// Categories merged case-insensitively and sorted by name, each with its most common spelling
+/// (the first one seen on a tie), its number of rows and its cheapest row per currency. Blank
+/// categories come last, under an empty name.
+fn category_overview(rows: Vec<Row>, cfg: &Config) -> Vec<(String, usize, Vec<Row>)> {
+    let mut out: Vec<(String, usize, Vec<Row>)> = group_by(rows, |r| text::fold(&r.category))
+        .into_values()
+        .map(|g| {
+            let spellings = count_spellings(g.iter().map(|r| r.category.trim()));
+            let name = spellings.into_iter().max_by_key(|(_, n, first)| (*n, std::cmp::Reverse(*first))).map(|(s, _, _)| s);
+            let count = g.len();
+            let cheapest = cheapest_per_currency(g, cfg).into_iter().map(|(_, r)| r).collect();
+            (name.unwrap_or_default(), count, cheapest)
+        })
+        .collect();
+    out.sort_by_key(|(name, _, _)| name.is_empty());
+    out
+}
+
+/// Each exact spelling with how often it occurs and where it first appeared.
+fn count_spellings<'a>(values: impl Iterator<Item = &'a str>) -> Vec<(String, usize, usize)> {
+    let mut out: Vec<(String, usize, usize)> = Vec::new();
+    for (i, v) in values.enumerate() {
+        match out.iter_mut().find(|(s, _, _)| s == v) {
+            Some((_, n, _)) => *n += 1,
+            None => out.push((v.to_string(), 1, i)),
+        }
+    }
+    out
+}
+
+/// Non-empty values with their counts, merged case-insensitively and sorted by name.
+fn count_distinct<'a>(values: impl Iterator<Item = &'a str>) -> Vec<(String, usize)> {
+    let mut out: Vec<(String, usize)> = Vec::new();
+    for v in values.filter(|v| !v.is_empty()) {
+        match out.iter_mut().find(|(c, _)| matches_ignore_case(c, v)) {
+            Some((_, n)) => *n += 1,
+            None => out.push((v.to_string(), 1)),
+        }
+    }
+    out.sort_by_key(|(c, _)| text::fold(c));
+    out
+}
+
+/// The `n` lowest landed prices per currency, cheapest first. Rows tied with the last one kept
+/// are kept as well, so the cutoff never picks arbitrarily between equal prices.
+fn cheapest_n_per_currency(rows: Vec<Row>, n: usize, cfg: &Config) -> Vec<(String, Vec<Row>)> {
+    group_by(rows, |r| currency_of(r, &cfg.default_currency))
+        .into_iter()
+        .map(|(cur, mut g)| {
+            g.sort_by_key(Row::landed);
+            if let Some(cutoff) = g.get(n.saturating_sub(1)).map(Row::landed) {
+                g.retain(|r| r.landed() <= cutoff);
             }
+            (cur, g)
+        })
+        .collect()
+}
 
-            "5" => {
-                // Delete a product by selecting from a numbered list (product | price)
-                let mut rows = read_rows(db)?;
-                if rows.is_empty() {
-                    println!("No entries.");
-                } else {
-                    for (i, r) in rows.iter().enumerate() {
-                        println!("{}: {} | {:.2}", i + 1, r.product, r.price);
-                    }
-                    let sel = prompt_input("Number to delete (or empty to cancel): ")?;
-                    if sel.is_empty() {
-                        println!("Canceled.");
-                    } else {
-                        let n: usize = match sel.parse() {
-                            Ok(v) => v,
-                            Err(_) => { println!("Invalid number."); continue; }
-                        };
-                        if n == 0 || n > rows.len() {
-                            println!("Out of range.");
-                            continue;
-                        }
-                        let idx = n - 1;
-                        let choice = &rows[idx];
-                        let confirm = prompt_input(&format!("Delete '{}' ({} )? (y/N): ", choice.product, choice.price))?;
-                        if matches!(confirm.to_lowercase().as_str(), "y" | "yes") {
-                            rows.remove(idx);
-                            write_rows(db, &rows)?;
-                            println!("Deleted.");
-                        } else {
-                            println!("Canceled.");
-                        }
-                    }
+/// Number of rows and their lowest, mean and highest price.
+struct PriceStats {
+    count: usize,
+    min: Money,
+    mean: f64,
+    max: Money,
+}
+
+/// `None` for no rows. Only meaningful for rows sharing a currency.
+fn price_stats(rows: &[Row]) -> Option<PriceStats> {
+    let min = rows.iter().map(|r| r.price).min()?;
+    let max = rows.iter().map(|r| r.price).max()?;
+    let mean = rows.iter().map(|r| r.price.to_f64()).sum::<f64>() / rows.len() as f64;
+    Some(PriceStats { count: rows.len(), min, mean, max })
+}
+
+/// What `verify` found.
+#[derive(Serialize)]
+struct Verification {
+    entries: usize,
+    products: usize,
+    categories: usize,
+    /// Stored timestamps of the oldest and newest entry.
+    oldest: Option<String>,
+    newest: Option<String>,
+    sha256: String,
+    /// From the checksum file, when there is one.
+    recorded_sha256: Option<String>,
+    /// Lines that could only be read by making up fields, or were skipped.
+    problems: Vec<VerifyProblem>,
+    /// Ids of entries whose timestamp doesn't parse.
+    unreadable_timestamps: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct VerifyProblem {
+    line: u64,
+    problems: Vec<String>,
+    raw: String,
+}
+
+/// Read every entry and summarize it, without the made-up fields other commands fall back on
+/// counting as readable.
+fn verify(ctx: &Ctx) -> Result<Verification> {
+    let rows = ctx.store.all()?;
+    let problems =
+        ctx.store.issues().into_iter().map(|i| VerifyProblem { line: i.line, problems: i.problems, raw: i.raw }).collect();
+    let products: HashSet<String> = rows.iter().map(|r| ctx.aliases.key(&r.product)).collect();
+    let categories: HashSet<String> = rows.iter().map(|r| category::key(&r.category)).filter(|c| !c.is_empty()).collect();
+    let (dated, undated): (Vec<&Row>, Vec<&Row>) = rows.iter().partition(|r| DateTime::parse_from_rfc3339(&r.timestamp).is_ok());
+    let by_time = |a: &&&Row, b: &&&Row| compare_timestamps(&a.timestamp, &b.timestamp);
+    Ok(Verification {
+        entries: rows.len(),
+        products: products.len(),
+        categories: categories.len(),
+        oldest: dated.iter().min_by(by_time).map(|r| r.timestamp.clone()),
+        newest: dated.iter().max_by(by_time).map(|r| r.timestamp.clone()),
+        sha256: checksum::checksum(&rows)?,
+        recorded_sha256: checksum::read_sidecar(ctx.db)?,
+        problems,
+        unreadable_timestamps: undated.iter().map(|r| r.id).collect(),
+    })
+}
+
+fn print_verification(v: &Verification, cfg: &Config) {
+    for p in &v.problems {
+        println!("line {}  {}", p.line, p.problems.join(", "));
+        println!("    {}", p.raw);
+    }
+    for id in &v.unreadable_timestamps {
+        println!("--id {}  unreadable timestamp", id);
+    }
+    let time = |t: &Option<String>| t.as_deref().map_or_else(|| "-".to_string(), |t| cfg.time(t));
+    println!("Entries:     {}", v.entries);
+    println!("Products:    {}", v.products);
+    println!("Categories:  {}", v.categories);
+    println!("Oldest:      {}", time(&v.oldest));
+    println!("Newest:      {}", time(&v.newest));
+    println!("SHA-256:     {}", v.sha256);
+    match &v.recorded_sha256 {
+        Some(sum) if *sum == v.sha256 => println!("Recorded:    matches"),
+        Some(sum) => println!("Recorded:    {} (differs)", sum),
+        None => println!("Recorded:    none"),
+    }
+}
+
+/// Distribution of the prices in one currency.
+#[derive(Serialize)]
+struct PriceDistribution {
+    currency: String,
+    count: usize,
+    min: f64,
+    p25: f64,
+    median: f64,
+    p75: f64,
+    max: f64,
+    mean: f64,
+    /// Sample standard deviation; `None` for a single price.
+    stddev: Option<f64>,
+}
+
+/// Price buckets of one currency, for `hist`.
+#[derive(Serialize)]
+struct Histogram {
+    currency: String,
+    buckets: Vec<chart::Bucket>,
+}
+
+/// `None` for no rows. Percentiles interpolate linearly between neighbouring prices.
+fn price_distribution(rows: &[Row], currency: String) -> Option<PriceDistribution> {
+    let mut prices: Vec<f64> = rows.iter().map(|r| r.price.to_f64()).collect();
+    prices.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let n = prices.len();
+    let percentile = |p: f64| {
+        let pos = p * (n - 1) as f64;
+        let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+        prices[lo] + (prices[hi] - prices[lo]) * (pos - lo as f64)
+    };
+    let mean = prices.iter().sum::<f64>() / n.max(1) as f64;
+    let stddev = (n > 1).then(|| (prices.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt());
+    Some(PriceDistribution {
+        currency,
+        count: n,
+        min: *prices.first()?,
+        p25: percentile(0.25),
+        median: percentile(0.5),
+        p75: percentile(0.75),
+        max: *prices.last()?,
+        mean,
+        stddev,
+    })
+}
+
+/// The cheapest row for each currency (rows without one count as `default_currency`),
+/// so prices in different currencies are never compared with each other.
+fn cheapest_per_currency(rows: Vec<Row>, cfg: &Config) -> Vec<(String, Row)> {
+    group_by(rows, |r| currency_of(r, &cfg.default_currency))
+        .into_iter()
+        .filter_map(|(cur, g)| cheapest(g).map(|r| (cur, r)))
+        .collect()
+}
+
+/// The lowest unit price for each currency and base unit. Rows without a quantity
+/// form their own group per currency, compared by total price.
+fn cheapest_per_unit(rows: Vec<Row>, cfg: &Config) -> Vec<(String, Row)> {
+    let key = |r: &Row| {
+        let basis = r.unit_price().map_or("total price".to_string(), |(_, unit)| format!("per {}", unit));
+        let cur = currency_of(r, &cfg.default_currency);
+        if cur.is_empty() { basis } else { format!("{} {}", cur, basis) }
+    };
+    group_by(rows, key)
+        .into_iter()
+        .filter_map(|(k, g)| cheapest_by(g, |r| r.unit_price().map_or(r.price.to_f64(), |(p, _)| p)).map(|r| (k, r)))
+        .collect()
+}
+
+/// The change from `prev` to `cur`, e.g. "-0.10 (-8.3%)"; without a percentage when `prev` is zero.
+fn price_change(prev: Money, cur: Money) -> String {
+    let d = cur - prev;
+    let sign = if d.units() >= 0 { "+" } else { "" };
+    if prev == Money::default() {
+        format!("{}{:.2}", sign, d.to_f64())
+    } else {
+        format!("{}{:.2} ({:+.1}%)", sign, d.to_f64(), d.to_f64() / prev.to_f64() * 100.0)
+    }
+}
+
+/// The latest row in `rows` recording the same product as `new` (see `product_identity`).
+fn previous_observation(mut rows: Vec<Row>, new: &Row, aliases: &Aliases) -> Option<Row> {
+    // `new` takes part so its SKU links rows that only share its name.
+    rows.push(new.clone());
+    let identity = product_identity(&rows, aliases);
+    let key = identity(new);
+    rows.pop();
+    rows.into_iter().filter(|r| identity(r) == key).max_by(|a, b| compare_timestamps(&a.timestamp, &b.timestamp))
+}
+
+/// A warning when `new` costs more than `anomaly_factor` times more or less than the median
+/// of the earlier prices recorded for the same product in the same currency, such as 1999 typed
+/// for 19.99. `None` for a product's first price, and for zero or negative prices.
+fn price_anomaly(rows: &[Row], new: &Row, ctx: &Ctx) -> Option<String> {
+    let (cfg, factor) = (ctx.cfg, ctx.cfg.anomaly_factor);
+    if factor <= 1.0 || new.price <= Money::default() {
+        return None;
+    }
+    let mut rows = rows.to_vec();
+    // `new` takes part so its SKU links rows that only share its name.
+    rows.push(new.clone());
+    let identity = product_identity(&rows, ctx.aliases);
+    let key = identity(new);
+    rows.pop();
+    let mut earlier: Vec<Row> =
+        rows.into_iter().filter(|r| identity(r) == key && currency_of(r, &cfg.default_currency) == currency_of(new, &cfg.default_currency)).collect();
+    let mut units: Vec<i64> = earlier.iter().map(|r| r.price.units()).collect();
+    units.sort_unstable();
+    let median = match units.len() {
+        0 => return None,
+        n if n % 2 == 1 => units[n / 2] as f64,
+        n => (units[n / 2 - 1] + units[n / 2]) as f64 / 2.0,
+    };
+    if median <= 0.0 {
+        return None;
+    }
+    let ratio = new.price.units() as f64 / median;
+    let (times, direction) = if ratio > factor {
+        (ratio, "more")
+    } else if ratio < 1.0 / factor {
+        (1.0 / ratio, "less")
+    } else {
+        return None;
+    };
+    earlier.sort_by(|a, b| compare_timestamps(&b.timestamp, &a.timestamp));
+    let recent: Vec<String> = earlier.iter().take(5).map(|r| cfg.price(r)).collect();
+    let median = format_price(Money::from_units(median.round() as i64).to_f64(), &new.currency, &cfg.currency_symbol);
+    Some(format!(
+        "{} is {:.1} times {} than the median {} of {} earlier prices for {} (latest: {}).",
+        cfg.price(new),
+        times,
+        direction,
+        median,
+        earlier.len(),
+        new.product,
+        recent.join(", ")
+    ))
+}
+
+/// Whether `row` may be saved after the `price_anomaly` check: with `strict` an outlier is an
+/// error, otherwise the warning is shown and the user decides.
+fn accept_price(ctx: &Ctx, rows: &[Row], row: &Row, strict: bool) -> Result<bool> {
+    let Some(warning) = price_anomaly(rows, row, ctx) else {
+        return Ok(true);
+    };
+    if strict {
+        return Err(Failure::InvalidInput(warning).into());
+    }
+    eprintln!("{}", warning);
+    Ok(Input::new(Vec::new()).confirm("Save anyway? (y/N): ")?)
+}
+
+/// The latest row of each URL `product` is recorded at (see `product_rows`), newest first.
+fn latest_offers(rows: Vec<Row>, product: &str, aliases: &Aliases) -> Result<Vec<Row>> {
+    let rows = product_rows(rows, product, aliases)?;
+    let mut offers: Vec<Row> = group_by(rows, |r| text::fold(&r.url))
+        .into_values()
+        .filter_map(|g| g.into_iter().max_by(|a, b| compare_timestamps(&a.timestamp, &b.timestamp)))
+        .collect();
+    offers.sort_by(|a, b| compare_timestamps(&b.timestamp, &a.timestamp));
+    Ok(offers)
+}
+
+/// Ask which of several offers is meant; returns its position in `offers`.
+fn choose_offer(input: &mut Input, offers: &[Row], cfg: &Config) -> Result<usize> {
+    for (i, r) in offers.iter().enumerate() {
+        let place = if r.url.is_empty() { "(no URL)" } else { &r.url };
+        println!("{}) {} | {} on {}", i + 1, place, cfg.price(r), cfg.date(&r.timestamp));
+    }
+    let label = format!("Which offer (1-{})", offers.len());
+    input.valid(&label, "1", |s| match s.trim().parse::<usize>() {
+        Ok(n) if (1..=offers.len()).contains(&n) => Ok(n - 1),
+        _ => Err(Failure::InvalidInput(format!("Invalid choice '{}', expected 1 to {}", s.trim(), offers.len())).into()),
+    })
+}
+
+/// A new observation of the same offer as `latest` at `price`, recorded now. Sale, purchase and
+/// archive details belong to the old observation and are not copied.
+fn updated_row(latest: &Row, price: Money) -> Row {
+    Row {
+        id: 0,
+        price,
+        original_price: None,
+        archived: false,
+        purchased_at: String::new(),
+        source: SOURCE_MANUAL.to_string(),
+        timestamp: Utc::now().to_rfc3339(),
+        ..latest.clone()
+    }
+}
+
+fn parse_strategy(s: &str) -> Result<DedupeStrategy> {
+    match s.trim().to_lowercase().as_str() {
+        "n" | "newest" => Ok(DedupeStrategy::Newest),
+        "o" | "oldest" => Ok(DedupeStrategy::Oldest),
+        "a" | "all" => Ok(DedupeStrategy::All),
+        _ => Err(Failure::InvalidInput(format!("Unknown choice '{}', expected n, o or a", s.trim())).into()),
+    }
+}
+
+/// Ask per group (or apply `strategy` to all), then remove the chosen rows in one write after
+/// backing up the database.
+fn dedupe(ctx: &Ctx, strategy: Option<DedupeStrategy>, yes: bool) -> Result<()> {
+    let cfg = ctx.cfg;
+    let rows = ctx.store.all()?;
+    let groups = dedupe_groups(&rows, ctx.aliases);
+    if groups.is_empty() {
+        return Err(Failure::NoMatch("No duplicates found.".to_string()).into());
+    }
+    let interactive = io::stdin().is_terminal();
+    if strategy.is_none() && !interactive {
+        return Err(Failure::InvalidInput("Without a terminal, pass --strategy".to_string()).into());
+    }
+    let mut input = Input::new(Vec::new());
+    let mut remove: HashSet<usize> = HashSet::new();
+    let (mut exact_removed, mut near_removed) = (0, 0);
+    for (exact, g) in &groups {
+        let choice = match strategy {
+            Some(s) => s,
+            None => {
+                let kind = if *exact { "same product, URL and price" } else { "same product and URL within an hour" };
+                println!("{} entries, {}:", g.len(), kind);
+                for (i, r) in g {
+                    println!("  --id {}  --index {}  {}  {}", r.id, i + 1, cfg.price(r), cfg.time(&r.timestamp));
                 }
+                input.valid("Keep (n)ewest, (o)ldest or (a)ll", "a", parse_strategy)?
             }
+        };
+        let doomed = removals(g, choice);
+        if *exact {
+            exact_removed += doomed.len();
+        } else {
+            near_removed += doomed.len();
+        }
+        remove.extend(doomed);
+    }
+    if remove.is_empty() {
+        println!("Nothing removed.");
+        return Ok(());
+    }
+    if ctx.asks(yes) {
+        if !interactive {
+            return Err(Failure::InvalidInput("Refusing to remove duplicates without confirmation; pass --yes".to_string()).into());
+        }
+        if !input.confirm(&format!("Remove {} entries? (y/N): ", remove.len()))? {
+            println!("Canceled.");
+            return Ok(());
+        }
+    }
+    backup_db(ctx)?;
+    let (kept, changes) = store::remove_at(rows, &remove);
+    ctx.store.commit(&kept, &changes)?;
+    if ctx.tells() {
+        println!(
+            "Removed {} entries: {} exact duplicates, {} recorded within an hour of another.",
+            remove.len(),
+            exact_removed,
+            near_removed
+        );
+    }
+    Ok(())
+}
 
-            "6" => {
-                println!("Goodbye.");
-                break;
-            }
+/// Rows on sale with the percentage saved, biggest discount first.
+fn deals(rows: Vec<Row>) -> Vec<(Row, f64)> {
+    let mut hits: Vec<(Row, f64)> = rows.into_iter().filter_map(|r| r.discount().map(|d| (r, d))).collect();
+    hits.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    hits
+}
+
+/// Latest rows at or below their target, with the percentage below target, biggest drop first.
+fn under_target(rows: Vec<Row>, aliases: &Aliases) -> Vec<(Row, f64)> {
+    let mut hits: Vec<(Row, f64)> = latest_observations(rows, false, aliases)
+        .into_iter()
+        .filter(Row::under_target)
+        .map(|r| {
+            let t = r.target.unwrap_or_default().to_f64();
+            let pct = if t > 0.0 { (t - r.price.to_f64()) / t * 100.0 } else { 0.0 };
+            (r, pct)
+        })
+        .collect();
+    hits.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    hits
+}
 
-            _ => println!("Invalid option."),
+/// Apply `change` to the row with `id` and save the result.
+fn update_row(ctx: &Ctx, id: u64, change: impl FnOnce(&mut Row)) -> Result<()> {
+    let mut rows = ctx.store.all()?;
+    let row = rows.iter_mut().find(|r| r.id == id).ok_or_else(|| Failure::NoMatch(format!("No entry with id {}.", id)))?;
+    let old = row.clone();
+    change(row);
+    let changes = Changes { updated: vec![(old, row.clone())], ..Default::default() };
+    ctx.store.commit(&rows, &changes)
+}
+
+/// Archive or restore the rows with the given ids in one write; returns how many changed.
+fn set_archived(ctx: &Ctx, ids: &[u64], archived: bool) -> Result<usize> {
+    let mut rows = ctx.store.all()?;
+    let stored: HashSet<u64> = rows.iter().map(|r| r.id).collect();
+    if let Some(missing) = ids.iter().find(|id| !stored.contains(id)) {
+        return Err(Failure::NoMatch(format!("No entry with id {}.", missing)).into());
+    }
+    let ids: HashSet<u64> = ids.iter().copied().collect();
+    let mut changes = Changes::default();
+    for r in rows.iter_mut().filter(|r| ids.contains(&r.id) && r.archived != archived) {
+        let old = r.clone();
+        r.archived = archived;
+        changes.updated.push((old, r.clone()));
+    }
+    ctx.store.commit(&rows, &changes)?;
+    Ok(changes.updated.len())
+}
+
+/// Set (or with `None` clear) the target on every row named `product`; returns how many matched.
+fn set_target(ctx: &Ctx, product: &str, target: Option<Money>) -> Result<usize> {
+    let mut rows = ctx.store.all()?;
+    let mut changes = Changes::default();
+    let mut matched = 0;
+    for r in rows.iter_mut().filter(|r| matches_ignore_case(&r.product, product)) {
+        matched += 1;
+        if r.target != target {
+            let old = r.clone();
+            r.target = target;
+            changes.updated.push((old, r.clone()));
+        }
+    }
+    if matched == 0 {
+        let mut similar: Vec<&str> = Vec::new();
+        for (_, r, _) in search::fuzzy(&rows, product, FUZZY_LIMIT) {
+            if !similar.iter().any(|s| matches_ignore_case(s, &r.product)) {
+                similar.push(&r.product);
+            }
         }
+        let hint = if similar.is_empty() { String::new() } else { format!(" Did you mean: {}?", similar.join(", ")) };
+        return Err(Failure::NoMatch(format!("No entries for product '{}'.{}", product, hint)).into());
     }
+    ctx.store.commit(&rows, &changes)?;
+    Ok(matched)
+}
+
+/// For each product with a target (aliases resolved), its cheapest buyable latest price per
+/// store alongside the target; closest to or furthest below the target first.
+fn target_progress(rows: Vec<Row>, aliases: &Aliases) -> Vec<(Row, Money)> {
+    let mut targets: Vec<(Row, Money)> = group_by(rows, |r| aliases.key(&r.product))
+        .into_values()
+        .filter_map(|g| {
+            let target = g.iter().find_map(|r| r.target)?;
+            let latest = latest_observations(not_purchased(g), true, aliases);
+            let buyable = in_stock(latest.clone());
+            let best = cheapest(if buyable.is_empty() { latest } else { buyable })?;
+            Some((best, target))
+        })
+        .collect();
+    targets.sort_by_key(|(best, target)| best.price - *target);
+    targets
+}
 
+/// Apply `adjustment` to every row `filters` and `product` select, after listing each old and
+/// new price and confirming, then rewrite the database once. Rows priced 0.00 (how an unreadable
+/// price is loaded) and rows that would drop below zero are skipped with a warning.
+fn adjust_prices(
+    ctx: &Ctx,
+    filters: &Filters,
+    product: Option<&str>,
+    adjustment: Adjustment,
+    currency: Option<String>,
+    yes: bool,
+) -> Result<()> {
+    let cfg = ctx.cfg;
+    let mut rows = ctx.store.all()?;
+    let mut selected = filters.apply(rows.clone());
+    if let Some(p) = product {
+        selected = product_rows(selected, p, ctx.aliases)?;
+    }
+    let ids: HashSet<u64> = selected.iter().map(|r| r.id).collect();
+    if ids.is_empty() {
+        return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+    }
+    let (changes, skipped) = adjust::adjust(&mut rows, &ids, adjustment, currency.as_deref())?;
+    for (r, why) in skipped {
+        let why = match why {
+            Skip::Unpriced => "price is 0.00, possibly unreadable in the file",
+            Skip::BelowZero => "price would drop below zero",
+        };
+        eprintln!("warning: skipping #{} {}: {}", r.id, r.product, why);
+    }
+    if changes.updated.is_empty() {
+        println!("No prices changed.");
+        return Ok(());
+    }
+    for (old, new) in &changes.updated {
+        println!("#{} {}: {} -> {}", old.id, old.product, cfg.price(old), cfg.price(new));
+    }
+    if ctx.asks(yes) {
+        if !io::stdin().is_terminal() {
+            return Err(Failure::InvalidInput("Refusing to adjust prices without confirmation; pass --yes".to_string()).into());
+        }
+        if !Input::new(Vec::new()).confirm(&format!("Adjust {} entries? (y/N): ", changes.updated.len()))? {
+            println!("Canceled.");
+            return Ok(());
+        }
+    }
+    ctx.store.commit(&rows, &changes)?;
+    if ctx.tells() {
+        println!("Adjusted {} entries.", changes.updated.len());
+    }
     Ok(())
 }
+
+/// Ask which of the rows at `matched` to use, accepting a selection such as `1,3` or `all`
+/// by their position in the list shown. Refuses without a terminal to ask on.
+fn pick_rows(rows: &[Row], matched: Vec<usize>, cfg: &Config) -> Result<Vec<usize>> {
+    if !io::stdin().is_terminal() {
+        return Err(Failure::InvalidInput(format!("{} entries match; pass --all to touch them all", matched.len())).into());
+    }
+    for (n, &i) in matched.iter().enumerate() {
+        let r = &rows[i];
+        println!("{:>3}) #{} {} | {} | {}", n + 1, r.id, r.product, r.store, cfg.date(&r.timestamp));
+    }
+    let choices: Vec<u64> = (1..=matched.len() as u64).collect();
+    let mut input = Input::new(Vec::new());
+    loop {
+        let sel = select::parse(&input.line("Which entries? (e.g. 1,3 or all, empty to cancel): ")?, &choices);
+        for e in &sel.errors {
+            println!("Skipped {}", e);
+        }
+        if sel.errors.is_empty() || !sel.picked.is_empty() {
+            return Ok(sel.picked.iter().map(|&n| matched[n as usize - 1]).collect());
+        }
+    }
+}
+
+/// Set the timestamp of the rows at `targets` to now, printing each old and new value.
+fn touch_rows(ctx: &Ctx, mut rows: Vec<Row>, targets: &[usize]) -> Result<()> {
+    if targets.is_empty() {
+        println!("Canceled.");
+        return Ok(());
+    }
+    let now = Utc::now().to_rfc3339();
+    let mut changes = Changes::default();
+    for &i in targets {
+        let old = rows[i].clone();
+        rows[i].timestamp = now.clone();
+        changes.updated.push((old, rows[i].clone()));
+    }
+    ctx.store.commit(&rows, &changes)?;
+    if ctx.tells() {
+        for (old, new) in &changes.updated {
+            println!("#{} {}: {} -> {}", old.id, old.product, ctx.cfg.time(&old.timestamp), ctx.cfg.time(&new.timestamp));
+        }
+    }
+    Ok(())
+}
+
+/// `r` with whitespace cleaned up: single-line fields squeezed, notes trimmed line by line,
+/// blank tags dropped and category segments trimmed (" food / snacks " becomes "food/snacks").
+/// Custom fields are left verbatim. Applying it twice gives the same row.
+fn tidy_row(r: &Row, product_case: Option<NameCase>, category_case: Option<NameCase>) -> Row {
+    let recase = |s: String, case: Option<NameCase>| case.map_or(s.clone(), |c| c.apply(&s));
+    let category: Vec<String> = r.category.split('/').map(text::squeeze).filter(|s| !s.is_empty()).collect();
+    let notes: Vec<String> = r.notes.lines().map(text::squeeze).collect();
+    Row {
+        product: recase(text::squeeze(&r.product), product_case),
+        category: recase(category.join("/"), category_case),
+        currency: text::squeeze(&r.currency),
+        url: r.url.trim().to_string(),
+        store: text::squeeze(&r.store),
+        notes: notes.join("\n").trim().to_string(),
+        tags: r.tags.iter().map(|t| text::squeeze(t)).filter(|t| !t.is_empty()).collect(),
+        unit: text::squeeze(&r.unit),
+        source: text::squeeze(&r.source),
+        sku: r.sku.trim().to_string(),
+        purchased_at: r.purchased_at.trim().to_string(),
+        timestamp: r.timestamp.trim().to_string(),
+        ..r.clone()
+    }
+}
+
+/// The text fields that differ between `old` and `new`, as `name: "old" -> "new"`.
+fn changed_fields(old: &Row, new: &Row) -> Vec<String> {
+    let fields = [
+        ("product", old.product.clone(), new.product.clone()),
+        ("category", old.category.clone(), new.category.clone()),
+        ("currency", old.currency.clone(), new.currency.clone()),
+        ("url", old.url.clone(), new.url.clone()),
+        ("store", old.store.clone(), new.store.clone()),
+        ("notes", old.notes.clone(), new.notes.clone()),
+        ("tags", old.tags.join(";"), new.tags.join(";")),
+        ("unit", old.unit.clone(), new.unit.clone()),
+        ("source", old.source.clone(), new.source.clone()),
+        ("sku", old.sku.clone(), new.sku.clone()),
+        ("purchased_at", old.purchased_at.clone(), new.purchased_at.clone()),
+        ("timestamp", old.timestamp.clone(), new.timestamp.clone()),
+    ];
+    fields.into_iter().filter(|(_, a, b)| a != b).map(|(name, a, b)| format!("{}: {:?} -> {:?}", name, a, b)).collect()
+}
+
+/// Clean up whitespace (and optionally casing) in every entry, listing each changed field,
+/// then rewrite the database once.
+fn tidy(ctx: &Ctx, product_case: Option<NameCase>, category_case: Option<NameCase>) -> Result<()> {
+    let mut rows = ctx.store.all()?;
+    let mut changes = Changes::default();
+    for r in rows.iter_mut() {
+        let new = tidy_row(r, product_case, category_case);
+        let fields = changed_fields(r, &new);
+        if fields.is_empty() {
+            continue;
+        }
+        println!("#{}: {}", r.id, fields.join(", "));
+        changes.updated.push((std::mem::replace(r, new), r.clone()));
+    }
+    if changes.updated.is_empty() {
+        if !ctx.quiet {
+            println!("Nothing to tidy.");
+        }
+        return Ok(());
+    }
+    ctx.store.commit(&rows, &changes)?;
+    if ctx.tells() {
+        println!("Tidied {} of {} entries.", changes.updated.len(), rows.len());
+    }
+    Ok(())
+}
+
+/// Explain up front when `cheapest_per_unit` had to split rows into several groups.
+fn print_unit_groups(best: &[(String, Row)]) {
+    let mut units: Vec<String> = best.iter().filter_map(|(_, r)| r.unit_price().map(|(_, u)| u)).collect();
+    units.sort();
+    units.dedup();
+    if units.len() > 1 {
+        println!("Units differ ({}); each is compared separately.", units.join(", "));
+    }
+    if units.is_empty() {
+        println!("No entries have a quantity; comparing total prices.");
+    } else if best.iter().any(|(_, r)| r.unit_price().is_none()) {
+        println!("Entries without a quantity are compared by total price.");
+    }
+}
+
+fn print_cheapest(best: &[(String, Row)], cfg: &Config, quiet: bool) {
+    for (cur, r) in best {
+        if !quiet {
+            if best.len() > 1 {
+                println!("Cheapest option ({}):", if cur.is_empty() { "no currency" } else { cur });
+            } else {
+                println!("Cheapest option:");
+            }
+        }
+        print_row(r, cfg);
+        if r.shipping != Money::default() && !quiet {
+            let fmt = |m: Money| format_price(m.to_f64(), &r.currency, &cfg.currency_symbol);
+            println!("  item {} + shipping {} = {}", fmt(r.price), fmt(r.shipping), fmt(r.landed()));
+        }
+    }
+}
+
+/// Ranked options per currency; equal prices share a rank, and every option after the first
+/// shows how much more it costs than the cheapest.
+fn print_top(groups: &[(String, Vec<Row>)], cfg: &Config, quiet: bool) {
+    for (cur, g) in groups {
+        if !quiet {
+            if groups.len() > 1 {
+                println!("Cheapest options ({}):", if cur.is_empty() { "no currency" } else { cur });
+            } else {
+                println!("Cheapest options:");
+            }
+        }
+        let best = g[0].landed();
+        let mut rank = 0;
+        for (i, r) in g.iter().enumerate() {
+            if i == 0 || r.landed() != g[i - 1].landed() {
+                rank = i + 1;
+            }
+            let extra = r.landed() - best;
+            let diff = if extra == Money::default() {
+                String::new()
+            } else if best.units() > 0 {
+                let pct = extra.to_f64() / best.to_f64() * 100.0;
+                format!(" | +{} (+{:.1}%)", format_price(extra.to_f64(), &r.currency, &cfg.currency_symbol), pct)
+            } else {
+                format!(" | +{}", format_price(extra.to_f64(), &r.currency, &cfg.currency_symbol))
+            };
+            println!("{:>2}. {}{}", rank, format_row(r, cfg), diff);
+        }
+    }
+}
+
+fn list(
+    ctx: &Ctx,
+    filters: &Filters,
+    format: ListFormat,
+    sort: Option<(SortKey, bool)>,
+    full: bool,
+    no_pager: bool,
+    strict: bool,
+) -> Result<()> {
+    // JSON is written as rows are read, so a large file never has to fit in memory. Sorting
+    // and the table's column widths need every row first, and strict listings read everything
+    // so nothing is printed from a file that fails.
+    if format != ListFormat::Table && sort.is_none() && !strict {
+        let mut rows = filters.stream(ctx.store.stream()?);
+        if format == ListFormat::Ndjson {
+            write_ndjson(&mut io::stdout().lock(), rows.by_ref())?;
+        } else {
+            write_json(&mut io::stdout().lock(), rows.by_ref())?;
+        }
+        rows.finish();
+        return report_issues(ctx, false);
+    }
+    let mut rows = ctx.store.filter(filters)?;
+    report_issues(ctx, strict)?;
+    if let Some((key, desc)) = sort {
+        sort_rows(&mut rows, key, desc);
+    }
+    if format == ListFormat::Ndjson {
+        write_ndjson(&mut io::stdout().lock(), rows.into_iter().map(Ok))?;
+    } else if format == ListFormat::Json {
+        write_json(&mut io::stdout().lock(), rows.into_iter().map(Ok))?;
+    } else if rows.is_empty() {
+        if !ctx.quiet {
+            println!("No entries.");
+        }
+    } else {
+        let mut out = table::render_table(&rows, &TableOpts::detect(full, ctx.cfg));
+        if !ctx.quiet {
+            out += &list_footer(rows, ctx.cfg);
+        }
+        pager::show(&out, no_pager)?;
+    }
+    Ok(())
+}
+
+/// After a read: with `strict` any line that could only be loaded by making up fields, or had
+/// to be skipped, is an error; otherwise how many there were is mentioned on stderr.
+fn report_issues(ctx: &Ctx, strict: bool) -> Result<()> {
+    let issues = ctx.store.issues();
+    let Some(first) = issues.first() else {
+        return Ok(());
+    };
+    let count = match issues.len() {
+        1 => "1 line had problems".to_string(),
+        n => format!("{} lines had problems", n),
+    };
+    if strict {
+        anyhow::bail!(
+            "{} in {}, the first on line {}: {}; run `pricepeek doctor` for details",
+            count,
+            ctx.db,
+            first.line,
+            first.problems.join(", ")
+        );
+    }
+    eprintln!("warning: {}, run `pricepeek doctor` for details", count);
+    Ok(())
+}
+
+/// "12 entries · min 4.99 · avg 9.50 · max 19.99 · newest 2024-06-01", one line per currency.
+fn list_footer(rows: Vec<Row>, cfg: &Config) -> String {
+    let mut out = String::new();
+    for (cur, g) in group_by(rows, |r| currency_of(r, &cfg.default_currency)) {
+        let Some(stats) = price_stats(&g) else { continue };
+        let fmt = |p: f64| format_price(p, &cur, &cfg.currency_symbol);
+        let newest = g.iter().max_by(|a, b| compare_timestamps(&a.timestamp, &b.timestamp)).map(|r| cfg.date(&r.timestamp));
+        out += &format!(
+            "{} {} · min {} · avg {} · max {}",
+            stats.count,
+            if stats.count == 1 { "entry" } else { "entries" },
+            fmt(stats.min.to_f64()),
+            fmt(stats.mean),
+            fmt(stats.max.to_f64())
+        );
+        if let Some(newest) = newest {
+            out += &format!(" · newest {}", newest);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Stable sort, so rows that compare equal keep their file order in either direction.
+fn sort_rows(rows: &mut [Row], key: SortKey, desc: bool) {
+    let order = |a: &Row, b: &Row| match key {
+        SortKey::Price => a.price.cmp(&b.price),
+        SortKey::Name => text::fold(&a.product).cmp(&text::fold(&b.product)),
+        SortKey::Category => text::fold(&a.category).cmp(&text::fold(&b.category)),
+        SortKey::Date => compare_timestamps(&a.timestamp, &b.timestamp),
+        SortKey::Priority => b.priority.cmp(&a.priority).then(a.landed().cmp(&b.landed())),
+    };
+    if desc {
+        rows.sort_by(|a, b| order(b, a));
+    } else {
+        rows.sort_by(order);
+    }
+}
+
+/// One row per product (aliases resolved): the cheapest buyable one, ranked by the highest
+/// priority among the product's rows, then by price.
+fn wishlist(rows: Vec<Row>, aliases: &Aliases) -> Vec<Row> {
+    let mut best: Vec<Row> = group_by(rows, |r| aliases.key(&r.product))
+        .into_values()
+        .filter_map(|g| {
+            let priority = g.iter().map(|r| r.priority).max().unwrap_or(0);
+            let buyable = in_stock(g.clone());
+            let mut row = cheapest(if buyable.is_empty() { g } else { buyable })?;
+            row.priority = priority;
+            Some(row)
+        })
+        .collect();
+    sort_rows(&mut best, SortKey::Priority, false);
+    best
+}
+
+/// Purchased rows, oldest purchase first, and the landed spend per month and currency.
+fn purchases(rows: Vec<Row>, cfg: &Config) -> (Vec<Row>, Vec<(String, Money)>) {
+    let mut bought: Vec<Row> = rows.into_iter().filter(Row::purchased).collect();
+    bought.sort_by_key(|r| DateTime::parse_from_rfc3339(&r.purchased_at).ok());
+    let mut totals: Vec<(String, Money)> = Vec::new();
+    for r in &bought {
+        let month = match DateTime::parse_from_rfc3339(&r.purchased_at) {
+            Ok(d) => d.format("%Y-%m").to_string(),
+            Err(_) => r.purchased_at.chars().take(7).collect(),
+        };
+        let key = format!("{}\0{}", month, currency_of(r, &cfg.default_currency));
+        match totals.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, sum)) => *sum = *sum + r.landed(),
+            None => totals.push((key, r.landed())),
+        }
+    }
+    totals.sort_by(|a, b| a.0.cmp(&b.0));
+    (bought, totals)
+}
+
+fn show_cheapest(rows: Vec<Row>, filters: &Filters, per_unit: bool, top: usize, cfg: &Config) {
+    let filtered = not_purchased(in_stock(filters.apply(rows)));
+    if filtered.is_empty() {
+        println!("No matching entries.");
+    } else if top > 1 && !per_unit {
+        print_top(&cheapest_n_per_currency(filtered, top, cfg), cfg, false);
+    } else if per_unit {
+        let best = cheapest_per_unit(filtered, cfg);
+        print_unit_groups(&best);
+        print_cheapest(&best, cfg, false);
+    } else {
+        print_cheapest(&cheapest_per_currency(filtered, cfg), cfg, false);
+    }
+}
+
+/// Copy the database to `<database>.bak` before a change that removes many rows at once.
+fn backup_db(ctx: &Ctx) -> Result<()> {
+    let backup = format!("{}.bak", ctx.db);
+    if !ctx.may_write(format_args!("copy the database to {}", backup)) {
+        return Ok(());
+    }
+    copy_db(ctx.db, &backup)?;
+    if !ctx.quiet {
+        println!("Saved a copy of the database to {}", backup);
+    }
+    Ok(())
+}
+
+/// Copy the database file, or every file of a directory database, to `to`.
+fn copy_db(db: &str, to: &str) -> Result<()> {
+    let context = || format!("Back up {} to {}", db, to);
+    if !Path::new(db).is_dir() {
+        fs::copy(db, to).with_context(context)?;
+        return Ok(());
+    }
+    fs::create_dir_all(to).with_context(context)?;
+    for entry in fs::read_dir(db).with_context(context)? {
+        let path = entry.with_context(context)?.path();
+        if path.is_file() {
+            fs::copy(&path, Path::new(to).join(path.file_name().unwrap_or_default())).with_context(context)?;
+        }
+    }
+    Ok(())
+}
+
+/// `<stem>-2024-06-01T12-00-00.bak.<ext>` next to the database, or `<dir>-<time>.bak` for a
+/// directory, so repeated clears never overwrite an earlier copy.
+fn timestamped_backup(db: &str) -> String {
+    let path = Path::new(db);
+    let time = Local::now().format("%Y-%m-%dT%H-%M-%S");
+    if path.is_dir() {
+        return format!("{}-{}.bak", db, time);
+    }
+    let (stem, ext) = backup::stem_and_ext(path);
+    let name = format!("{}-{}.bak.{}", stem, time, ext);
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Copy all rows, with their ids, and the aliases into a new database at `dest`, whose format
+/// follows its extension. A destination that already has entries is refused.
+fn migrate(ctx: &Ctx, dest: &str) -> Result<()> {
+    let (dest, backend) = dir_slash(dest, None);
+    if Path::new(dest) == Path::new(ctx.db) {
+        return Err(Failure::InvalidInput("The destination is the database itself".to_string()).into());
+    }
+    let rows = ctx.store.all()?;
+    if !ctx.may_write(format_args!("copy {} entries to {}", rows.len(), dest)) {
+        return Ok(());
+    }
+    let _lock = lock::exclusive(dest)?;
+    let target = store::open(dest, backend, ctx.cipher.cloned())?;
+    let existing = target.all()?.len();
+    if existing > 0 {
+        return Err(Failure::InvalidInput(format!("{} already has {} entries; migrate into a new file", dest, existing)).into());
+    }
+    target.overwrite(&rows)?;
+    if ctx.aliases.iter().next().is_some() {
+        ctx.aliases.save(dest)?;
+    }
+    if !ctx.quiet {
+        println!("Copied {} entries to {}.", rows.len(), dest);
+    }
+    Ok(())
+}
+
+/// Rewrite a database in an older layout in the current one, after copying the old file
+/// next to it.
+fn upgrade(ctx: &Ctx) -> Result<()> {
+    let Some(old) = ctx.store.outdated()? else {
+        if !ctx.quiet {
+            println!("{} already uses the current layout.", ctx.db);
+        }
+        return Ok(());
+    };
+    let rows = ctx.store.all()?;
+    if !ctx.may_write(format_args!("upgrade {} entries from the {} layout", rows.len(), old)) {
+        return Ok(());
+    }
+    let backup = timestamped_backup(ctx.db);
+    copy_db(ctx.db, &backup)?;
+    ctx.store.overwrite(&rows)?;
+    if !ctx.quiet {
+        println!("Upgraded {} entries from the {} layout; the old file is saved as {}.", rows.len(), old, backup);
+    }
+    Ok(())
+}
+
+/// The last `limit` journal entries, oldest first.
+fn print_log(ctx: &Ctx, limit: usize) -> Result<()> {
+    let entries = journal::read(ctx.db)?;
+    if entries.is_empty() {
+        return Err(Failure::NoMatch(format!("No changes recorded in {}", journal::path(ctx.db))).into());
+    }
+    let shown = &entries[entries.len().saturating_sub(limit)..];
+    let op_width = shown.iter().map(|e| e.op.chars().count()).max().unwrap_or(0);
+    for e in shown {
+        println!("{}  {:<op_width$}  {:>5}  {}", ctx.cfg.time(&e.time), e.op, e.rows, e.description, op_width = op_width);
+    }
+    Ok(())
+}
+
+/// Rewrite the database encrypted. Its automatic backups are left as they are, so any taken
+/// before stay readable without the passphrase; the user is told so.
+fn encrypt(ctx: &Ctx) -> Result<()> {
+    if crypt::file_is_encrypted(ctx.db) {
+        if !ctx.quiet {
+            println!("{} is already encrypted.", ctx.db);
+        }
+        return Ok(());
+    }
+    let rows = ctx.store.all()?;
+    if !ctx.may_write(format_args!("encrypt {} entries", rows.len())) {
+        return Ok(());
+    }
+    // Straight to the file: a backup taken now would be one more plain copy.
+    store::open(ctx.db, ctx.backend, ctx.cipher.cloned())?.overwrite(&rows)?;
+    if !ctx.quiet {
+        println!("Encrypted {} entries in {}.", rows.len(), ctx.db);
+        let backups = Backups::new(ctx.db, ctx.cfg.backup_keep);
+        let plain = backups.list()?.len();
+        if plain > 0 {
+            println!(
+                "The {} backups in {} were taken before and aren't encrypted; delete them if they shouldn't stay readable.",
+                plain,
+                backups.dir().display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite an encrypted database as plain CSV.
+fn decrypt(ctx: &Ctx) -> Result<()> {
+    if !crypt::file_is_encrypted(ctx.db) {
+        if !ctx.quiet {
+            println!("{} isn't encrypted.", ctx.db);
+        }
+        return Ok(());
+    }
+    let rows = ctx.store.all()?;
+    if !ctx.may_write(format_args!("decrypt {} entries", rows.len())) {
+        return Ok(());
+    }
+    store::open(ctx.db, ctx.backend, None)?.overwrite(&rows)?;
+    if !ctx.quiet {
+        println!("Decrypted {} entries in {}.", rows.len(), ctx.db);
+        if ctx.cfg.encrypted {
+            println!("`encrypted = true` in the config file encrypts it again on the next change.");
+        }
+    }
+    Ok(())
+}
+
+/// Placeholder product name for the entries `clear --keep-categories` leaves behind.
+const PLACEHOLDER: &str = "(placeholder)";
+
+/// Empty the database, or reduce it to one zero-priced placeholder per category, after the user
+/// types "clear". The old contents are copied to a timestamped backup first.
+fn clear(ctx: &Ctx, keep_categories: bool) -> Result<()> {
+    let rows = ctx.store.all()?;
+    let mut kept: Vec<Row> = Vec::new();
+    if keep_categories {
+        for c in category::distinct(rows.iter().map(|r| r.category.as_str())) {
+            kept.push(new_row(PLACEHOLDER.to_string(), c, Money::default(), String::new()));
+        }
+    }
+    if ctx.asks(false) {
+        if !io::stdin().is_terminal() {
+            return Err(Failure::InvalidInput("Refusing to clear the database without a terminal to confirm".to_string()).into());
+        }
+        println!("This deletes all {} entries in {}.", rows.len(), ctx.db);
+        let answer = match Input::new(Vec::new()).line("Type 'clear' to continue: ") {
+            Ok(answer) => answer,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        if answer != "clear" {
+            println!("Canceled.");
+            return Ok(());
+        }
+    }
+    let backup = timestamped_backup(ctx.db);
+    if ctx.may_write(format_args!("copy the database to {}", backup)) {
+        copy_db(ctx.db, &backup)?;
+        if !ctx.quiet {
+            println!("Saved a copy of the database to {}", backup);
+        }
+    }
+    let changes = Changes { removed: rows, ..Default::default() };
+    ctx.store.commit(&kept, &changes)?;
+    if ctx.tells() {
+        match kept.len() {
+            0 => println!("Cleared {} entries.", changes.removed.len()),
+            n => println!("Cleared {} entries; kept a placeholder for each of {} categories.", changes.removed.len(), n),
+        }
+    }
+    Ok(())
+}
+
+/// Up to this many entries are listed before asking whether to delete them.
+const LIST_BEFORE_DELETE: usize = 20;
+
+/// Remove the rows at `targets` (0-based) after confirming, then rewrite the database once.
+/// Removing more than one row first copies the database to `<database>.bak`.
+fn delete_rows(ctx: &Ctx, rows: Vec<Row>, targets: &[usize], all: bool, yes: bool) -> Result<()> {
+    let cfg = ctx.cfg;
+    if targets.is_empty() {
+        return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+    }
+    if targets.len() > 1 && !all {
+        for &i in targets {
+            eprintln!("#{}: {} | {}", rows[i].id, rows[i].product, cfg.price(&rows[i]));
+        }
+        return Err(Failure::InvalidInput(format!("{} entries match; pass --all to delete them all", targets.len())).into());
+    }
+    if ctx.asks(yes) && cfg.confirm_deletes {
+        if !io::stdin().is_terminal() {
+            return Err(Failure::InvalidInput("Refusing to delete without confirmation; pass --yes".to_string()).into());
+        }
+        if targets.len() <= LIST_BEFORE_DELETE {
+            for &i in targets {
+                println!("#{}: {} | {}", rows[i].id, rows[i].product, cfg.price(&rows[i]));
+            }
+        }
+        if !Input::new(Vec::new()).confirm(&format!("Delete {} entries? (y/N): ", targets.len()))? {
+            println!("Canceled.");
+            return Ok(());
+        }
+    }
+    if targets.len() > 1 {
+        backup_db(ctx)?;
+    }
+    let (kept, changes) = store::remove_at(rows, &targets.iter().copied().collect());
+    ctx.store.commit(&kept, &changes)?;
+    if ctx.tells() {
+        println!("Deleted {} rows.", targets.len());
+    }
+    Ok(())
+}
+
+fn print_completions(shell: Shell) {
+    clap_complete::generate(shell, &mut Cli::command(), "pricepeek", &mut io::stdout());
+    if shell == Shell::Bash {
+        print!("{}", BASH_CATEGORY_COMPLETION);
+    }
+}
+
+fn run_command(ctx: &Ctx, cmd: Command) -> Result<()> {
+    let (cfg, quiet) = (ctx.cfg, ctx.quiet);
+    match cmd {
+        Command::Add { stdin: true, allow_negative, strict, .. } => add_batch(ctx, io::stdin().lock(), allow_negative, strict)?,
+        Command::Add {
+            product,
+            category,
+            price,
+            url,
+            currency,
+            store,
+            notes,
+            tags,
+            quantity,
+            unit,
+            shipping,
+            status,
+            original_price,
+            priority,
+            extras,
+            sku,
+            allow_negative,
+            strict,
+            normalize_url,
+            ..
+        } => {
+            let product = validate::product(&product.unwrap_or_default())?;
+            let price = validate::price(&price.unwrap_or_default(), allow_negative)?;
+            let mut url = checked_url(&url)?;
+            if normalize_url {
+                url = validate::normalize_url(&url, &cfg.strip_url_params).unwrap_or(url);
+            }
+            let category = category.unwrap_or_else(|| cfg.default_category.clone());
+            let mut row = new_row(product, category, price, url);
+            row.currency = parse_currency(currency.as_deref().unwrap_or(&cfg.default_currency))?;
+            if !store.trim().is_empty() {
+                row.store = store.trim().to_string();
+            }
+            row.notes = notes;
+            row.tags = parse_tags(&tags);
+            row.quantity = parse_quantity(quantity.as_deref().unwrap_or(""))?;
+            row.unit = unit.trim().to_string();
+            row.shipping = parse_shipping(&shipping, price)?;
+            row.status = status;
+            row.original_price = parse_original_price(original_price.as_deref().unwrap_or(""), price)?;
+            row.priority = priority;
+            row.extra = join_extras(&extras);
+            row.sku = sku.trim().to_string();
+            if (strict || io::stdin().is_terminal()) && !accept_price(ctx, &ctx.store.all()?, &row, strict)? {
+                println!("Not saved.");
+                return Ok(());
+            }
+            ctx.store.append(std::slice::from_ref(&row))?;
+        }
+        Command::Update { product, price, url } => {
+            let price = validate::price(&price, false)?;
+            let rows = ctx.store.all()?;
+            let mut offers = latest_offers(rows.clone(), &product, ctx.aliases)?;
+            if let Some(url) = url {
+                offers.retain(|r| matches_ignore_case(&r.url, &url));
+                if offers.is_empty() {
+                    return Err(Failure::NoMatch(format!("No offer for '{}' at {}.", product, url.trim())).into());
+                }
+            }
+            let pick = match offers.len() {
+                1 => 0,
+                _ if io::stdin().is_terminal() => choose_offer(&mut Input::new(Vec::new()), &offers, cfg)?,
+                n => {
+                    for r in &offers {
+                        eprintln!("  {}", r.url);
+                    }
+                    return Err(Failure::InvalidInput(format!("'{}' has {} offers; pick one with --url", product, n)).into());
+                }
+            };
+            let prev = &offers[pick];
+            let row = updated_row(prev, price);
+            if io::stdin().is_terminal() && !accept_price(ctx, &rows, &row, false)? {
+                println!("Not saved.");
+                return Ok(());
+            }
+            ctx.store.append(std::slice::from_ref(&row))?;
+            if !quiet {
+                println!("{} since {} (was {}).", price_change(prev.price, row.price), cfg.date(&prev.timestamp), cfg.price(prev));
+            }
+        }
+        Command::List { filters, format, json, full, no_pager, sort, desc, strict } => {
+            let format = if json { ListFormat::Json } else { format };
+            list(ctx, &filters, format, sort.map(|key| (key, desc)), full, no_pager, strict)?
+        }
+        Command::Wishlist => {
+            let rows = wishlist(ctx.store.filter(&Filters::default())?, ctx.aliases);
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No entries.".to_string()).into());
+            }
+            for r in &rows {
+                let target = match r.target {
+                    Some(_) if r.under_target() => " | target reached",
+                    Some(_) => " | waiting for target",
+                    None => "",
+                };
+                println!("[{}] {} | {} | {}{}", r.priority, ctx.aliases.canonical(&r.product), cfg.price(r), r.store, target);
+            }
+        }
+        Command::Cheapest { filters, per_unit, top, latest_only, include_oos, include_purchased, by_domain } => {
+            let rows = ctx.store.all()?;
+            report_issues(ctx, false)?;
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No entries.".to_string()).into());
+            }
+            let rows = filters.apply(rows);
+            let rows = if include_oos { rows } else { in_stock(rows) };
+            let rows = if include_purchased { rows } else { not_purchased(rows) };
+            let rows = if latest_only { latest_observations(rows, true, ctx.aliases) } else { rows };
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+            }
+            let groups = if by_domain { group_by(rows, domain_label) } else { BTreeMap::from([(String::new(), rows)]) };
+            for (domain, rows) in groups {
+                if by_domain && !quiet {
+                    println!("== {} ==", domain);
+                }
+                if top > 1 {
+                    print_top(&cheapest_n_per_currency(rows, top, cfg), cfg, quiet);
+                    continue;
+                }
+                let best = if per_unit { cheapest_per_unit(rows, cfg) } else { cheapest_per_currency(rows, cfg) };
+                if per_unit && !quiet {
+                    print_unit_groups(&best);
+                }
+                print_cheapest(&best, cfg, quiet);
+            }
+        }
+        Command::Compare { filters, by_domain } => {
+            let mut rows = ctx.store.filter(&filters)?;
+            if by_domain {
+                // The domain stands in for the store, both for grouping and in the output.
+                for r in &mut rows {
+                    r.store = domain_label(r);
+                }
+            }
+            let offers = compare_offers(rows, ctx.aliases, &cfg.default_currency);
+            if offers.is_empty() {
+                return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+            }
+            for (best, next) in &offers {
+                print_offer(best, next.as_ref(), ctx);
+            }
+        }
+        Command::Spread { filters } => {
+            let rows = ctx.store.filter(&filters)?;
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+            }
+            print_spread(rows, ctx);
+        }
+        Command::Savings { filters, min_savings } => {
+            let rows = ctx.store.filter(&filters)?;
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+            }
+            print_savings(rows, min_savings, ctx)?;
+        }
+        Command::Value { filters } => {
+            let rows = ctx.store.filter(&filters)?;
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+            }
+            print_value(rows, cfg, quiet);
+        }
+        Command::Stale { days } => {
+            let days = i64::from(days.or(cfg.stale_after_days).unwrap_or(30));
+            let rows = ctx.store.filter(&Filters::default())?;
+            let mut stale: Vec<(i64, &Row)> =
+                rows.iter().filter_map(|r| age_days(&r.timestamp).map(|age| (age, r))).filter(|(age, _)| *age > days).collect();
+            let unknown: Vec<&Row> = rows.iter().filter(|r| age_days(&r.timestamp).is_none()).collect();
+            if stale.is_empty() && unknown.is_empty() {
+                return Err(Failure::NoMatch(format!("No entries older than {} days.", days)).into());
+            }
+            stale.sort_by(|a, b| compare_timestamps(&a.1.timestamp, &b.1.timestamp));
+            for (age, r) in &stale {
+                println!("{:>5} days  {}", age, format_row(r, cfg));
+            }
+            if !unknown.is_empty() {
+                if !quiet {
+                    println!("Unknown age (timestamp can't be read):");
+                }
+                for r in unknown {
+                    println!("            {}", format_row(r, cfg));
+                }
+            }
+        }
+        Command::Recent { limit, all } => {
+            let mut rows = ctx.store.filter(&Filters::default())?;
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No entries.".to_string()).into());
+            }
+            sort_rows(&mut rows, SortKey::Date, true);
+            if !all && limit > 0 {
+                rows.truncate(limit);
+            }
+            let opts = TableOpts { relative_time: true, ..TableOpts::detect(false, cfg) };
+            pager::show(&table::render_table(&rows, &opts), false)?;
+        }
+        Command::Export { output, filters, format } => {
+            let output = output.unwrap_or_else(|| cfg.default_export_file.clone());
+            check_export_path(ctx, &output)?;
+            match format {
+                ExportFormat::Csv => {
+                    let mut rows = filters.stream(ctx.store.stream()?);
+                    export_rows(&output, rows.by_ref())?;
+                    rows.finish();
+                }
+                ExportFormat::Ndjson => {
+                    let file = fs::File::create(&output).with_context(|| format!("Create {}", output))?;
+                    let mut rows = filters.stream(ctx.store.stream()?);
+                    let mut out = Output::new(file, &output);
+                    write_ndjson(&mut io::BufWriter::new(&mut out), rows.by_ref())?;
+                    csvfile::close(out, &output)?;
+                    rows.finish();
+                }
+            }
+            if !quiet {
+                println!("Exported to {}", output);
+            }
+        }
+        Command::Delete { id, index, product, category, older_than, pattern, regex, all, yes } => {
+            let rows = ctx.store.all()?;
+            let matcher = pattern.map(|p| Matcher::new(&p, regex, &[])).transpose()?;
+            let targets: Vec<usize> = match (id, index.as_deref()) {
+                (Some(id), _) => rows.iter().position(|r| r.id == id).into_iter().collect(),
+                (_, Some(list)) => {
+                    let positions: Vec<u64> = (1..=rows.len() as u64).collect();
+                    let sel = select::parse(list, &positions);
+                    if !sel.errors.is_empty() {
+                        return Err(Failure::InvalidInput(format!("Invalid --index: {}", sel.errors.join("; "))).into());
+                    }
+                    sel.picked.iter().map(|n| *n as usize - 1).collect()
+                }
+                _ => rows
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, r)| product.as_deref().is_none_or(|p| matches_ignore_case(&r.product, p)))
+                    .filter(|(_, r)| category.as_ref().is_none_or(|c| c.matches(&r.category, false)))
+                    .filter(|(_, r)| {
+                        older_than.is_none_or(|cutoff| DateTime::parse_from_rfc3339(&r.timestamp).is_ok_and(|t| t < cutoff))
+                    })
+                    .filter(|(_, r)| matcher.as_ref().is_none_or(|m| m.matches(r)))
+                    .map(|(i, _)| i)
+                    .collect(),
+            };
+            let bulk = category.is_some() || older_than.is_some() || index.is_some();
+            delete_rows(ctx, rows, &targets, all || bulk, yes)?;
+        }
+        Command::Watch { filters } => watch::run(ctx, &filters)?,
+        Command::Adjust { filters, product, multiply, add, set, currency, yes } => {
+            let adjustment = match (multiply, add, set) {
+                (Some(f), _, _) => Adjustment::Multiply(f),
+                (_, Some(amount), _) => Adjustment::Add(amount),
+                (_, _, Some(p)) => Adjustment::Set(p),
+                _ => unreachable!("clap requires one operation"),
+            };
+            adjust_prices(ctx, &filters, product.as_deref(), adjustment, currency, yes)?;
+        }
+        Command::Target { action } => run_target(ctx, action)?,
+        Command::Deals => {
+            let hits = deals(ctx.store.filter(&Filters::default())?);
+            if hits.is_empty() {
+                return Err(Failure::NoMatch("No entries on sale.".to_string()).into());
+            }
+            for (r, pct) in &hits {
+                let orig = format_price(r.original_price.unwrap_or_default().to_f64(), &r.currency, &cfg.currency_symbol);
+                println!("{} | was {} | {:.1}% off", format_row(r, cfg), orig, pct);
+            }
+        }
+        Command::UnderTarget => {
+            let hits = under_target(ctx.store.filter(&Filters::default())?, ctx.aliases);
+            if hits.is_empty() {
+                return Err(Failure::NoMatch("No products at or below their target.".to_string()).into());
+            }
+            print_under_target(&hits, cfg);
+        }
+        Command::MarkPurchased { id, date } => {
+            let when = match date {
+                Some(d) => parse_date(&d)?,
+                None => Local::now().fixed_offset(),
+            };
+            update_row(ctx, id, |r| r.purchased_at = when.to_rfc3339())?;
+            if ctx.tells() {
+                println!("#{} marked as purchased on {}.", id, when.format("%Y-%m-%d"));
+            }
+        }
+        Command::Purchases => {
+            let (bought, totals) = purchases(ctx.store.all()?, cfg);
+            if bought.is_empty() {
+                return Err(Failure::NoMatch("No purchases recorded.".to_string()).into());
+            }
+            for r in &bought {
+                let day = DateTime::parse_from_rfc3339(&r.purchased_at).map_or(r.purchased_at.clone(), |d| d.format("%Y-%m-%d").to_string());
+                let paid = format_price(r.landed().to_f64(), &r.currency, &cfg.currency_symbol);
+                println!("{}  #{}  {} | {} | {}", day, r.id, r.product, paid, r.store);
+            }
+            if !quiet {
+                println!("\nSpend per month:");
+            }
+            for (key, total) in &totals {
+                let (month, currency) = key.split_once('\0').unwrap_or((key, ""));
+                println!("  {}  {}", month, format_price(total.to_f64(), currency, &cfg.currency_symbol));
+            }
+        }
+        Command::History { product, by_store } => {
+            let rows = product_rows(ctx.store.all()?, &product, ctx.aliases)?;
+            for group in group_history(rows, by_store, ctx.aliases) {
+                print_history(&group, by_store, ctx);
+            }
+        }
+        Command::Trend { product } => {
+            let rows = product_rows(ctx.store.all()?, &product, ctx.aliases)?;
+            for group in group_history(rows, false, ctx.aliases) {
+                print_history(&group, false, ctx);
+                let (first, last) = (&group[0], &group[group.len() - 1]);
+                if group.len() < 2 {
+                    println!("  Only one observation so far.");
+                } else if first.currency != last.currency {
+                    println!("  Overall: not comparable ({} to {}).", cfg.price(first), cfg.price(last));
+                } else {
+                    println!("  Overall: {} over {} observations", price_change(first.price, last.price), group.len());
+                }
+            }
+        }
+        Command::Chart { product, width, ascii } => {
+            let rows = product_rows(ctx.store.all()?, &product, ctx.aliases)?;
+            let width = width.or_else(|| terminal_size::terminal_size().map(|(w, _)| w.0 as usize)).unwrap_or(80);
+            let levels = if ascii || !chart::unicode_locale() { chart::ASCII_LEVELS } else { chart::BLOCK_LEVELS };
+            for group in group_history(rows, false, ctx.aliases) {
+                print_chart(&group, width, levels, ctx);
+            }
+        }
+        Command::Alias { action } => run_alias(ctx, action)?,
+        Command::Backup { action } => run_backup(ctx, action)?,
+        Command::Restore { file, yes } => restore(ctx, file, yes)?,
+        Command::Category { action: CategoryAction::Rename { old, new, merge, yes } } => rename_category(ctx, &old, &new, merge, yes)?,
+        Command::Recategorize { from, to, yes } => match (from, to) {
+            (Some(from), Some(to)) => rename_category(ctx, &from, &to, true, yes)?,
+            (from, _) => recategorize(ctx, from.as_deref())?,
+        },
+        Command::Basket { action } => run_basket(ctx, action)?,
+        Command::Archive { ids } => {
+            let n = set_archived(ctx, &ids, true)?;
+            if ctx.tells() {
+                println!("Archived {} rows.", n);
+            }
+        }
+        Command::Unarchive { ids } => {
+            let n = set_archived(ctx, &ids, false)?;
+            if ctx.tells() {
+                println!("Restored {} rows.", n);
+            }
+        }
+        Command::SetPriority { id, priority } => {
+            update_row(ctx, id, |r| r.priority = priority)?;
+            if ctx.tells() {
+                println!("#{} now has priority {}.", id, priority);
+            }
+        }
+        Command::SetStatus { id, status } => {
+            update_row(ctx, id, |r| r.status = status)?;
+            if ctx.tells() {
+                println!("#{} is now {}.", id, status.as_str());
+            }
+        }
+        Command::Lookup { sku } => {
+            if sku.trim().is_empty() {
+                return Err(Failure::InvalidInput("SKU must not be empty".to_string()).into());
+            }
+            let rows: Vec<Row> = ctx.store.all()?.into_iter().filter(|r| matches_ignore_case(&r.sku, &sku)).collect();
+            if rows.is_empty() {
+                return Err(Failure::NoMatch(format!("No entries with SKU '{}'.", sku.trim())).into());
+            }
+            pager::show(&table::render_table(&rows, &TableOpts::detect(false, cfg)), false)?;
+        }
+        Command::Search { query, regex, fields, fuzzy } => {
+            let matcher = Matcher::new(&query, regex, &fields)?;
+            let rows = ctx.store.all()?;
+            if fuzzy {
+                let hits = search::fuzzy(&rows, &query, FUZZY_LIMIT);
+                if hits.is_empty() {
+                    return Err(Failure::NoMatch(format!("No entries resemble '{}'.", query.trim())).into());
+                }
+                print_fuzzy_matches(&hits, cfg);
+                return Ok(());
+            }
+            let hits = matcher.find(&rows);
+            if !hits.is_empty() {
+                print_matches(&hits, cfg);
+                return Ok(());
+            }
+            // Typos only make sense to correct in product names.
+            let closest = if regex || !(fields.is_empty() || fields.contains(&SearchField::Product)) {
+                Vec::new()
+            } else {
+                search::fuzzy(&rows, &query, FUZZY_LIMIT)
+            };
+            if closest.is_empty() {
+                return Err(Failure::NoMatch(format!("No entries match '{}'.", query.trim())).into());
+            }
+            if !quiet {
+                println!("No exact matches, closest were:");
+            }
+            print_fuzzy_matches(&closest, cfg);
+        }
+        Command::Show { id } => {
+            let rows = ctx.store.all()?;
+            let row = rows.iter().find(|r| r.id == id).ok_or_else(|| Failure::NoMatch(format!("No entry with id {}.", id)))?;
+            print_details(row, cfg);
+        }
+        Command::Touch { id, index, product, all } => {
+            let rows = ctx.store.all()?;
+            let targets: Vec<usize> = match (id, index, product) {
+                (Some(id), ..) => {
+                    vec![rows.iter().position(|r| r.id == id).ok_or_else(|| Failure::NoMatch(format!("No entry with id {}.", id)))?]
+                }
+                (_, Some(n), _) if n == 0 || n > rows.len() => {
+                    return Err(Failure::InvalidInput(format!("Index {} out of range (1-{})", n, rows.len())).into());
+                }
+                (_, Some(n), _) => vec![n - 1],
+                (.., product) => {
+                    let product = product.unwrap_or_default();
+                    let key = ctx.aliases.key(&product);
+                    let matched: Vec<usize> = (0..rows.len()).filter(|&i| ctx.aliases.key(&rows[i].product) == key).collect();
+                    if matched.is_empty() {
+                        return Err(Failure::NoMatch(format!("No entries for product '{}'.", product)).into());
+                    }
+                    if all || matched.len() == 1 {
+                        matched
+                    } else {
+                        pick_rows(&rows, matched, cfg)?
+                    }
+                }
+            };
+            touch_rows(ctx, rows, &targets)?;
+        }
+        Command::Tidy { product_case, category_case } => tidy(ctx, product_case, category_case)?,
+        Command::NormalizeUrls => {
+            let mut rows = ctx.store.all()?;
+            let mut changes = Changes::default();
+            for r in rows.iter_mut() {
+                match validate::normalize_url(&r.url, &cfg.strip_url_params) {
+                    Some(url) if url != r.url => {
+                        let old = r.clone();
+                        r.url = url;
+                        changes.updated.push((old, r.clone()));
+                    }
+                    _ => {}
+                }
+            }
+            if changes.updated.is_empty() {
+                if !quiet {
+                    println!("No URLs changed.");
+                }
+                return Ok(());
+            }
+            ctx.store.commit(&rows, &changes)?;
+            if ctx.tells() {
+                println!("Normalized {} of {} URLs.", changes.updated.len(), rows.len());
+            }
+        }
+        Command::Edit { id, index, touch } => {
+            let rows = ctx.store.all()?;
+            let idx = match id {
+                Some(id) => rows.iter().position(|r| r.id == id).ok_or_else(|| Failure::NoMatch(format!("No entry with id {}.", id)))?,
+                None => match index.unwrap_or_default() {
+                    n if n == 0 || n > rows.len() => {
+                        return Err(Failure::InvalidInput(format!("Index {} out of range (1-{})", n, rows.len())).into());
+                    }
+                    n => n - 1,
+                },
+            };
+            menu::edit_row(ctx, &mut Input::new(Vec::new()), rows, idx, touch)?;
+        }
+        Command::Domains { filters } => {
+            let rows = ctx.store.filter(&filters)?;
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No entries.".to_string()).into());
+            }
+            print_domains(rows, cfg);
+        }
+        Command::Summary { filters } => {
+            let rows = ctx.store.filter(&filters)?;
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No entries.".to_string()).into());
+            }
+            print_summary(rows, cfg);
+        }
+        Command::Dedupe { strategy, yes } => dedupe(ctx, strategy, yes)?,
+        Command::Clear { keep_categories } => clear(ctx, keep_categories)?,
+        Command::Migrate { dest: Some(dest) } => migrate(ctx, &dest)?,
+        Command::Migrate { dest: None } => upgrade(ctx)?,
+        Command::Encrypt => encrypt(ctx)?,
+        Command::Log { limit } => print_log(ctx, limit)?,
+        Command::Decrypt => decrypt(ctx)?,
+        Command::Dupes { keys } => {
+            let groups = find_dupes(&ctx.store.all()?, &keys, ctx.aliases);
+            if groups.is_empty() {
+                return Err(Failure::NoMatch("No duplicates found.".to_string()).into());
+            }
+            for g in &groups {
+                let r = &g[0].1;
+                println!("{} entries: {} | {} | {}", g.len(), r.product, r.url, cfg.price(r));
+                for (i, r) in g {
+                    println!("  --id {}  --index {}  {}", r.id, i + 1, cfg.time(&r.timestamp));
+                }
+            }
+            if !quiet {
+                let extra: usize = groups.iter().map(|g| g.len() - 1).sum();
+                println!("{} groups, {} entries beyond the first of each.", groups.len(), extra);
+            }
+        }
+        Command::Doctor { checks } => {
+            let rows = ctx.store.all()?;
+            let issues = ctx.store.issues();
+            for issue in &issues {
+                println!("line {}  {}", issue.line, issue.problems.join(", "));
+                println!("    {}", issue.raw);
+            }
+            let mut bad = 0;
+            for (i, r) in rows.iter().enumerate() {
+                let problems = checks.problems(r);
+                if problems.is_empty() {
+                    continue;
+                }
+                bad += 1;
+                println!("--id {}  --index {}  {}: {}", r.id, i + 1, r.product, problems.join(", "));
+            }
+            if !issues.is_empty() {
+                return Err(Failure::InvalidInput(format!(
+                    "{} lines could not be read as written; {} of {} entries have problems.",
+                    issues.len(),
+                    bad,
+                    rows.len()
+                ))
+                .into());
+            }
+            if bad > 0 {
+                return Err(Failure::InvalidInput(format!("{} of {} entries have problems.", bad, rows.len())).into());
+            }
+            if !quiet {
+                println!("No problems found in {} entries.", rows.len());
+            }
+        }
+        Command::Verify { json } => {
+            let v = verify(ctx)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&v)?);
+            } else {
+                print_verification(&v, cfg);
+            }
+            let bad = v.problems.len() + v.unreadable_timestamps.len();
+            if bad > 0 {
+                return Err(Failure::InvalidInput(match bad {
+                    1 => format!("1 line of {} doesn't read as written.", ctx.db),
+                    n => format!("{} lines of {} don't read as written.", n, ctx.db),
+                }).into());
+            }
+            if v.recorded_sha256.as_ref().is_some_and(|sum| *sum != v.sha256) {
+                return Err(Failure::InvalidInput(format!(
+                    "The checksum differs from the one in {}; {} was changed outside pricepeek.",
+                    checksum::sidecar_path(ctx.db),
+                    ctx.db
+                ))
+                .into());
+            }
+        }
+        Command::Stats { filters, product, include_zero, json } => {
+            let mut rows = ctx.store.filter(&filters)?;
+            if let Some(p) = product {
+                let key = ctx.aliases.key(&p);
+                rows.retain(|r| ctx.aliases.key(&r.product) == key);
+            }
+            let before = rows.len();
+            if !include_zero {
+                rows.retain(|r| r.price != Money::default());
+            }
+            let zeros = before - rows.len();
+            let stats: Vec<PriceDistribution> = group_by(rows, |r| currency_of(r, &cfg.default_currency))
+                .into_iter()
+                .filter_map(|(cur, g)| price_distribution(&g, cur))
+                .collect();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                print_stats(&stats, cfg);
+                if zeros > 0 && !quiet {
+                    println!("Ignored {} entries priced 0.00; pass --include-zero to count them.", zeros);
+                }
+            }
+            if stats.is_empty() {
+                return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+            }
+        }
+        Command::Monthly { filters, product, fill_gaps } => {
+            let mut rows = ctx.store.filter(&filters)?;
+            if let Some(p) = product {
+                rows = product_rows(rows, &p, ctx.aliases)?;
+            }
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+            }
+            print_monthly(rows, fill_gaps, cfg, quiet);
+        }
+        Command::Hist { filters, buckets, json } => {
+            let rows = ctx.store.filter(&filters)?;
+            if rows.is_empty() {
+                return Err(Failure::NoMatch("No matching entries.".to_string()).into());
+            }
+            let hists: Vec<Histogram> = group_by(rows, |r| currency_of(r, &cfg.default_currency))
+                .into_iter()
+                .map(|(currency, g)| {
+                    let prices: Vec<f64> = g.iter().map(|r| r.price.to_f64()).collect();
+                    Histogram { currency, buckets: chart::histogram(&prices, buckets) }
+                })
+                .collect();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&hists)?);
+            } else {
+                let bar = if chart::unicode_locale() { '█' } else { '#' };
+                for (i, h) in hists.iter().enumerate() {
+                    if hists.len() > 1 {
+                        if i > 0 {
+                            println!();
+                        }
+                        println!("{}:", if h.currency.is_empty() { "No currency" } else { &h.currency });
+                    }
+                    print_histogram(&h.buckets, &h.currency, bar, cfg);
+                }
+            }
+        }
+        Command::Categories { plain, tree } => {
+            let rows = ctx.store.filter(&Filters::default())?;
+            if tree {
+                print_category_tree(&rows);
+            } else if plain {
+                for (name, _, _) in category_overview(rows, cfg).into_iter().filter(|(name, _, _)| !name.is_empty()) {
+                    println!("{}", name);
+                }
+            } else {
+                print_category_overview(category_overview(rows, cfg), cfg);
+            }
+        }
+        Command::Tags { plain } => {
+            let rows = ctx.store.filter(&Filters::default())?;
+            print_counts(count_distinct(rows.iter().flat_map(|r| r.tags.iter().map(String::as_str))), plain);
+        }
+        Command::Completions { shell } => print_completions(shell),
+    }
+    Ok(())
+}
+
+/// One line with the main fields of `r`; rows not saved yet show as `#new`.
+fn format_row(r: &Row, cfg: &Config) -> String {
+    format!(
+        "#{} | {} | {} | {}{}{} | {} | {} | {}",
+        if r.id == 0 { "new".to_string() } else { r.id.to_string() },
+        r.product,
+        r.category,
+        cfg.price(r),
+        cfg.unit_price(r).map(|u| format!(" ({})", u)).unwrap_or_default(),
+        r.target.map(|t| format!(" [target {}]", format_price(t.to_f64(), &r.currency, &cfg.currency_symbol))).unwrap_or_default(),
+        r.store,
+        r.url,
+        cfg.time(&r.timestamp)
+    )
+}
+
+/// Rename `old` and its subcategories in one write. Renaming onto a category that already has
+/// entries merges the two, which needs `merge` so a typo can't silently combine categories.
+/// A category name can't be blank, contain a comma or start with `!`, which `--category` reads
+/// as a list separator and an exclusion.
+fn check_category_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(',') || name.starts_with('!') {
+        return Err(Failure::InvalidInput(format!("Invalid category name '{}'", name)).into());
+    }
+    Ok(name.to_string())
+}
+
+fn rename_category(ctx: &Ctx, old: &str, new: &str, merge: bool, yes: bool) -> Result<()> {
+    check_category_name(new)?;
+    let mut rows = ctx.store.all()?;
+    let targets: Vec<usize> = (0..rows.len()).filter(|&i| category::within(&rows[i].category, old)).collect();
+    if targets.is_empty() {
+        return Err(Failure::NoMatch(format!("No entries in category '{}'.", old.trim())).into());
+    }
+    let existing = (0..rows.len())
+        .filter(|i| !targets.contains(i) && category::within(&rows[*i].category, new))
+        .count();
+    if existing > 0 && !merge {
+        return Err(Failure::InvalidInput(format!(
+            "Category '{}' already has {} entries; pass --merge to merge '{}' into it",
+            new.trim(),
+            existing,
+            old.trim()
+        ))
+        .into());
+    }
+    if !ctx.quiet {
+        if existing > 0 {
+            println!("Merging {} entries of '{}' into '{}', which has {}.", targets.len(), old.trim(), new.trim(), existing);
+        } else {
+            println!("Renaming '{}' to '{}' in {} entries.", old.trim(), new.trim(), targets.len());
+        }
+    }
+    if ctx.asks(yes) {
+        if !io::stdin().is_terminal() {
+            return Err(Failure::InvalidInput("Refusing to rename without confirmation; pass --yes".to_string()).into());
+        }
+        if !Input::new(Vec::new()).confirm("Continue? (y/N): ")? {
+            println!("Canceled.");
+            return Ok(());
+        }
+    }
+    let mut changes = Changes::default();
+    for &i in &targets {
+        let old_row = rows[i].clone();
+        rows[i].category = category::renamed(&old_row.category, old, new).unwrap_or_default();
+        if rows[i].category != old_row.category {
+            changes.updated.push((old_row, rows[i].clone()));
+        }
+    }
+    ctx.store.commit(&rows, &changes)?;
+    if ctx.tells() {
+        println!("Updated {} rows.", changes.updated.len());
+    }
+    Ok(())
+}
+
+/// Ask for a new category for each uncategorized entry, or each entry within `from`, one at a
+/// time: Enter or `s` skips an entry, `q` stops early. Whatever was answered is saved in one
+/// write, followed by a summary of the moves.
+fn recategorize(ctx: &Ctx, from: Option<&str>) -> Result<()> {
+    let cfg = ctx.cfg;
+    let mut rows = ctx.store.all()?;
+    let targets: Vec<usize> = (0..rows.len())
+        .filter(|&i| match from {
+            Some(from) => category::within(&rows[i].category, from),
+            None => rows[i].category.trim().is_empty(),
+        })
+        .collect();
+    if targets.is_empty() {
+        let msg = match from {
+            Some(from) => format!("No entries in category '{}'.", from.trim()),
+            None => "No uncategorized entries.".to_string(),
+        };
+        return Err(Failure::NoMatch(msg).into());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(Failure::InvalidInput("Without a terminal, pass --from and --to".to_string()).into());
+    }
+    let mut known: Vec<String> = Vec::new();
+    for r in &rows {
+        let c = r.category.trim();
+        if !c.is_empty() && !known.iter().any(|k| matches_ignore_case(k, c)) {
+            known.push(c.to_string());
+        }
+    }
+    enum Answer {
+        Skip,
+        Stop,
+        Move(String),
+    }
+    let mut input = Input::new(known);
+    let mut changes = Changes::default();
+    for (n, &i) in targets.iter().enumerate() {
+        let r = &rows[i];
+        println!("[{}/{}] #{} {} | {} | {} | {}", n + 1, targets.len(), r.id, r.product, cfg.price(r), r.store, r.url);
+        let current = if r.category.is_empty() { "(none)" } else { r.category.as_str() };
+        let answer = input.valid(&format!("Category for it, now {} (s to skip, q to stop)", current), "", |a| {
+            match a.trim() {
+                "" | "s" | "S" => Ok(Answer::Skip),
+                "q" | "Q" => Ok(Answer::Stop),
+                a => check_category_name(a).map(Answer::Move),
+            }
+        })?;
+        match answer {
+            Answer::Skip => continue,
+            Answer::Stop => break,
+            Answer::Move(c) if c == rows[i].category => continue,
+            Answer::Move(c) => {
+                input.remember(&c);
+                let old = rows[i].clone();
+                rows[i].category = c;
+                changes.updated.push((old, rows[i].clone()));
+            }
+        }
+    }
+    if changes.updated.is_empty() {
+        println!("Nothing changed.");
+        return Ok(());
+    }
+    ctx.store.commit(&rows, &changes)?;
+    if ctx.tells() {
+        for (old, new) in &changes.updated {
+            let was = if old.category.is_empty() { "(none)" } else { old.category.as_str() };
+            println!("#{} {}: {} -> {}", old.id, old.product, was, new.category);
+        }
+        println!("Moved {} of {} entries.", changes.updated.len(), targets.len());
+    }
+    Ok(())
+}
+
+fn run_target(ctx: &Ctx, action: TargetAction) -> Result<()> {
+    let (product, target) = match action {
+        TargetAction::Set { product, price } => (product, Some(price)),
+        TargetAction::Clear { product } => (product, None),
+        TargetAction::List => {
+            let targets = target_progress(ctx.store.filter(&Filters::default())?, ctx.aliases);
+            if targets.is_empty() {
+                return Err(Failure::NoMatch("No products have a target.".to_string()).into());
+            }
+            print_targets(&targets, ctx.cfg);
+            return Ok(());
+        }
+    };
+    let n = set_target(ctx, &product, target)?;
+    if ctx.tells() {
+        match target {
+            Some(t) => println!("Target {} set on {} rows.", t, n),
+            None => println!("Target cleared on {} rows.", n),
+        }
+    }
+    Ok(())
+}
+
+fn run_alias(ctx: &Ctx, action: AliasAction) -> Result<()> {
+    let mut aliases = ctx.aliases.clone();
+    match action {
+        AliasAction::Add { variant, canonical } => {
+            aliases.add(&variant, &canonical)?;
+            if !ctx.may_write(format_args!("add alias: {} -> {}", variant.trim(), aliases.canonical(&variant))) {
+                return Ok(());
+            }
+            aliases.save(ctx.db)?;
+            if !ctx.quiet {
+                println!("'{}' now groups with '{}'.", variant.trim(), aliases.canonical(&variant));
+            }
+        }
+        AliasAction::Remove { variant } => {
+            if !aliases.remove(&variant) {
+                return Err(Failure::NoMatch(format!("No alias '{}'.", variant.trim())).into());
+            }
+            if !ctx.may_write(format_args!("remove alias: {}", variant.trim())) {
+                return Ok(());
+            }
+            aliases.save(ctx.db)?;
+        }
+        AliasAction::List => {
+            for (variant, canonical) in aliases.iter() {
+                println!("{} -> {}", variant, canonical);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_backup(ctx: &Ctx, action: BackupAction) -> Result<()> {
+    let backups = Backups::new(ctx.db, ctx.cfg.backup_keep);
+    match action {
+        BackupAction::Now => {
+            if Path::new(ctx.db).is_dir() {
+                return Err(Failure::InvalidInput(format!("{} is a directory; backups cover single-file databases", ctx.db)).into());
+            }
+            if !ctx.may_write(format_args!("back up {} to {}", ctx.db, backups.dir().display())) {
+                return Ok(());
+            }
+            match backups.take()? {
+                Some(path) if !ctx.quiet => println!("Saved a backup to {}.", path.display()),
+                Some(_) => {}
+                None if !ctx.quiet => println!("The newest backup already matches {}; nothing to do.", ctx.db),
+                None => {}
+            }
+        }
+        BackupAction::List => {
+            let list = backups.list()?;
+            if list.is_empty() {
+                return Err(Failure::NoMatch(format!("No backups in {}", backups.dir().display())).into());
+            }
+            for b in list {
+                println!("{}  {:>9} bytes  {}", b.taken.format("%Y-%m-%d %H:%M:%S"), b.size, b.path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replace the database with the rows of the backup `file`, or of one picked from the automatic
+/// backups, after showing how it differs and confirming. The current contents are backed up
+/// first, so a restore can itself be undone; a backup in an older layout is written in the
+/// current one.
+fn restore(ctx: &Ctx, file: Option<String>, yes: bool) -> Result<()> {
+    let backups = Backups::new(ctx.db, ctx.cfg.backup_keep);
+    let file = match file {
+        Some(file) => PathBuf::from(file),
+        None => match pick_backup(ctx, &backups)? {
+            Some(path) => path,
+            None => {
+                println!("Canceled.");
+                return Ok(());
+            }
+        },
+    };
+    let name = file.to_string_lossy().into_owned();
+    if !file.is_file() {
+        return Err(Failure::InvalidInput(format!("No backup file {}", name)).into());
+    }
+    let source = store::open(&name, ctx.backend, ctx.cipher.cloned())?;
+    let rows = source.all()?;
+    let current = ctx.store.all()?;
+    let diff = backup::Diff::new(&current, &rows);
+    if diff.is_empty() {
+        if !ctx.quiet {
+            println!("{} already has the contents of {}; nothing to restore.", ctx.db, name);
+        }
+        return Ok(());
+    }
+    if !ctx.quiet {
+        println!(
+            "{} has {} entries; compared with the current {}, restoring brings back {}, removes {} and changes {}.",
+            name,
+            rows.len(),
+            current.len(),
+            diff.added,
+            diff.removed,
+            diff.changed
+        );
+    }
+    if ctx.asks(yes) {
+        if !io::stdin().is_terminal() {
+            return Err(Failure::InvalidInput("Refusing to restore without a terminal to confirm; pass --yes".to_string()).into());
+        }
+        if !Input::new(Vec::new()).confirm("Replace the database with this backup? (y/N): ")? {
+            println!("Canceled.");
+            return Ok(());
+        }
+    }
+    if ctx.may_write(format_args!("back up {} to {}", ctx.db, backups.dir().display())) {
+        if let Some(saved) = backups.take()? {
+            if !ctx.quiet {
+                println!("Saved the current database to {}.", saved.display());
+            }
+        }
+    }
+    let upgraded = source.outdated()?;
+    ctx.store.overwrite(&rows)?;
+    if ctx.tells() {
+        match upgraded {
+            Some(old) => println!("Restored {} entries from {}, upgraded from the {} layout.", rows.len(), name, old),
+            None => println!("Restored {} entries from {}.", rows.len(), name),
+        }
+    }
+    Ok(())
+}
+
+/// List the automatic backups with their entry counts and ask for one; `None` when the
+/// question is left empty.
+fn pick_backup(ctx: &Ctx, backups: &Backups) -> Result<Option<PathBuf>> {
+    let list = backups.list()?;
+    if list.is_empty() {
+        return Err(Failure::NoMatch(format!("No backups in {}; give the file to restore", backups.dir().display())).into());
+    }
+    for (i, b) in list.iter().enumerate() {
+        let path = b.path.to_string_lossy();
+        let entries = store::open(&path, ctx.backend, ctx.cipher.cloned()).and_then(|s| s.all()).map_or_else(|_| "?".to_string(), |rows| rows.len().to_string());
+        println!("{:>3}. {}  {:>6} entries  {}", i + 1, b.taken.format("%Y-%m-%d %H:%M:%S"), entries, path);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(Failure::InvalidInput("No terminal to pick a backup; give the file to restore".to_string()).into());
+    }
+    let answer = Input::new(Vec::new()).line(&format!("Restore which backup? (1-{}, Enter to cancel): ", list.len()))?;
+    if answer.trim().is_empty() {
+        return Ok(None);
+    }
+    match answer.trim().parse::<usize>().ok().and_then(|n| n.checked_sub(1)).and_then(|i| list.get(i)) {
+        Some(b) => Ok(Some(b.path.clone())),
+        None => Err(Failure::InvalidInput(format!("No backup number '{}'", answer.trim())).into()),
+    }
+}
+
+fn run_basket(ctx: &Ctx, action: BasketAction) -> Result<()> {
+    let mut basket = Basket::load(ctx.db)?;
+    match action {
+        BasketAction::Add { ids } => {
+            let rows = ctx.store.all()?;
+            if let Some(missing) = ids.iter().find(|id| !rows.iter().any(|r| r.id == **id)) {
+                return Err(Failure::NoMatch(format!("No entry with id {}.", missing)).into());
+            }
+            let added = ids.iter().filter(|id| basket.add(**id)).count();
+            if !ctx.may_write(format_args!("add {} entries to the basket", added)) {
+                return Ok(());
+            }
+            basket.save(ctx.db)?;
+            if !ctx.quiet {
+                println!("Added {} entries; the basket holds {}.", added, basket.ids().len());
+            }
+        }
+        BasketAction::Remove { ids } => {
+            let removed = ids.iter().filter(|id| basket.remove(**id)).count();
+            if removed == 0 {
+                return Err(Failure::NoMatch("None of these entries are in the basket.".to_string()).into());
+            }
+            if !ctx.may_write(format_args!("remove {} entries from the basket", removed)) {
+                return Ok(());
+            }
+            basket.save(ctx.db)?;
+        }
+        BasketAction::Clear => {
+            if !ctx.may_write(format_args!("remove {} entries from the basket", basket.ids().len())) {
+                return Ok(());
+            }
+            basket.clear();
+            basket.save(ctx.db)?;
+            if !ctx.quiet {
+                println!("Basket emptied.");
+            }
+        }
+        BasketAction::List => {
+            let (items, missing) = basket_items(&ctx.store.all()?, basket.ids(), ctx.aliases, ctx.cfg);
+            if items.is_empty() && missing.is_empty() {
+                return Err(Failure::NoMatch("The basket is empty.".to_string()).into());
+            }
+            for r in &items {
+                println!("{}", format_row(r, ctx.cfg));
+            }
+            for id in &missing {
+                eprintln!("#{} is no longer in the database", id);
+            }
+            for (cur, g) in group_by(items, |r| currency_of(r, &ctx.cfg.default_currency)) {
+                let total = g.iter().fold(Money::default(), |sum, r| sum + r.landed());
+                println!("Total: {}", format_price(total.to_f64(), &cur, &ctx.cfg.currency_symbol));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One row per basket product and currency: the cheapest available row of that product (see
+/// `product_identity`), or the basket entry itself when none is available. Also returns the
+/// basket ids that no longer exist.
+fn basket_items(rows: &[Row], ids: &[u64], aliases: &Aliases, cfg: &Config) -> (Vec<Row>, Vec<u64>) {
+    let identity = product_identity(rows, aliases);
+    let key = |r: &Row| format!("{}\0{}", identity(r), currency_of(r, &cfg.default_currency));
+    let (mut items, mut missing, mut seen) = (Vec::new(), Vec::new(), Vec::new());
+    for id in ids {
+        let Some(picked) = rows.iter().find(|r| r.id == *id) else {
+            missing.push(*id);
+            continue;
+        };
+        let k = key(picked);
+        if seen.contains(&k) {
+            continue;
+        }
+        let offers: Vec<Row> = rows.iter().filter(|r| !r.archived && key(r) == k).cloned().collect();
+        items.push(cheapest(not_purchased(in_stock(offers))).unwrap_or_else(|| picked.clone()));
+        seen.push(k);
+    }
+    (items, missing)
+}
+
+/// One line per observation, with the change since the previous one in the same currency.
+fn print_history(group: &[Row], by_store: bool, ctx: &Ctx) {
+    let first = &group[0];
+    let name = ctx.aliases.canonical(&first.product);
+    match (by_store, first.store.is_empty()) {
+        (true, false) => println!("{} ({})", name, first.store),
+        (true, true) => println!("{} ({})", name, first.url),
+        _ => println!("{}", name),
+    }
+    let mut prev: Option<&Row> = None;
+    for r in group {
+        let delta = match prev {
+            Some(p) if p.currency == r.currency => format!("  {}", price_change(p.price, r.price)),
+            _ => String::new(),
+        };
+        let variant = if r.product.trim() == name.trim() { String::new() } else { format!("  as \"{}\"", r.product) };
+        println!("  {}  #{}  {}{}{}", ctx.cfg.time(&r.timestamp), r.id, ctx.cfg.price(r), delta, variant);
+        prev = Some(r);
+    }
+}
+
+/// Search hits with their 1-based file position, as `delete --index` takes it, and their id.
+fn print_matches(hits: &[(usize, &Row)], cfg: &Config) {
+    for (i, r) in hits {
+        let archived = if r.archived { " (archived)" } else { "" };
+        println!("{:>4}  {}{}", i + 1, format_row(r, cfg), archived);
+    }
+}
+
+/// Fuzzy hits like `print_matches`, with their similarity as a percentage.
+fn print_fuzzy_matches(hits: &[(usize, &Row, f64)], cfg: &Config) {
+    for (i, r, score) in hits {
+        println!("{:>4}  {:>3.0}%  {}", i + 1, score * 100.0, format_row(r, cfg));
+    }
+}
+
+/// Whole days since `timestamp`, or `None` when it doesn't parse.
+fn age_days(timestamp: &str) -> Option<i64> {
+    let t = DateTime::parse_from_rfc3339(timestamp).ok()?;
+    Some((Utc::now() - t.with_timezone(&Utc)).num_days())
+}
+
+/// How long ago `timestamp` was, e.g. "just now", "5 min ago", "2 h ago" or "3 d ago";
+/// `None` when it doesn't parse.
+fn relative_age(timestamp: &str) -> Option<String> {
+    let t = DateTime::parse_from_rfc3339(timestamp).ok()?;
+    let secs = (Utc::now() - t.with_timezone(&Utc)).num_seconds();
+    Some(match secs {
+        s if s < 0 => "in the future".to_string(),
+        s if s < 60 => "just now".to_string(),
+        s if s < 3600 => format!("{} min ago", s / 60),
+        s if s < 86_400 => format!("{} h ago", s / 3600),
+        s if s < 86_400 * 60 => format!("{} d ago", s / 86_400),
+        s if s < 86_400 * 730 => format!("{} mo ago", s / (86_400 * 30)),
+        s => format!("{} y ago", s / (86_400 * 365)),
+    })
+}
+
+/// The priciest entry overall, then per product and currency the lowest and highest price with
+/// the dates they were seen, widest spread first. Products seen only once are counted in a footnote.
+fn print_spread(rows: Vec<Row>, ctx: &Ctx) {
+    let cfg = ctx.cfg;
+    if let Some(top) = rows.iter().max_by_key(|r| r.price) {
+        println!("Most expensive: {}\n", format_row(top, cfg));
+    }
+    let (spreads, single) = report::spreads(rows, ctx.aliases, &cfg.default_currency);
+    if !spreads.is_empty() {
+        let mut lines = vec![["product", "entries", "lowest", "lowest seen", "highest", "highest seen", "spread", "%"].map(String::from).to_vec()];
+        lines.extend(spreads.iter().map(|s| {
+            let fmt = |m: Money| format_price(m.to_f64(), &s.low.currency, &cfg.currency_symbol);
+            vec![
+                ctx.aliases.canonical(&s.low.product).to_string(),
+                s.entries.to_string(),
+                fmt(s.low.price),
+                cfg.date(&s.low.timestamp),
+                fmt(s.high.price),
+                cfg.date(&s.high.timestamp),
+                fmt(s.spread),
+                format!("{:.1}%", s.pct),
+            ]
+        }));
+        print!("{}", table::render_columns(&lines, 1));
+    }
+    if single > 0 && !ctx.quiet {
+        println!("{} products with a single observation not shown.", single);
+    }
+}
+
+/// Per product seen more than once, the latest price against the highest ever recorded, biggest
+/// saving first and products at their peak last, then the totals of both per currency. Rows whose
+/// timestamp can't be read can't be placed in time, so they are listed separately instead.
+fn print_savings(rows: Vec<Row>, min_savings: f64, ctx: &Ctx) -> Result<()> {
+    let cfg = ctx.cfg;
+    let (dated, undated): (Vec<Row>, Vec<Row>) =
+        rows.into_iter().partition(|r| DateTime::parse_from_rfc3339(&r.timestamp).is_ok());
+    let identity = product_identity(&dated, ctx.aliases);
+    let groups = group_by(dated.clone(), |r| format!("{}\0{}", identity(r), currency_of(r, &cfg.default_currency)));
+    let mut found: Vec<(f64, Vec<String>)> = Vec::new();
+    // Currency, as stored and as grouped, with the sum of latest and of peak prices.
+    let mut totals: Vec<(String, String, Money, Money)> = Vec::new();
+    let mut single = 0;
+    for (_, mut g) in groups {
+        if g.len() < 2 {
+            single += 1;
+            continue;
+        }
+        g.sort_by(|a, b| compare_timestamps(&a.timestamp, &b.timestamp));
+        let latest = &g[g.len() - 1];
+        // The last of equal maximums, so the date shown is the most recent time at that price.
+        let Some(peak) = g.iter().max_by_key(|r| r.price) else {
+            continue;
+        };
+        let saving = peak.price - latest.price;
+        let pct = if peak.price.units() > 0 { saving.to_f64() / peak.price.to_f64() * 100.0 } else { 0.0 };
+        if pct < min_savings {
+            continue;
+        }
+        let currency = currency_of(latest, &cfg.default_currency);
+        match totals.iter_mut().find(|t| t.1 == currency) {
+            Some(t) => {
+                t.2 = t.2 + latest.price;
+                t.3 = t.3 + peak.price;
+            }
+            None => totals.push((latest.currency.clone(), currency, latest.price, peak.price)),
+        }
+        let fmt = |m: Money| format_price(m.to_f64(), &latest.currency, &cfg.currency_symbol);
+        let (saving_text, pct_text, rank) = if saving.units() > 0 {
+            (fmt(saving), format!("{:.1}%", pct), pct)
+        } else {
+            ("at peak".to_string(), String::new(), -1.0)
+        };
+        found.push((
+            rank,
+            vec![
+                ctx.aliases.canonical(&latest.product).to_string(),
+                g.len().to_string(),
+                fmt(latest.price),
+                cfg.date(&latest.timestamp),
+                fmt(peak.price),
+                cfg.date(&peak.timestamp),
+                saving_text,
+                pct_text,
+            ],
+        ));
+    }
+    if found.is_empty() && undated.is_empty() {
+        let msg = if min_savings > 0.0 {
+            format!("No products at least {}% below their peak.", min_savings)
+        } else {
+            "No products with more than one observation.".to_string()
+        };
+        return Err(Failure::NoMatch(msg).into());
+    }
+    found.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    if !found.is_empty() {
+        let mut lines = vec![["product", "entries", "latest", "latest seen", "peak", "peak seen", "saving", "%"].map(String::from).to_vec()];
+        lines.extend(found.into_iter().map(|(_, line)| line));
+        print!("{}", table::render_columns(&lines, 1));
+    }
+    for (currency, _, today, peak) in &totals {
+        let fmt = |m: Money| format_price(m.to_f64(), currency, &cfg.currency_symbol);
+        let saving = *peak - *today;
+        let pct = if peak.units() > 0 { saving.to_f64() / peak.to_f64() * 100.0 } else { 0.0 };
+        println!("Everything today: {}, at peak: {}, saving {} ({:.1}%)", fmt(*today), fmt(*peak), fmt(saving), pct);
+    }
+    if !undated.is_empty() {
+        if !ctx.quiet {
+            println!("Undated (timestamp can't be read):");
+        }
+        for r in &undated {
+            println!("  {}", format_row(r, cfg));
+        }
+    }
+    if single > 0 && !ctx.quiet {
+        println!("{} products with a single observation not shown.", single);
+    }
+    Ok(())
+}
+
+/// One line per year and month of the recorded time (in the offset it was stored with), per
+/// currency. Months in between without entries are skipped unless `fill_gaps` is set.
+fn print_monthly(rows: Vec<Row>, fill_gaps: bool, cfg: &Config, quiet: bool) {
+    let mut undated = 0;
+    let groups = group_by(rows, |r| currency_of(r, &cfg.default_currency));
+    let several = groups.len() > 1;
+    for (currency, g) in groups {
+        let mut months: BTreeMap<(i32, u32), Vec<Row>> = BTreeMap::new();
+        for r in g {
+            match DateTime::parse_from_rfc3339(&r.timestamp) {
+                Ok(t) => months.entry((t.year(), t.month())).or_default().push(r),
+                Err(_) => undated += 1,
+            }
+        }
+        let (Some(&first), Some(&last)) = (months.keys().next(), months.keys().next_back()) else {
+            continue;
+        };
+        if several && !quiet {
+            println!("Prices in {}:", currency);
+        }
+        let mut lines = vec![["month", "entries", "average", "lowest"].map(String::from).to_vec()];
+        let mut month = first;
+        while month <= last {
+            match months.get(&month).and_then(|rows| price_stats(rows).map(|s| (rows, s))) {
+                Some((rows, stats)) => {
+                    let fmt = |p: f64| format_price(p, &rows[0].currency, &cfg.currency_symbol);
+                    lines.push(vec![
+                        format!("{}-{:02}", month.0, month.1),
+                        stats.count.to_string(),
+                        fmt(stats.mean),
+                        fmt(stats.min.to_f64()),
+                    ]);
+                }
+                None if fill_gaps => lines.push(vec![format!("{}-{:02}", month.0, month.1), "0".to_string(), "-".to_string(), "-".to_string()]),
+                None => {}
+            }
+            month = if month.1 == 12 { (month.0 + 1, 1) } else { (month.0, month.1 + 1) };
+        }
+        print!("{}", table::render_columns(&lines, 1));
+    }
+    if undated > 0 {
+        eprintln!("warning: left out {} entries whose timestamp could not be read", undated);
+    }
+}
+
+/// Rows ranked by unit price within each currency and base unit; equal unit prices go by
+/// price, then id. Rows without any quantity are listed last, by price.
+fn print_value(rows: Vec<Row>, cfg: &Config, quiet: bool) {
+    let (sized, unsized_rows): (Vec<Row>, Vec<Row>) = rows.into_iter().partition(|r| r.unit_price_or_guess().is_some());
+    let key = |r: &Row| {
+        let unit = r.unit_price_or_guess().map(|(_, u, _)| u).unwrap_or_default();
+        let cur = currency_of(r, &cfg.default_currency);
+        if cur.is_empty() { format!("per {}", unit) } else { format!("{} per {}", cur, unit) }
+    };
+    let per_unit = |r: &Row| r.unit_price_or_guess().map_or(0.0, |(p, _, _)| p);
+    for (heading, mut g) in group_by(sized, key) {
+        g.sort_by(|a, b| {
+            per_unit(a).partial_cmp(&per_unit(b)).unwrap_or(std::cmp::Ordering::Equal).then(a.price.cmp(&b.price)).then(a.id.cmp(&b.id))
+        });
+        if !quiet {
+            println!("Best value {}:", heading);
+        }
+        let mut lines = Vec::new();
+        for (i, r) in g.iter().enumerate() {
+            let (p, unit, guessed) = r.unit_price_or_guess().unwrap_or_default();
+            let note = if guessed { "size from name" } else { "" };
+            lines.push(vec![
+                format!("{}.", i + 1),
+                format!("#{}", r.id),
+                r.product.clone(),
+                cfg.price(r),
+                format!("{}/{}", format_price(p, &r.currency, &cfg.currency_symbol), unit),
+                note.to_string(),
+            ]);
+        }
+        print!("{}", table::render_columns(&lines, 3));
+        println!();
+    }
+    if !unsized_rows.is_empty() {
+        if !quiet {
+            println!("Without a quantity:");
+        }
+        let mut rest = unsized_rows;
+        rest.sort_by(|a, b| a.price.cmp(&b.price).then(a.id.cmp(&b.id)));
+        let lines: Vec<Vec<String>> = rest.iter().map(|r| vec![format!("#{}", r.id), r.product.clone(), cfg.price(r)]).collect();
+        print!("{}", table::render_columns(&lines, 2));
+    }
+}
+
+fn print_offer(best: &Row, next: Option<&Row>, ctx: &Ctx) {
+    let cfg = ctx.cfg;
+    let place = |r: &Row| match (r.store.is_empty(), r.url.is_empty()) {
+        (false, _) => r.store.clone(),
+        (true, false) => r.url.clone(),
+        (true, true) => "an unnamed store".to_string(),
+    };
+    let fmt = |m: Money| format_price(m.to_f64(), &best.currency, &cfg.currency_symbol);
+    let runner_up = match next {
+        Some(n) => {
+            let more = n.landed() - best.landed();
+            let pct = match best.landed().units() {
+                c if c > 0 => format!(" (+{:.1}%)", more.to_f64() / best.landed().to_f64() * 100.0),
+                _ => String::new(),
+            };
+            format!("{} costs {} more{}", place(n), fmt(more), pct)
+        }
+        None => "only one offer".to_string(),
+    };
+    let url = if best.url.is_empty() || best.store.is_empty() { String::new() } else { format!(" ({})", best.url) };
+    println!("{} | {} at {}{} | {}", ctx.aliases.canonical(&best.product), fmt(best.landed()), place(best), url, runner_up);
+}
+
+/// Sparkline of one history group with its price range and first and last dates.
+fn print_chart(group: &[Row], width: usize, levels: &[char], ctx: &Ctx) {
+    let (first, last) = (&group[0], &group[group.len() - 1]);
+    let name = ctx.aliases.canonical(&first.product);
+    if group.len() < 2 {
+        println!("{}: only one price so far ({}), nothing to chart yet.", name, ctx.cfg.price(first));
+        return;
+    }
+    let prices: Vec<f64> = group.iter().map(|r| r.price.to_f64()).collect();
+    let line = chart::sparkline(&prices, width, levels);
+    let low = group.iter().map(|r| r.price).min().unwrap_or_default();
+    let high = group.iter().map(|r| r.price).max().unwrap_or_default();
+    let fmt = |m: Money| format_price(m.to_f64(), &first.currency, &ctx.cfg.currency_symbol);
+    println!("{}: {} prices, low {}, high {}", name, group.len(), fmt(low), fmt(high));
+    if group.iter().any(|r| r.currency != first.currency) {
+        println!("(prices are in different currencies)");
+    }
+    println!("{}", line);
+    let (from, to) = (ctx.cfg.date(&first.timestamp), ctx.cfg.date(&last.timestamp));
+    let span = line.chars().count();
+    if span > from.len() + to.len() {
+        println!("{}{:>w$}", from, to, w = span - from.len());
+    } else {
+        println!("{} to {}", from, to);
+    }
+}
+
+fn print_targets(targets: &[(Row, Money)], cfg: &Config) {
+    let mut lines = vec![["product", "target", "best", "gap"].map(String::from).to_vec()];
+    for (best, target) in targets {
+        let fmt = |m: Money| format_price(m.to_f64(), &best.currency, &cfg.currency_symbol);
+        let gap = best.price - *target;
+        let gap = if gap.units() > 0 { format!("+{}", fmt(gap)) } else { fmt(gap) };
+        lines.push(vec![best.product.clone(), fmt(*target), fmt(best.price), gap]);
+    }
+    print!("{}", table::render_columns(&lines, 1));
+}
+
+fn print_under_target(hits: &[(Row, f64)], cfg: &Config) {
+    for (r, pct) in hits {
+        println!("{} | {:.1}% below target", format_row(r, cfg), pct);
+    }
+}
+
+fn print_stats(stats: &[PriceDistribution], cfg: &Config) {
+    for (i, d) in stats.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let fmt = |p: f64| format_price(p, &d.currency, &cfg.currency_symbol);
+        if stats.len() > 1 {
+            println!("{}:", if d.currency.is_empty() { "No currency" } else { &d.currency });
+        }
+        println!("Entries:   {}", d.count);
+        println!("Lowest:    {}", fmt(d.min));
+        println!("25th pct:  {}", fmt(d.p25));
+        println!("Median:    {}", fmt(d.median));
+        println!("75th pct:  {}", fmt(d.p75));
+        println!("Highest:   {}", fmt(d.max));
+        println!("Average:   {}", fmt(d.mean));
+        println!("Std dev:   {}", d.stddev.map_or("n/a (single entry)".to_string(), |s| format!("{:.2}", s)));
+    }
+}
+
+/// One aligned line per category (case-insensitive) and currency, sorted by name, then a total
+/// per currency. Currencies are never mixed in one line.
+fn print_summary(rows: Vec<Row>, cfg: &Config) {
+    let line = |name: &str, stats: &PriceStats, currency: &str| {
+        let fmt = |p: f64| format_price(p, currency, &cfg.currency_symbol);
+        vec![name.to_string(), stats.count.to_string(), fmt(stats.min.to_f64()), fmt(stats.mean), fmt(stats.max.to_f64())]
+    };
+    let mut lines = vec![["category", "entries", "cheapest", "average", "highest"].map(String::from).to_vec()];
+    let groups = group_by(rows.clone(), |r| format!("{}\0{}", text::fold(&r.category), currency_of(r, &cfg.default_currency)));
+    for g in groups.values() {
+        let name = if g[0].category.trim().is_empty() { "(uncategorized)" } else { g[0].category.trim() };
+        if let Some(stats) = price_stats(g) {
+            lines.push(line(name, &stats, &currency_of(&g[0], &cfg.default_currency)));
+        }
+    }
+    for (_, g) in group_by(rows, |r| currency_of(r, &cfg.default_currency)) {
+        if let Some(stats) = price_stats(&g) {
+            lines.push(line("(total)", &stats, &currency_of(&g[0], &cfg.default_currency)));
+        }
+    }
+    print!("{}", table::render_columns(&lines, 1));
+}
+
+/// One aligned line per URL domain and currency, sorted by domain; rows without a usable URL
+/// count under "(no domain)".
+fn print_domains(rows: Vec<Row>, cfg: &Config) {
+    let mut lines = vec![["domain", "entries", "average"].map(String::from).to_vec()];
+    for (_, g) in group_by(rows, |r| format!("{}\0{}", domain_label(r), currency_of(r, &cfg.default_currency))) {
+        if let Some(stats) = price_stats(&g) {
+            let average = format_price(stats.mean, &currency_of(&g[0], &cfg.default_currency), &cfg.currency_symbol);
+            lines.push(vec![domain_label(&g[0]), stats.count.to_string(), average]);
+        }
+    }
+    print!("{}", table::render_columns(&lines, 1));
+}
+
+/// Each category node indented under its parent, with the number of rows at or below it.
+fn print_category_tree(rows: &[Row]) {
+    let mut nodes: Vec<(Vec<String>, String, usize)> = Vec::new();
+    for r in rows {
+        let names: Vec<&str> = r.category.split('/').map(str::trim).filter(|s| !s.is_empty()).collect();
+        for depth in 1..=names.len() {
+            let path: Vec<String> = names[..depth].iter().map(|s| text::fold(s)).collect();
+            match nodes.iter_mut().find(|(p, _, _)| *p == path) {
+                Some((_, _, n)) => *n += 1,
+                None => nodes.push((path, names[depth - 1].to_string(), 1)),
+            }
+        }
+    }
+    nodes.sort_by(|a, b| a.0.cmp(&b.0));
+    for (path, name, n) in nodes {
+        println!("{}{} ({})", "  ".repeat(path.len() - 1), name, n);
+    }
+}
+
+fn print_category_overview(categories: Vec<(String, usize, Vec<Row>)>, cfg: &Config) {
+    let mut lines = vec![["category", "entries", "cheapest"].map(String::from).to_vec()];
+    for (name, count, cheapest) in categories {
+        let prices: Vec<String> = cheapest.iter().map(|r| cfg.price(r)).collect();
+        let name = if name.is_empty() { "(none)".to_string() } else { name };
+        lines.push(vec![name, count.to_string(), prices.join(", ")]);
+    }
+    print!("{}", table::render_columns(&lines, 1));
+}
+
+/// One line per bucket, e.g. `5.00–7.50 | ████ 4`, the longest bar 40 characters wide.
+fn print_histogram(buckets: &[chart::Bucket], currency: &str, bar: char, cfg: &Config) {
+    const BAR_WIDTH: usize = 40;
+    let most = buckets.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+    let ranges: Vec<String> = buckets
+        .iter()
+        .map(|b| format!("{}–{}", format_price(b.from, currency, &cfg.currency_symbol), format_price(b.to, currency, &cfg.currency_symbol)))
+        .collect();
+    let w = ranges.iter().map(|r| r.chars().count()).max().unwrap_or(0);
+    for (b, range) in buckets.iter().zip(&ranges) {
+        // Any non-empty bucket gets at least one mark, however skewed the counts are.
+        let len = (b.count * BAR_WIDTH).div_ceil(most);
+        println!("{:>w$} | {} {}", range, bar.to_string().repeat(len), b.count, w = w);
+    }
+}
+
+fn print_counts(counts: Vec<(String, usize)>, plain: bool) {
+    for (name, n) in counts {
+        if plain {
+            println!("{}", name);
+        } else {
+            println!("{} ({})", name, n);
+        }
+    }
+}
+
+fn print_row(r: &Row, cfg: &Config) {
+    println!("{}", format_row(r, cfg));
+}
+
+/// One field per line, with multi-line notes indented under their label.
+fn print_details(r: &Row, cfg: &Config) {
+    println!("Id:        {}", r.id);
+    println!("Product:   {}", r.product);
+    println!("Category:  {}", r.category);
+    println!("Price:     {}", cfg.price(r));
+    if let (Some(orig), Some(pct)) = (r.original_price, r.discount()) {
+        println!("Regular:   {} ({:.0}% off)", format_price(orig.to_f64(), &r.currency, &cfg.currency_symbol), pct);
+    }
+    if r.shipping != Money::default() {
+        println!("Shipping:  {}", format_price(r.shipping.to_f64(), &r.currency, &cfg.currency_symbol));
+    }
+    if let Some(t) = r.target {
+        let hit = if r.under_target() { " (reached)" } else { "" };
+        println!("Target:    {}{}", format_price(t.to_f64(), &r.currency, &cfg.currency_symbol), hit);
+    }
+    if let Some(q) = r.quantity {
+        println!("Quantity:  {} {}", q, r.unit);
+        println!("Per unit:  {}", cfg.unit_price(r).unwrap_or_default());
+    }
+    println!("Store:     {}", r.store);
+    if !r.sku.is_empty() {
+        println!("SKU:       {}", r.sku);
+    }
+    println!("Status:    {}", r.status.as_str());
+    println!("Priority:  {}", r.priority);
+    println!("Source:    {}", r.source);
+    if r.archived {
+        println!("Archived:  yes");
+    }
+    if r.purchased() {
+        println!("Purchased: {}", r.purchased_at);
+    }
+    println!("URL:       {}", r.url);
+    println!("Tags:      {}", r.tags.join(", "));
+    println!("Recorded:  {}", cfg.time(&r.timestamp));
+    if !r.extra.is_empty() {
+        println!("Extra:");
+        for (k, v) in r.extras() {
+            println!("  {} = {}", k, v);
+        }
+    }
+    if !r.notes.is_empty() {
+        println!("Notes:");
+        for line in r.notes.lines() {
+            println!("  {}", line);
+        }
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+/// The subcommand path of `matches`, e.g. `edit` or `target set`, as journal entries name it.
+fn op_name(matches: &ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut m = matches;
+    while let Some((name, sub)) = m.subcommand() {
+        names.push(name);
+        m = sub;
+    }
+    names.join(" ")
+}
+
+fn run() -> Result<()> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let level = match cli.verbose {
+        0 => log::LevelFilter::Off,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new().filter_level(level).format_timestamp(None).init();
+    if let Some(Command::Completions { shell }) = cli.command {
+        print_completions(shell);
+        return Ok(());
+    }
+    let mut cfg = Config::load()?;
+    cfg.utc |= cli.utc;
+    csvfile::set_sync_writes(cli.sync_writes || cfg.sync_writes);
+    let (db, default_db) = match cli.db.or_else(|| cfg.db_path.clone()) {
+        Some(db) => (db, false),
+        None => (default_db_path(), true),
+    };
+    let (db, backend) = dir_slash(db.as_str(), cli.backend);
+    let read_only = cli.read_only || cfg.read_only;
+    // Refused before anything is opened, so not even a lock or backup file gets written.
+    if read_only && cli.command.as_ref().is_some_and(Command::writes) {
+        return Err(Failure::InvalidInput(format!("`{}` isn't available: {} is open read-only", op_name(&matches), db)).into());
+    }
+    // The passphrase is asked for up front whenever the file is encrypted, so a wrong one fails
+    // before anything else happens; `decrypt` on a plain file needs none.
+    let sealed = crypt::file_is_encrypted(db);
+    let encrypting = !read_only && (cli.encrypted || cfg.encrypted || matches!(cli.command, Some(Command::Encrypt)));
+    if encrypting && backend.unwrap_or_else(|| Backend::for_path(db)) != Backend::Csv {
+        return Err(Failure::InvalidInput(format!("{} isn't a single CSV file; only those can be encrypted", db)).into());
+    }
+    let cipher = if sealed || (encrypting && !matches!(cli.command, Some(Command::Decrypt))) {
+        Some(Rc::new(Cipher::new(passphrase(db, !sealed)?)))
+    } else {
+        None
+    };
+    if default_db && !read_only {
+        migrate_legacy_db(db, cipher.as_ref())?;
+    }
+    let mut store = if read_only {
+        store::open_read_only(db, backend, cipher.clone())?
+    } else {
+        store::open(db, backend, cipher.clone())?
+    };
+    if cfg.auto_backup && !read_only {
+        store = Box::new(BackupStore::new(store, Backups::new(db, cfg.backup_keep)));
+    }
+    if cfg.checksum_file && !read_only {
+        store = Box::new(checksum::ChecksumStore::new(store, db));
+    }
+
+    let aliases = Aliases::load(db)?;
+    let ctx = Ctx { db, backend: cli.backend, store: store.as_ref(), cfg: &cfg, aliases: &aliases, cipher: cipher.as_ref(), read_only, quiet: cli.quiet, dry_run: cli.dry_run };
+    if let Some(cmd) = cli.command {
+        // `watch` stays open indefinitely, so it locks for each redraw instead. A dry run only
+        // reads: its store reports changes instead of making them, and nothing beside the
+        // database is written either.
+        let _lock = match &cmd {
+            _ if ctx.read_only => None,
+            Command::Watch { .. } => None,
+            cmd if cmd.writes() && !ctx.dry_run => Some(lock::exclusive(db)?),
+            _ => Some(lock::shared(db)?),
+        };
+        let op = op_name(&matches);
+        let journal = JournalStore::new(ctx.store, db, &op);
+        let dry_run = DryRunStore::new(&journal, &cfg);
+        let store: &dyn PriceStore = if ctx.dry_run { &dry_run } else { &journal };
+        return run_command(&Ctx { store, ..ctx }, cmd);
+    }
+
+    menu::run(&ctx)
+}
@@ -0,0 +1,594 @@
+//! The interactive numbered menu, used when no subcommand is given.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+
+use anyhow::Result;
+use chrono::Utc;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::CachedStore;
+use crate::dryrun::DryRunStore;
+use crate::category::CategoryFilter;
+use crate::input::{self, Input};
+use crate::journal::JournalStore;
+use crate::lock;
+use crate::search::{self, Matcher};
+use crate::select;
+use crate::validate::{self, UrlCheck};
+use crate::config::Config;
+use crate::money::Money;
+use crate::{backup_db, check_export_path, choose_offer, latest_offers, updated_row, format_price, format_row, previous_observation, price_anomaly, price_change, export_rows, list, ListFormat, new_row, parse_currency, parse_original_price, parse_price, parse_quantity, parse_shipping, parse_tags, print_details, print_fuzzy_matches, print_matches, set_archived, set_target, show_cheapest, Changes, Ctx, Failure, Filters, PriceStore, Row, SortKey, Status, FUZZY_LIMIT};
+
+/// Answers remembered between adds, persisted best-effort in a file next to the database.
+#[derive(Default, Serialize, Deserialize)]
+struct Session {
+    last_product: String,
+    last_category: String,
+}
+
+impl Session {
+    fn state_path(db: &str) -> String {
+        format!("{}.state.json", db)
+    }
+
+    fn load(db: &str) -> Self {
+        fs::read_to_string(Self::state_path(db))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, db: &str) {
+        if let Ok(json) = serde_json::to_string(self) {
+            let _ = fs::write(Self::state_path(db), json);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Add,
+    List,
+    Cheapest,
+    Export,
+    Delete,
+    Show,
+    Target,
+    Search,
+    Edit,
+    Update,
+    Quit,
+}
+
+/// Accepted inputs per action: the menu number first, then command words and shortcuts.
+const ACTIONS: [(Action, &[&str]); 11] = [
+    (Action::Add, &["1", "add", "a"]),
+    (Action::List, &["2", "list", "l", "ls"]),
+    (Action::Cheapest, &["3", "cheapest", "c"]),
+    (Action::Export, &["4", "export", "e"]),
+    (Action::Delete, &["5", "delete", "d", "rm"]),
+    (Action::Show, &["6", "show", "s", "details"]),
+    (Action::Target, &["7", "target", "t"]),
+    (Action::Search, &["8", "search", "find", "f"]),
+    (Action::Edit, &["9", "edit"]),
+    (Action::Update, &["10", "update", "u"]),
+    (Action::Quit, &["11", "quit", "q", "exit"]),
+];
+
+impl Action {
+    /// Whether the action changes the database.
+    fn writes(self) -> bool {
+        matches!(self, Action::Add | Action::Delete | Action::Target | Action::Edit | Action::Update)
+    }
+
+    /// The command word for the action, e.g. `add`.
+    fn word(self) -> &'static str {
+        ACTIONS.iter().find(|(a, _)| *a == self).map_or("", |(_, words)| words[1])
+    }
+}
+
+fn parse_action(choice: &str) -> Option<Action> {
+    let choice = choice.to_lowercase();
+    ACTIONS
+        .iter()
+        .find(|(_, words)| words.contains(&choice.as_str()))
+        .map(|(action, _)| *action)
+}
+
+/// The command word closest to `choice`, if it is plausibly a typo of one.
+fn suggest_action(choice: &str) -> Option<&'static str> {
+    let choice = choice.to_lowercase();
+    ACTIONS
+        .iter()
+        .flat_map(|(_, words)| words.iter().skip(1).filter(|w| w.len() > 2))
+        .map(|w| (strsim::levenshtein(&choice, w), *w))
+        .filter(|(d, w)| *d <= 2 && *d < w.len())
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, w)| w)
+}
+
+pub fn run(ctx: &Ctx) -> Result<()> {
+    let db = ctx.db;
+    // Actions mostly read a file nothing has changed since the last one, so rows are kept
+    // between them for as long as the file stays the same.
+    let cache = CachedStore::new(ctx.store, db);
+    let ctx = &Ctx { store: &cache, ..*ctx };
+    let mut known: Vec<String> = Vec::new();
+    for r in ctx.store.all()? {
+        for v in [r.product, r.category] {
+            if !v.is_empty() && !known.contains(&v) {
+                known.push(v);
+            }
+        }
+    }
+    let mut input = Input::new(known);
+    let mut session = Session::load(db);
+
+    loop {
+        println!("\n== Price Tracker ==");
+        if ctx.read_only {
+            println!("Database: {} (read-only)", db);
+        } else {
+            println!("Database: {}", db);
+        }
+        println!("1) Add product price");
+        println!("2) List all prices");
+        println!("3) Show cheapest option");
+        println!("4) Export data to CSV");
+        println!("5) Delete a product");
+        println!("6) Show entry details");
+        println!("7) Set or clear a target price");
+        println!("8) Search entries");
+        println!("9) Edit an entry");
+        println!("10) Record a new price for a product");
+        println!("11) Exit");
+
+        let choice = match input.line("Select an option (number or command): ") {
+            Ok(choice) => choice,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        let action = match parse_action(&choice) {
+            Some(Action::Quit) => {
+                println!("Goodbye.");
+                break;
+            }
+            Some(action) => action,
+            None => {
+                match suggest_action(&choice) {
+                    Some(word) => println!("Invalid option. Did you mean '{}'?", word),
+                    None => println!("Invalid option."),
+                }
+                continue;
+            }
+        };
+
+        if ctx.read_only && action.writes() {
+            println!("{} is open read-only; '{}' is turned off.", db, action.word());
+            continue;
+        }
+        // The lock is held for the whole action, so rows read at its start are still current
+        // when it writes. Read-only sessions take none, as they mustn't create the lock file.
+        let _lock = if ctx.read_only {
+            None
+        } else {
+            match lock::exclusive(db) {
+                Ok(lock) => Some(lock),
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            }
+        };
+        let op = format!("menu {}", action.word());
+        let journal = JournalStore::new(ctx.store, db, &op);
+        let dry_run = DryRunStore::new(&journal, ctx.cfg);
+        let store: &dyn PriceStore = if ctx.dry_run { &dry_run } else { &journal };
+        // Ctrl-C inside an action abandons just that action.
+        if let Err(e) = run_action(action, &Ctx { store, ..*ctx }, &mut session, &mut input) {
+            match input::cancel_kind(&e) {
+                Some(ErrorKind::Interrupted) => println!("Canceled."),
+                Some(_) => break,
+                None => return Err(e),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_top(s: &str) -> Result<usize> {
+    match s.trim().parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(Failure::InvalidInput(format!("Invalid number '{}', expected 1 or more", s.trim())).into()),
+    }
+}
+
+const CATEGORY_PROMPT: &str = "Categories, comma-separated, !name to leave one out (empty for all)";
+
+/// An empty answer means every category.
+fn parse_category(s: &str) -> Result<Option<CategoryFilter>> {
+    if s.trim().is_empty() {
+        return Ok(None);
+    }
+    CategoryFilter::parse(s).map(Some).map_err(|e| Failure::InvalidInput(format!("Invalid category '{}': {}", s.trim(), e)).into())
+}
+
+fn run_action(action: Action, ctx: &Ctx, session: &mut Session, input: &mut Input) -> Result<()> {
+    let (db, cfg) = (ctx.db, ctx.cfg);
+    match action {
+        Action::Add => {
+            let product = input.valid("Product name", &session.last_product, validate::product)?;
+            let last_category = if session.last_category.is_empty() { &cfg.default_category } else { &session.last_category };
+            let category = input.with_default("Category", last_category)?;
+            let price = input.valid("Price", "", |s| validate::price(s, false))?;
+            let currency = input.valid("Currency", &cfg.default_currency, parse_currency)?;
+            let url = loop {
+                match input.valid("Product link (URL)", "", validate::url)? {
+                    UrlCheck::Valid(url) => break url,
+                    UrlCheck::MissingScheme(fixed) if input.confirm(&format!("Use '{}'? (y/N): ", fixed))? => break fixed,
+                    UrlCheck::MissingScheme(_) => {}
+                }
+            };
+            let mut row = new_row(product.clone(), category.clone(), price, url);
+            let store = input.with_default("Store", &row.store)?;
+            row.store = store;
+            row.sku = input.line("SKU/barcode (Enter to skip): ")?.trim().to_string();
+            row.notes = input.line("Notes (Enter to skip): ")?;
+            row.tags = parse_tags(&input.line("Tags, comma-separated (Enter to skip): ")?);
+            row.original_price =
+                input.valid("Regular price if on sale (Enter to skip)", "", |s| parse_original_price(s, price))?;
+            row.shipping = input.valid("Shipping", "0", |s| parse_shipping(s, price))?;
+            row.status = Status::parse(&input.line("In stock? (in/out, Enter for unknown): ")?);
+            row.quantity = input.valid("Quantity, e.g. 0.5 (Enter to skip)", "", parse_quantity)?;
+            if row.quantity.is_some() {
+                row.unit = input.line("Unit, e.g. g, kg, ml, l or pcs: ")?;
+            }
+            row.currency = currency;
+            let rows = ctx.store.all()?;
+            if let Some(warning) = price_anomaly(&rows, &row, ctx) {
+                println!("{}", warning);
+                if !input.confirm("Save anyway? (y/N): ")? {
+                    println!("Not saved.");
+                    return Ok(());
+                }
+            }
+            let previous = previous_observation(rows, &row, ctx.aliases);
+            ctx.store.append(std::slice::from_ref(&row))?;
+            if ctx.tells() {
+                println!("Saved.");
+            }
+            if let Some(prev) = previous.filter(|p| p.currency == row.currency) {
+                let when = ctx.cfg.date(&prev.timestamp);
+                println!("{} since {} (was {}).", price_change(prev.price, row.price), when, cfg.price(&prev));
+            }
+            input.remember(&product);
+            input.remember(&category);
+            session.last_product = product;
+            session.last_category = category;
+            session.save(db);
+        }
+
+        Action::List => {
+            let sort = loop {
+                let choice = input.line("Sort by? (price/name/category/date/priority) [none]: ")?;
+                if choice.is_empty() || choice.eq_ignore_ascii_case("none") {
+                    break None;
+                }
+                match SortKey::from_str(&choice, true) {
+                    Ok(key) => break Some(key),
+                    Err(_) => println!("Unknown sort order '{}'.", choice),
+                }
+            };
+            let desc = sort.is_some() && input.confirm("Descending? (y/N): ")?;
+            list(ctx, &Filters::default(), ListFormat::Table, sort.map(|key| (key, desc)), false, false, false)?
+        }
+
+        Action::Cheapest => {
+            let rows = ctx.store.all()?;
+            if rows.is_empty() {
+                println!("No entries.");
+            } else {
+                let category = input.valid(CATEGORY_PROMPT, "", parse_category)?;
+                let store = input.line("Store to search (leave empty for all): ")?;
+                let filters = Filters {
+                    category,
+                    store: Some(store).filter(|s| !s.is_empty()),
+                    ..Default::default()
+                };
+                let per_unit = input.confirm("Compare price per unit? (y/N): ")?;
+                let top = if per_unit { 1 } else { input.valid("How many options to show", "1", parse_top)? };
+                show_cheapest(rows, &filters, per_unit, top, cfg);
+            }
+        }
+
+        Action::Export => {
+            if input.confirm("Export data to CSV? (y/N): ")? {
+                let out = input.line(&format!("Filename (default {}): ", cfg.default_export_file))?;
+                let out = if out.is_empty() { &cfg.default_export_file } else { &out };
+                if let Err(e) = check_export_path(ctx, out) {
+                    println!("{}", e);
+                    return Ok(());
+                }
+                let category = input.valid(CATEGORY_PROMPT, "", parse_category)?;
+                // write current rows to `out`
+                let filters = Filters { category, ..Default::default() };
+                let mut rows = filters.stream(ctx.store.stream()?);
+                export_rows(out, rows.by_ref())?;
+                rows.finish();
+                println!("Exported to {}", out);
+            } else {
+                println!("Export canceled.");
+            }
+        }
+
+        Action::Delete => {
+            // Pick entries by id from a list (id: product | price); `1,3,5-8` and `all` select several
+            let rows = ctx.store.all()?;
+            if rows.is_empty() {
+                println!("No entries.");
+                return Ok(());
+            }
+            for r in &rows {
+                let archived = if r.archived { " (archived)" } else { "" };
+                println!("#{}: {} | {}{}", r.id, r.product, cfg.price(r), archived);
+            }
+            let sel = input.line("Ids to delete, e.g. 3 or 1,3,5-8 or all (empty to cancel): ")?;
+            if sel.is_empty() {
+                println!("Canceled.");
+                return Ok(());
+            }
+            let ids: Vec<u64> = rows.iter().map(|r| r.id).collect();
+            let sel = select::parse(&sel, &ids);
+            for e in &sel.errors {
+                println!("Skipped {}", e);
+            }
+            if sel.picked.is_empty() {
+                println!("Nothing selected.");
+                return Ok(());
+            }
+            println!("Selected {} entries:", sel.picked.len());
+            let picked: HashSet<u64> = sel.picked.iter().copied().collect();
+            for r in rows.iter().filter(|r| picked.contains(&r.id)) {
+                println!("  #{}: {} | {}", r.id, r.product, cfg.price(r));
+            }
+            let live: Vec<u64> = rows.iter().filter(|r| picked.contains(&r.id) && !r.archived).map(|r| r.id).collect();
+            if !live.is_empty() && input.confirm("Archive instead, keeping their history? (y/N): ")? {
+                let n = set_archived(ctx, &live, true)?;
+                if ctx.tells() {
+                    println!("Archived {} entries.", n);
+                }
+                return Ok(());
+            }
+            if !cfg.confirm_deletes || input.confirm(&format!("Delete {} entries? (y/N): ", sel.picked.len()))? {
+                if sel.picked.len() > 1 {
+                    backup_db(ctx)?;
+                }
+                let (removed, kept): (Vec<Row>, Vec<Row>) = rows.into_iter().partition(|r| picked.contains(&r.id));
+                let changes = Changes { removed, ..Default::default() };
+                ctx.store.commit(&kept, &changes)?;
+                if ctx.tells() {
+                    println!("Deleted {} entries.", changes.removed.len());
+                }
+            } else {
+                println!("Canceled.");
+            }
+        }
+
+        Action::Show => {
+            let rows = ctx.store.all()?;
+            if rows.is_empty() {
+                println!("No entries.");
+                return Ok(());
+            }
+            for r in &rows {
+                println!("#{}: {} | {}", r.id, r.product, cfg.price(r));
+            }
+            let sel = input.line("Id to show (or empty to cancel): ")?;
+            if sel.is_empty() {
+                return Ok(());
+            }
+            match sel.trim_start_matches('#').parse::<u64>() {
+                Ok(id) => match rows.iter().find(|r| r.id == id) {
+                    Some(r) => print_details(r, cfg),
+                    None => println!("No entry with id {}.", id),
+                },
+                Err(_) => println!("Invalid number."),
+            }
+        }
+
+        Action::Target => {
+            let product = input.line("Product name: ")?;
+            if product.is_empty() {
+                println!("Canceled.");
+                return Ok(());
+            }
+            let target = input.line("Target price (leave empty to clear): ")?;
+            let target = if target.is_empty() { None } else { Some(parse_price(&target)?) };
+            match set_target(ctx, &product, target) {
+                Ok(n) if ctx.tells() => println!("Updated {} rows.", n),
+                Ok(_) => {}
+                Err(e) => println!("{}", e),
+            }
+        }
+
+        Action::Search => {
+            let query = input.line("Search for (product, category, store or notes): ")?;
+            if query.is_empty() {
+                println!("Canceled.");
+                return Ok(());
+            }
+            let rows = ctx.store.all()?;
+            let hits = Matcher::new(&query, false, &[])?.find(&rows);
+            if !hits.is_empty() {
+                print_matches(&hits, cfg);
+                return Ok(());
+            }
+            let closest = search::fuzzy(&rows, &query, FUZZY_LIMIT);
+            if closest.is_empty() {
+                println!("No matches.");
+            } else {
+                println!("No exact matches, closest were:");
+                print_fuzzy_matches(&closest, cfg);
+            }
+        }
+
+        Action::Edit => {
+            let rows = ctx.store.all()?;
+            if rows.is_empty() {
+                println!("No entries.");
+                return Ok(());
+            }
+            for r in &rows {
+                println!("#{}: {} | {}", r.id, r.product, cfg.price(r));
+            }
+            let sel = input.line("Id to edit (or empty to cancel): ")?;
+            if sel.is_empty() {
+                println!("Canceled.");
+                return Ok(());
+            }
+            let Ok(id) = sel.trim_start_matches('#').parse::<u64>() else {
+                println!("Invalid number.");
+                return Ok(());
+            };
+            let Some(idx) = rows.iter().position(|r| r.id == id) else {
+                println!("No entry with id {}.", id);
+                return Ok(());
+            };
+            let touch = input.confirm("Set its time to now? (y/N): ")?;
+            edit_row(ctx, input, rows, idx, touch)?;
+        }
+
+        Action::Update => {
+            let product = input.valid("Product name", &session.last_product, validate::product)?;
+            let rows = ctx.store.all()?;
+            let offers = match latest_offers(rows.clone(), &product, ctx.aliases) {
+                Ok(offers) => offers,
+                Err(e) => {
+                    println!("{}", e);
+                    return Ok(());
+                }
+            };
+            let pick = if offers.len() > 1 { choose_offer(input, &offers, cfg)? } else { 0 };
+            let prev = &offers[pick];
+            let price = input.valid(&format!("New price (was {})", cfg.price(prev)), "", |s| validate::price(s, false))?;
+            let row = updated_row(prev, price);
+            if let Some(warning) = price_anomaly(&rows, &row, ctx) {
+                println!("{}", warning);
+                if !input.confirm("Save anyway? (y/N): ")? {
+                    println!("Not saved.");
+                    return Ok(());
+                }
+            }
+            ctx.store.append(std::slice::from_ref(&row))?;
+            if ctx.tells() {
+                println!("Saved. {} since {}.", price_change(prev.price, row.price), ctx.cfg.date(&prev.timestamp));
+            }
+        }
+
+        Action::Quit => {}
+    }
+    Ok(())
+}
+
+/// `-` clears an optional field; anything else is kept as typed.
+fn cleared(s: &str) -> &str {
+    if s.trim() == "-" {
+        ""
+    } else {
+        s
+    }
+}
+
+fn optional(input: &mut Input, label: &str, current: &str) -> Result<String> {
+    Ok(cleared(&input.with_default(label, current)?).to_string())
+}
+
+/// Editable fields of `r` as they are shown in the summary of changes.
+fn edit_fields(r: &Row, cfg: &Config) -> Vec<(&'static str, String)> {
+    let money = |m: Option<Money>| m.map(|m| format_price(m.to_f64(), &r.currency, &cfg.currency_symbol)).unwrap_or_default();
+    vec![
+        ("product", r.product.clone()),
+        ("category", r.category.clone()),
+        ("price", cfg.price(r)),
+        ("currency", r.currency.clone()),
+        ("url", r.url.clone()),
+        ("store", r.store.clone()),
+        ("sku", r.sku.clone()),
+        ("notes", r.notes.clone()),
+        ("tags", r.tags.join(", ")),
+        ("regular price", money(r.original_price)),
+        ("shipping", money(Some(r.shipping))),
+        ("stock", r.status.as_str().to_string()),
+        ("quantity", r.quantity.map(|q| q.to_string()).unwrap_or_default()),
+        ("unit", r.unit.clone()),
+        ("timestamp", r.timestamp.clone()),
+    ]
+}
+
+/// Ask for each field of `rows[idx]` with its current value as the default, show what changed and
+/// save after confirmation. The row keeps its place and, unless `touch` is set, its timestamp.
+pub fn edit_row(ctx: &Ctx, input: &mut Input, mut rows: Vec<Row>, idx: usize, touch: bool) -> Result<()> {
+    let cfg = ctx.cfg;
+    let old = rows[idx].clone();
+    println!("Editing {}", format_row(&old, cfg));
+    println!("Press Enter to keep a value; '-' clears an optional one.");
+    let mut row = old.clone();
+    row.product = input.valid("Product name", &old.product, validate::product)?;
+    row.category = optional(input, "Category", &old.category)?;
+    let allow_negative = old.price < Money::default();
+    let price = input.valid("Price", &old.price.to_string(), |s| validate::price(s, allow_negative))?;
+    row.price = price;
+    row.currency = input.valid("Currency", &old.currency, |s| parse_currency(cleared(s)))?;
+    row.url = loop {
+        match input.valid("Product link (URL)", &old.url, |s| validate::url(cleared(s)))? {
+            UrlCheck::Valid(url) => break url,
+            UrlCheck::MissingScheme(fixed) if input.confirm(&format!("Use '{}'? (y/N): ", fixed))? => break fixed,
+            UrlCheck::MissingScheme(_) => {}
+        }
+    };
+    row.store = optional(input, "Store", &old.store)?;
+    row.sku = optional(input, "SKU/barcode", &old.sku)?.trim().to_string();
+    row.notes = optional(input, "Notes", &old.notes)?;
+    row.tags = parse_tags(&optional(input, "Tags, comma-separated", &old.tags.join(", "))?);
+    let regular = old.original_price.map(|m| m.to_string()).unwrap_or_default();
+    row.original_price = input.valid("Regular price if on sale", &regular, |s| parse_original_price(cleared(s), price))?;
+    row.shipping = input.valid("Shipping", &old.shipping.to_string(), |s| parse_shipping(cleared(s), price))?;
+    row.status = Status::parse(&input.with_default("In stock? (in/out/unknown)", old.status.as_str())?);
+    let quantity = old.quantity.map(|q| q.to_string()).unwrap_or_default();
+    row.quantity = input.valid("Quantity", &quantity, |s| parse_quantity(cleared(s)))?;
+    row.unit = if row.quantity.is_some() { optional(input, "Unit", &old.unit)? } else { String::new() };
+    if touch {
+        row.timestamp = Utc::now().to_rfc3339();
+    }
+
+    let changed: Vec<(&str, String, String)> = edit_fields(&old, cfg)
+        .into_iter()
+        .zip(edit_fields(&row, cfg))
+        .filter(|((_, from), (_, to))| from != to)
+        .map(|((field, from), (_, to))| (field, from, to))
+        .collect();
+    if changed.is_empty() {
+        println!("No changes.");
+        return Ok(());
+    }
+    println!("Changes:");
+    let shown = |v: &str| if v.is_empty() { "(empty)".to_string() } else { v.to_string() };
+    for (field, from, to) in &changed {
+        println!("  {}: {} -> {}", field, shown(from), shown(to));
+    }
+    if !input.confirm("Save these changes? (y/N): ")? {
+        println!("Not saved.");
+        return Ok(());
+    }
+    rows[idx] = row.clone();
+    ctx.store.commit(&rows, &Changes { updated: vec![(old, row)], ..Default::default() })?;
+    if ctx.tells() {
+        println!("Saved.");
+    }
+    Ok(())
+}
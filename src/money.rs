@@ -0,0 +1,193 @@
+//! Prices as integer ten-thousandths, so values read from and written to the CSV never drift,
+//! including unit prices such as 0.1795.
+
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+
+/// Decimal places kept.
+const PLACES: usize = 4;
+/// Units per whole currency unit.
+const SCALE: i64 = 10_000;
+
+/// An amount in ten-thousandths of the currency unit. Only display and derived values such as
+/// unit prices go through `f64`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    /// From ten-thousandths, as returned by `units`.
+    pub fn from_units(units: i64) -> Self {
+        Money(units)
+    }
+
+    /// The amount in ten-thousandths, for exact arithmetic.
+    pub fn units(self) -> i64 {
+        self.0
+    }
+
+    /// The amount rounded to whole cents, half away from zero.
+    pub fn cents(self) -> i64 {
+        let half = SCALE / 200;
+        (self.0 + if self.0 < 0 { -half } else { half }) / (SCALE / 100)
+    }
+
+    /// The nearest whole cent, for computed prices such as `adjust --multiply` results.
+    pub fn round_to_cent(self) -> Self {
+        Money(self.cents() * (SCALE / 100))
+    }
+
+    /// The sum, or `None` when it doesn't fit.
+    pub fn checked_add(self, other: Money) -> Option<Self> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    /// The difference, or `None` when it doesn't fit.
+    pub fn checked_sub(self, other: Money) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Money)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / SCALE as f64
+    }
+
+    /// Nearest ten-thousandth to `value`, for input that has more decimals than `from_str` accepts.
+    pub fn round_f64(value: f64) -> Option<Self> {
+        let units = (value * SCALE as f64).round();
+        (units.is_finite() && units.abs() < i64::MAX as f64).then_some(Money(units as i64))
+    }
+}
+
+/// Accepts `19.99`, `19,99`, `-3`, `.5`, `0.1795`; more than four decimals only if the extra
+/// digits are zeros.
+impl FromStr for Money {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int, frac) = digits.split_once(['.', ',']).unwrap_or((digits, ""));
+        let is_digits = |p: &str| p.chars().all(|c| c.is_ascii_digit());
+        if (int.is_empty() && frac.is_empty()) || !is_digits(int) || !is_digits(frac) {
+            return Err("not a number");
+        }
+        if frac.len() > PLACES && frac[PLACES..].chars().any(|c| c != '0') {
+            return Err("more than four decimal places");
+        }
+        let whole: i64 = if int.is_empty() { 0 } else { int.parse().map_err(|_| "too large")? };
+        let frac: i64 = format!("{:0<width$}", &frac[..frac.len().min(PLACES)], width = PLACES).parse().unwrap_or(0);
+        let units = whole.checked_mul(SCALE).and_then(|c| c.checked_add(frac)).ok_or("too large")?;
+        Ok(Money(if negative { -units } else { units }))
+    }
+}
+
+/// Saturates, so rows read from a file with huge amounts sort and print as the largest amount
+/// instead of wrapping around; `add` and `edit` refuse such sums up front.
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0.saturating_add(other.0))
+    }
+}
+
+/// Saturates like `Add`.
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money(self.0.saturating_sub(other.0))
+    }
+}
+
+/// Two decimals, or up to four when the amount has them: `19.90`, `0.1795`, `2.125`.
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let frac = format!("{:0width$}", abs % SCALE as u64, width = PLACES);
+        let frac = frac.trim_end_matches('0');
+        let text = format!("{}{}.{:0<2}", sign, abs / SCALE as u64, frac);
+        f.pad(&text)
+    }
+}
+
+/// JSON keeps prices as plain numbers.
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(s: &str) -> Money {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_both_decimal_marks_and_up_to_four_places() {
+        assert_eq!(money("19.99").units(), 199_900);
+        assert_eq!(money("19,99"), money("19.99"));
+        assert_eq!(money(" 0.1795 ").units(), 1795);
+        assert_eq!(money(".5").units(), 5000);
+        assert_eq!(money("-3").units(), -30_000);
+        assert_eq!(money("+2").units(), 20_000);
+        assert_eq!(money("1.250000"), money("1.25"));
+    }
+
+    #[test]
+    fn rejects_what_it_cant_hold_exactly() {
+        assert_eq!("0.17951".parse::<Money>(), Err("more than four decimal places"));
+        assert_eq!("abc".parse::<Money>(), Err("not a number"));
+        assert_eq!("".parse::<Money>(), Err("not a number"));
+        assert_eq!("1.2.3".parse::<Money>(), Err("not a number"));
+        assert_eq!("99999999999999999".parse::<Money>(), Err("too large"));
+    }
+
+    #[test]
+    fn formats_two_places_or_as_many_as_needed() {
+        assert_eq!(money("19.9").to_string(), "19.90");
+        assert_eq!(money("0.1795").to_string(), "0.1795");
+        assert_eq!(money("2.125").to_string(), "2.125");
+        assert_eq!(money("-0.05").to_string(), "-0.05");
+        assert_eq!(money("7").to_string(), "7.00");
+        assert_eq!(format!("{:>8}", money("1.5")), "    1.50");
+    }
+
+    #[test]
+    fn survives_a_round_trip_through_text() {
+        for s in ["0.1795", "19.99", "0.0001", "-12.3456", "1000000.00"] {
+            assert_eq!(money(&money(s).to_string()), money(s));
+        }
+        assert_eq!(Money::round_f64(0.1795).unwrap(), money("0.1795"));
+        assert_eq!(Money::from_units(money("0.1795").units()).to_f64(), 0.1795);
+    }
+
+    #[test]
+    fn sums_and_differences_saturate_unless_checked() {
+        let max = Money::from_units(i64::MAX);
+        assert_eq!(money("1.5") + money("2.25"), money("3.75"));
+        assert_eq!(money("1.5") - money("2.25"), money("-0.75"));
+        assert_eq!(max + money("0.0001"), max);
+        assert_eq!(Money::from_units(i64::MIN) - money("1"), Money::from_units(i64::MIN));
+        assert_eq!(max.checked_add(money("0.0001")), None);
+        assert_eq!(max.checked_sub(money("-1")), None);
+        assert_eq!(money("3").checked_sub(money("1")), Some(money("2")));
+    }
+
+    #[test]
+    fn rounds_to_cents_half_away_from_zero() {
+        assert_eq!(money("0.1795").cents(), 18);
+        assert_eq!(money("0.1749").cents(), 17);
+        assert_eq!(money("-0.005").cents(), -1);
+        assert_eq!(money("2.125").round_to_cent(), money("2.13"));
+    }
+}
@@ -0,0 +1,37 @@
+//! Paging of long output through `$PAGER` (default `less -R`).
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// Print `text`, going through a pager when stdout is a terminal and the text
+/// doesn't fit on screen. Falls back to printing directly if no pager can be started.
+pub fn show(text: &str, no_pager: bool) -> io::Result<()> {
+    if no_pager || !needs_pager(text) || spawn_pager(text).is_err() {
+        io::stdout().write_all(text.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn needs_pager(text: &str) -> bool {
+    if !io::stdout().is_terminal() {
+        return false;
+    }
+    match terminal_size::terminal_size() {
+        Some((_, h)) => text.lines().count() >= h.0 as usize,
+        None => false,
+    }
+}
+
+fn spawn_pager(text: &str) -> io::Result<()> {
+    let pager = env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+    let mut parts = pager.split_whitespace();
+    let program = parts.next().ok_or_else(|| io::Error::other("empty PAGER"))?;
+    let mut child = Command::new(program).args(parts).stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The user may quit the pager before reading everything.
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -0,0 +1,355 @@
+//! Grouping and the calculations behind the reports: which rows record the same product, its
+//! history, the cheapest offers and how far its price has ranged. The binary only formats them.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Result;
+use chrono::DateTime;
+
+use crate::aliases::Aliases;
+use crate::money::Money;
+use crate::row::{Row, Status};
+use crate::{text, Failure};
+
+/// Rows grouped by `key`, in key order; each group keeps the rows' order.
+pub fn group_by(rows: Vec<Row>, key: impl Fn(&Row) -> String) -> BTreeMap<String, Vec<Row>> {
+    let mut groups: BTreeMap<String, Vec<Row>> = BTreeMap::new();
+    for r in rows {
+        groups.entry(key(&r)).or_default().push(r);
+    }
+    groups
+}
+
+/// The row's currency, or `default_currency` upper-cased when it has none.
+pub fn currency_of(r: &Row, default_currency: &str) -> String {
+    if r.currency.is_empty() { default_currency.to_ascii_uppercase() } else { r.currency.clone() }
+}
+
+/// Lowest landed price, so shipping costs count.
+pub fn cheapest(rows: Vec<Row>) -> Option<Row> {
+    cheapest_by(rows, Row::landed)
+}
+
+/// The row with the lowest `cost`; the first of equal ones.
+pub fn cheapest_by<K: PartialOrd>(rows: Vec<Row>, cost: impl Fn(&Row) -> K) -> Option<Row> {
+    rows.into_iter().min_by(|a, b| cost(a).partial_cmp(&cost(b)).unwrap_or(Ordering::Equal))
+}
+
+/// Drop rows marked out of stock, which cheapest never recommends by default.
+pub fn in_stock(rows: Vec<Row>) -> Vec<Row> {
+    rows.into_iter().filter(|r| r.status != Status::OutOfStock).collect()
+}
+
+/// Drop rows already bought; they are records rather than candidates for cheapest.
+pub fn not_purchased(rows: Vec<Row>) -> Vec<Row> {
+    rows.into_iter().filter(|r| !r.purchased()).collect()
+}
+
+/// Chronological order for RFC 3339 timestamps. Values that don't parse sort before all that
+/// do, and among themselves as plain text.
+pub fn compare_timestamps(a: &str, b: &str) -> Ordering {
+    match (DateTime::parse_from_rfc3339(a), DateTime::parse_from_rfc3339(b)) {
+        (Ok(x), Ok(y)) => x.cmp(&y),
+        (Ok(_), Err(_)) => Ordering::Greater,
+        (Err(_), Ok(_)) => Ordering::Less,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+/// Grouping key for the product a row records. Rows with a SKU group by it (case-insensitive),
+/// whatever their names; rows without one group by name (aliases resolved) and join the SKU
+/// of same-named rows when exactly one SKU is recorded for that name.
+pub fn product_identity<'a>(rows: &[Row], aliases: &'a Aliases) -> impl Fn(&Row) -> String + 'a {
+    // Name key and the one SKU seen for it; emptied when the name carries several SKUs.
+    let mut skus: HashMap<String, String> = HashMap::new();
+    for r in rows.iter().filter(|r| !r.sku.is_empty()) {
+        let sku = text::fold(&r.sku);
+        let seen = skus.entry(aliases.key(&r.product)).or_insert_with(|| sku.clone());
+        if *seen != sku {
+            seen.clear();
+        }
+    }
+    move |r: &Row| {
+        if !r.sku.is_empty() {
+            return format!("sku:{}", text::fold(&r.sku));
+        }
+        let name = aliases.key(&r.product);
+        match skus.get(&name).filter(|s| !s.is_empty()) {
+            Some(sku) => format!("sku:{}", sku),
+            None => format!("name:{}", name),
+        }
+    }
+}
+
+/// Observations of the same product (see `product_identity`), split per store when `by_store`
+/// is set (the URL stands in for a missing store), each group sorted oldest first.
+/// Rows whose timestamp does not parse count as oldest; equal timestamps keep their file order.
+pub fn group_history(rows: Vec<Row>, by_store: bool, aliases: &Aliases) -> Vec<Vec<Row>> {
+    let identity = product_identity(&rows, aliases);
+    let key = |r: &Row| {
+        let product = identity(r);
+        if !by_store {
+            return product;
+        }
+        let source = if r.store.is_empty() { &r.url } else { &r.store };
+        format!("{}\0{}", product, text::fold(source))
+    };
+    group_by(rows, key)
+        .into_values()
+        .map(|mut g| {
+            g.sort_by_key(|r| DateTime::parse_from_rfc3339(&r.timestamp).ok());
+            g
+        })
+        .collect()
+}
+
+/// The most recent row of each history group.
+pub fn latest_observations(rows: Vec<Row>, by_store: bool, aliases: &Aliases) -> Vec<Row> {
+    group_history(rows, by_store, aliases).into_iter().filter_map(|mut g| g.pop()).collect()
+}
+
+/// Rows recording `product`: those named like it (aliases resolved) and those sharing their SKU.
+pub fn product_rows(rows: Vec<Row>, product: &str, aliases: &Aliases) -> Result<Vec<Row>> {
+    let identity = product_identity(&rows, aliases);
+    let key = aliases.key(product);
+    let wanted: HashSet<String> = rows.iter().filter(|r| aliases.key(&r.product) == key).map(&identity).collect();
+    let rows: Vec<Row> = rows.into_iter().filter(|r| wanted.contains(&identity(r))).collect();
+    if rows.is_empty() {
+        return Err(Failure::NoMatch(format!("No entries for product '{}'.", product)).into());
+    }
+    Ok(rows)
+}
+
+/// For each product (see `product_identity`) and currency, the cheapest buyable offer and the
+/// runner-up, comparing the latest price at each store. Biggest saving over the runner-up
+/// first; products with a single offer come last.
+pub fn compare_offers(rows: Vec<Row>, aliases: &Aliases, default_currency: &str) -> Vec<(Row, Option<Row>)> {
+    let latest = latest_observations(not_purchased(in_stock(rows)), true, aliases);
+    let identity = product_identity(&latest, aliases);
+    let mut offers: Vec<(Row, Option<Row>)> =
+        group_by(latest, |r| format!("{}\0{}", identity(r), currency_of(r, default_currency)))
+            .into_values()
+            .filter_map(|mut g| {
+                g.sort_by_key(Row::landed);
+                let mut g = g.into_iter();
+                Some((g.next()?, g.next()))
+            })
+            .collect();
+    let saving = |(best, next): &(Row, Option<Row>)| next.as_ref().map(|n| n.landed() - best.landed());
+    offers.sort_by_key(|o| std::cmp::Reverse(saving(o)));
+    offers
+}
+
+/// How far the price of one product in one currency has ranged.
+pub struct Spread {
+    /// Observations of the product.
+    pub entries: usize,
+    /// The earliest of the lowest-priced rows.
+    pub low: Row,
+    /// The latest of the highest-priced rows.
+    pub high: Row,
+    pub spread: Money,
+    /// The spread as a percentage of the lowest price; 0 when that is not above zero.
+    pub pct: f64,
+}
+
+/// Per product (see `product_identity`) and currency seen more than once, the lowest and highest
+/// price, widest spread first, and the number of products seen only once.
+pub fn spreads(rows: Vec<Row>, aliases: &Aliases, default_currency: &str) -> (Vec<Spread>, usize) {
+    let identity = product_identity(&rows, aliases);
+    let groups = group_by(rows, |r| format!("{}\0{}", identity(r), currency_of(r, default_currency)));
+    let mut spreads: Vec<Spread> = Vec::new();
+    let mut single = 0;
+    for mut g in groups.into_values() {
+        if g.len() < 2 {
+            single += 1;
+            continue;
+        }
+        g.sort_by(|a, b| compare_timestamps(&a.timestamp, &b.timestamp));
+        let (Some(low), Some(high)) = (g.iter().min_by_key(|r| r.price), g.iter().max_by_key(|r| r.price)) else {
+            continue;
+        };
+        // Only prices of opposite signs can be further apart than an amount holds.
+        let spread = high.price.checked_sub(low.price).unwrap_or(Money::from_units(i64::MAX));
+        let pct = if low.price.units() > 0 { spread.to_f64() / low.price.to_f64() * 100.0 } else { 0.0 };
+        spreads.push(Spread { entries: g.len(), low: low.clone(), high: high.clone(), spread, pct });
+    }
+    spreads.sort_by(|a, b| b.pct.partial_cmp(&a.pct).unwrap_or(Ordering::Equal));
+    (spreads, single)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::row::SOURCE_MANUAL;
+
+    fn row(product: &str, price: &str, timestamp: &str) -> Row {
+        Row {
+            product: product.to_string(),
+            price: price.parse().unwrap(),
+            timestamp: timestamp.to_string(),
+            source: SOURCE_MANUAL.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn at(r: Row, store: &str, url: &str, sku: &str) -> Row {
+        Row { store: store.to_string(), url: url.to_string(), sku: sku.to_string(), ..r }
+    }
+
+    /// Product names and timestamps of each group.
+    fn shown(groups: &[Vec<Row>]) -> Vec<Vec<(&str, &str)>> {
+        groups.iter().map(|g| g.iter().map(|r| (r.product.as_str(), r.timestamp.as_str())).collect()).collect()
+    }
+
+    #[test]
+    fn history_groups_names_ignoring_case_and_spacing() {
+        let rows = vec![
+            row("USB-C cable", "9.99", "2024-03-01T00:00:00Z"),
+            row("Café crème", "3.10", "2024-01-05T00:00:00Z"),
+            row("usb-c  CABLE ", "8.49", "2024-01-01T00:00:00Z"),
+            row("CAFÉ CRÈME", "2.90", "2024-02-05T00:00:00+01:00"),
+            row("Usb-C Cable", "7.99", "2024-02-01T12:00:00+02:00"),
+        ];
+        let groups = group_history(rows, false, &Aliases::default());
+        assert_eq!(
+            shown(&groups),
+            [
+                vec![("Café crème", "2024-01-05T00:00:00Z"), ("CAFÉ CRÈME", "2024-02-05T00:00:00+01:00")],
+                vec![
+                    ("usb-c  CABLE ", "2024-01-01T00:00:00Z"),
+                    ("Usb-C Cable", "2024-02-01T12:00:00+02:00"),
+                    ("USB-C cable", "2024-03-01T00:00:00Z"),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn history_puts_unparseable_timestamps_first_in_file_order() {
+        let rows = vec![
+            row("Tea", "3", "2024-03-01T00:00:00Z"),
+            row("tea", "4", "yesterday"),
+            row("TEA", "5", ""),
+            row("Tea", "6", "2024-01-01T00:00:00Z"),
+            row("tea", "7", "01.02.2024"),
+        ];
+        let groups = group_history(rows.clone(), false, &Aliases::default());
+        let prices: Vec<String> = groups[0].iter().map(|r| r.price.to_string()).collect();
+        assert_eq!(prices, ["4.00", "5.00", "7.00", "6.00", "3.00"]);
+        let latest = latest_observations(rows, false, &Aliases::default());
+        assert_eq!(latest[0].price.to_string(), "3.00");
+    }
+
+    #[test]
+    fn history_follows_aliases_skus_and_optionally_stores() {
+        let mut aliases = Aliases::default();
+        aliases.add("Milch", "Milk").unwrap();
+        let rows = vec![
+            at(row("Milk", "1.19", "2024-01-01T00:00:00Z"), "Aldi", "", ""),
+            at(row("milch", "1.29", "2024-01-02T00:00:00Z"), "", "https://shop.example/milk", ""),
+            at(row("Whole milk 1l", "1.09", "2024-01-03T00:00:00Z"), "ALDI", "", "M-1"),
+            at(row("MILK", "1.39", "2024-01-04T00:00:00Z"), "aldi", "", "m-1"),
+        ];
+        let groups = group_history(rows.clone(), false, &aliases);
+        assert_eq!(groups.iter().map(Vec::len).collect::<Vec<_>>(), [4]);
+        let by_store = group_history(rows, true, &aliases);
+        let stores: Vec<Vec<&str>> = by_store.iter().map(|g| g.iter().map(|r| r.product.as_str()).collect()).collect();
+        assert_eq!(stores, [vec!["Milk", "Whole milk 1l", "MILK"], vec!["milch"]]);
+    }
+
+    #[test]
+    fn a_name_with_several_skus_keeps_them_apart() {
+        let rows = vec![
+            at(row("Cable", "5", ""), "", "", "C-1"),
+            at(row("Cable", "6", ""), "", "", "C-2"),
+            row("cable", "7", ""),
+        ];
+        let aliases = Aliases::default();
+        let identity = product_identity(&rows, &aliases);
+        let keys: Vec<String> = rows.iter().map(&identity).collect();
+        assert_eq!(keys, ["sku:c-1", "sku:c-2", "name:cable"]);
+    }
+
+    #[test]
+    fn product_rows_include_rows_sharing_a_sku_and_fail_when_none_match() {
+        let rows = vec![
+            at(row("Milk", "1.19", ""), "", "", "M-1"),
+            at(row("Whole milk 1l", "1.09", ""), "", "", "m-1"),
+            row("Bread", "2.49", ""),
+        ];
+        let found = product_rows(rows.clone(), "milk", &Aliases::default()).unwrap();
+        assert_eq!(found.iter().map(|r| r.product.as_str()).collect::<Vec<_>>(), ["Milk", "Whole milk 1l"]);
+        let err = product_rows(rows, "Tea", &Aliases::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<Failure>(), Some(Failure::NoMatch(_))));
+    }
+
+    #[test]
+    fn offers_compare_the_latest_buyable_price_per_store_and_currency() {
+        let stocked = |r: Row, status: Status| Row { status, ..r };
+        let rows = vec![
+            at(row("Tea", "4.00", "2024-01-01T00:00:00Z"), "A", "", ""),
+            at(row("Tea", "5.00", "2024-02-01T00:00:00Z"), "A", "", ""),
+            at(row("Tea", "4.50", "2024-01-15T00:00:00Z"), "B", "", ""),
+            stocked(at(row("Tea", "1.00", "2024-01-15T00:00:00Z"), "C", "", ""), Status::OutOfStock),
+            Row { currency: "USD".to_string(), ..at(row("Tea", "3.00", "2024-01-15T00:00:00Z"), "D", "", "") },
+            at(row("Jam", "2.00", "2024-01-15T00:00:00Z"), "A", "", ""),
+        ];
+        let offers = compare_offers(rows, &Aliases::default(), "eur");
+        let shown: Vec<(&str, String, Option<String>)> = offers
+            .iter()
+            .map(|(best, next)| (best.store.as_str(), best.price.to_string(), next.as_ref().map(|n| n.store.clone())))
+            .collect();
+        assert_eq!(
+            shown,
+            [
+                ("B", "4.50".to_string(), Some("A".to_string())),
+                ("A", "2.00".to_string(), None),
+                ("D", "3.00".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn spreads_run_from_the_first_low_to_the_last_high_widest_first() {
+        let rows = vec![
+            row("Tea", "4.00", "2024-03-01T00:00:00Z"),
+            row("Tea", "2.00", "2024-02-01T00:00:00Z"),
+            row("Tea", "4.00", "2024-01-01T00:00:00Z"),
+            row("Tea", "2.00", "2024-04-01T00:00:00Z"),
+            row("Jam", "3.00", "2024-01-01T00:00:00Z"),
+            row("Jam", "3.30", "2024-02-01T00:00:00Z"),
+            row("Bread", "2.49", "2024-01-01T00:00:00Z"),
+        ];
+        let (spreads, single) = spreads(rows, &Aliases::default(), "EUR");
+        assert_eq!(single, 1);
+        let shown: Vec<(&str, usize, &str, &str, String)> = spreads
+            .iter()
+            .map(|s| (s.low.product.as_str(), s.entries, s.low.timestamp.as_str(), s.high.timestamp.as_str(), s.spread.to_string()))
+            .collect();
+        assert_eq!(
+            shown,
+            [
+                ("Tea", 4, "2024-02-01T00:00:00Z", "2024-03-01T00:00:00Z", "2.00".to_string()),
+                ("Jam", 2, "2024-01-01T00:00:00Z", "2024-02-01T00:00:00Z", "0.30".to_string()),
+            ]
+        );
+        assert_eq!(spreads[0].pct, 100.0);
+    }
+
+    #[test]
+    fn spreads_of_opposite_signs_saturate() {
+        let rows = vec![Row { price: Money::from_units(i64::MIN), ..row("Refund", "0", "") }, row("Refund", "5", "")];
+        let (spreads, _) = spreads(rows, &Aliases::default(), "EUR");
+        assert_eq!(spreads[0].spread, Money::from_units(i64::MAX));
+        assert_eq!(spreads[0].pct, 0.0);
+    }
+
+    #[test]
+    fn many_products_group_by_sku() {
+        let rows: Vec<Row> = (0..20_000).map(|i| at(row(&format!("Product {}", i), "1", ""), "", "", &format!("S-{}", i % 10_000))).collect();
+        assert_eq!(group_history(rows.clone(), false, &Aliases::default()).len(), 10_000);
+        assert_eq!(product_rows(rows, "Product 7", &Aliases::default()).unwrap().len(), 2);
+    }
+}
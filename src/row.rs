@@ -0,0 +1,408 @@
+//! One price observation and its CSV form, shared by every storage backend.
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use csv::StringRecord;
+use serde::Serialize;
+
+use crate::money::Money;
+use crate::text::matches_ignore_case;
+
+/// Column order of the CSV file; older files may have fewer columns.
+pub const HEADER: [&str; 22] = [
+    "product", "category", "price", "url", "timestamp", "id", "currency", "store", "notes", "tags", "quantity", "unit",
+    "target", "shipping", "status", "archived", "source", "original_price", "priority", "extra",
+    "sku", "purchased_at",
+];
+
+/// Column counts of every layout the file has had, oldest first. Each one added columns at the
+/// end of `HEADER`, so files of any of them read by position. A new column is appended to
+/// `HEADER` and the new count here; the next rewrite then upgrades older files.
+pub(crate) const LAYOUTS: [usize; 17] = [5, 6, 7, 8, 9, 10, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22];
+const _: () = assert!(LAYOUTS[LAYOUTS.len() - 1] == HEADER.len());
+
+/// Columns of the first files, from before categories existed.
+const LEGACY_HEADER: [&str; 4] = ["product", "price", "url", "timestamp"];
+
+/// Which columns a CSV database has, told apart by its header row.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// `product,price,url,timestamp`.
+    Legacy,
+    /// The first this many columns of `HEADER`.
+    Columns(usize),
+}
+
+impl Layout {
+    pub const CURRENT: Layout = Layout::Columns(HEADER.len());
+
+    /// The layout `header` belongs to; column names are compared ignoring case and surrounding
+    /// spaces. An empty header, as in an empty file, counts as current. Headers of a newer
+    /// version and unknown ones are errors, since reading them by position would mix up fields.
+    pub fn detect(header: &StringRecord) -> Result<Layout> {
+        let names: Vec<String> = header.iter().map(|h| h.trim().to_ascii_lowercase()).collect();
+        if names.is_empty() || names.len() == 1 && names[0].is_empty() {
+            return Ok(Layout::CURRENT);
+        }
+        if names.iter().eq(LEGACY_HEADER.iter()) {
+            return Ok(Layout::Legacy);
+        }
+        if LAYOUTS.contains(&names.len()) && names.iter().eq(HEADER[..names.len()].iter()) {
+            return Ok(Layout::Columns(names.len()));
+        }
+        if names.len() > HEADER.len() && names[..HEADER.len()].iter().eq(HEADER.iter()) {
+            return Err(anyhow!(
+                "it was written by a newer version of pricepeek (unknown columns: {}); upgrade pricepeek to open it",
+                names[HEADER.len()..].join(", ")
+            ));
+        }
+        Err(anyhow!("unrecognized header '{}'; the first row must name the columns {}", names.join(","), HEADER.join(",")))
+    }
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Layout::Legacy => write!(f, "legacy {}", LEGACY_HEADER.join(",")),
+            Layout::Columns(n) => write!(f, "{}-column", n),
+        }
+    }
+}
+
+/// Source of rows typed in by hand, and of rows from files written before sources were recorded.
+pub const SOURCE_MANUAL: &str = "manual";
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Row {
+    /// Stable identifier; 0 until one is assigned.
+    pub id: u64,
+    pub product: String,
+    pub category: String,
+    pub price: Money,
+    /// ISO 4217 code such as "EUR"; empty means the configured default currency.
+    pub currency: String,
+    pub url: String,
+    pub store: String,
+    /// Free text; may contain commas and newlines, which the CSV quoting preserves.
+    pub notes: String,
+    /// Stored in the CSV as one `;`-separated field.
+    pub tags: Vec<String>,
+    /// Amount the price buys, in `unit`; `None` means only the total price is known.
+    pub quantity: Option<f64>,
+    pub unit: String,
+    /// Price the product is awaited to drop to; shared by all rows of the product.
+    pub target: Option<Money>,
+    /// Zero for free shipping and for files written before the column existed.
+    pub shipping: Money,
+    pub status: Status,
+    /// Hidden from listings and comparisons but kept for history.
+    pub archived: bool,
+    /// Where the price came from: "manual", "import:<file>", "fetch", or anything else.
+    pub source: String,
+    /// Regular price when `price` is a sale price; never below `price`.
+    pub original_price: Option<Money>,
+    /// Wishlist rank; higher means wanted more.
+    pub priority: i64,
+    /// Custom fields as `key=value;key=value`, kept verbatim so the CSV round-trips exactly.
+    #[serde(serialize_with = "serialize_extra")]
+    pub extra: String,
+    /// Store code or barcode; trimmed on entry, otherwise kept as typed. Empty when unknown.
+    pub sku: String,
+    /// When the entry was bought (RFC 3339); empty while it is only being watched.
+    pub purchased_at: String,
+    pub timestamp: String,
+}
+
+impl Row {
+    /// Price per base unit (kg, l, or the unit as given) when a quantity is recorded.
+    pub fn unit_price(&self) -> Option<(f64, String)> {
+        let q = self.quantity.filter(|q| *q > 0.0)?;
+        let (base, factor) = base_unit(&self.unit);
+        Some((self.price.to_f64() / (q * factor), base))
+    }
+
+    /// What the item costs delivered: price plus shipping.
+    pub fn landed(&self) -> Money {
+        self.price + self.shipping
+    }
+
+    /// Custom fields in stored order; pairs without `=` are skipped.
+    pub fn extras(&self) -> impl Iterator<Item = (&str, &str)> {
+        split_extras(&self.extra)
+    }
+
+    pub fn extra(&self, key: &str) -> Option<&str> {
+        self.extras().filter(|(k, _)| *k == key).last().map(|(_, v)| v)
+    }
+
+    /// Like `unit_price`, falling back to a size in the product name; the flag tells which was used.
+    pub fn unit_price_or_guess(&self) -> Option<(f64, String, bool)> {
+        if let Some((p, unit)) = self.unit_price() {
+            return Some((p, unit, false));
+        }
+        let (q, unit) = quantity_from_name(&self.product)?;
+        let (base, factor) = base_unit(&unit);
+        Some((self.price.to_f64() / (q * factor), base, true))
+    }
+
+    /// Percentage saved against the original price, when the row is on sale.
+    pub fn discount(&self) -> Option<f64> {
+        let orig = self.original_price.filter(|o| *o > self.price)?.to_f64();
+        Some((orig - self.price.to_f64()) / orig * 100.0)
+    }
+
+    pub fn under_target(&self) -> bool {
+        self.target.is_some_and(|t| self.price <= t)
+    }
+
+    pub fn purchased(&self) -> bool {
+        !self.purchased_at.is_empty()
+    }
+}
+
+/// A size at the end of a product name such as "Rice 500g" or "Juice 1,5 l", for rows recorded
+/// without a quantity. Only units `base_unit` converts are recognized.
+fn quantity_from_name(product: &str) -> Option<(f64, String)> {
+    let words: Vec<&str> = product.split_whitespace().collect();
+    let last = *words.last()?;
+    let (number, unit) = match last.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(0) => (*words.get(words.len().checked_sub(2)?)?, last),
+        Some(i) => last.split_at(i),
+        None => return None,
+    };
+    let quantity: f64 = number.replace(',', ".").parse().ok().filter(|q: &f64| *q > 0.0 && q.is_finite())?;
+    let unit = unit.to_lowercase();
+    matches!(unit.as_str(), "mg" | "g" | "kg" | "oz" | "lb" | "ml" | "cl" | "l" | "pc" | "pcs").then_some((quantity, unit))
+}
+
+/// The unit prices are compared in and the factor converting `unit` into it,
+/// so 500 g and 1 kg packs both compare per kg.
+fn base_unit(unit: &str) -> (String, f64) {
+    let unit = unit.trim().to_lowercase();
+    let (base, factor) = match unit.as_str() {
+        "mg" => ("kg", 1e-6),
+        "g" => ("kg", 1e-3),
+        "kg" => ("kg", 1.0),
+        "oz" => ("kg", 0.028_349_523_125),
+        "lb" => ("kg", 0.453_592_37),
+        "ml" => ("l", 1e-3),
+        "cl" => ("l", 1e-2),
+        "l" => ("l", 1.0),
+        "" | "pc" | "pcs" => ("pc", 1.0),
+        _ => return (unit, 1.0),
+    };
+    (base.to_string(), factor)
+}
+
+fn split_extras(extra: &str) -> impl Iterator<Item = (&str, &str)> {
+    extra.split(';').filter_map(|pair| pair.split_once('=')).map(|(k, v)| (k.trim(), v.trim()))
+}
+
+/// JSON shows custom fields as an object rather than the raw CSV string.
+fn serialize_extra<S: serde::Serializer>(extra: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    use serde::ser::SerializeMap;
+    let pairs: Vec<(&str, &str)> = split_extras(extra).collect();
+    let mut map = serializer.serialize_map(Some(pairs.len()))?;
+    for (k, v) in pairs {
+        map.serialize_entry(k, v)?;
+    }
+    map.end()
+}
+
+/// Whether an entry can currently be bought.
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    #[default]
+    #[value(name = "unknown")]
+    Unknown,
+    #[value(name = "in_stock")]
+    InStock,
+    #[value(name = "out_of_stock")]
+    OutOfStock,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Unknown => "unknown",
+            Status::InStock => "in_stock",
+            Status::OutOfStock => "out_of_stock",
+        }
+    }
+
+    /// Anything unrecognized, including an empty field, reads as unknown.
+    pub fn parse(s: &str) -> Status {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "in_stock" | "in" => Status::InStock,
+            "out_of_stock" | "out" | "oos" => Status::OutOfStock,
+            _ => Status::Unknown,
+        }
+    }
+}
+
+/// The row `rec` holds in a file of the given layout, and a description of everything that had
+/// to be made up to read it: a record with too few or too many fields, a missing or unreadable
+/// price, or no product name. Such rows still load, with empty fields and a price of 0.00.
+/// Fields past the end of the layout are kept in `extra` as `column23=...`, numbered by their
+/// position in the line, rather than read as the columns later layouts put there.
+pub(crate) fn parse_record(rec: &StringRecord, layout: Layout) -> (Row, Vec<String>) {
+    let mut problems = Vec::new();
+    let width = match layout {
+        Layout::Legacy => LEGACY_HEADER.len(),
+        Layout::Columns(n) => n,
+    };
+    let overflow: Vec<String> = rec
+        .iter()
+        .enumerate()
+        .skip(width)
+        .filter(|(_, f)| !f.trim().is_empty())
+        .map(|(i, f)| format!("column{}={}", i + 1, f.trim().replace(';', ",")))
+        .collect();
+    if rec.len() != width {
+        let mut problem = format!("{} fields instead of {}", rec.len(), width);
+        if !overflow.is_empty() {
+            let keys: Vec<&str> = overflow.iter().filter_map(|pair| pair.split_once('=')).map(|(k, _)| k).collect();
+            problem.push_str(&format!(", the rest kept in extra as {}", keys.join(", ")));
+        }
+        problems.push(problem);
+    }
+    let get = |i: usize| rec.get(i).filter(|_| i < width);
+    let field = |i: usize| get(i).unwrap_or("").to_string();
+    let price_at = if layout == Layout::Legacy { 1 } else { 2 };
+    let price = match get(price_at).map(|p| (p, p.parse::<Money>())) {
+        Some((_, Ok(p))) => p,
+        Some((raw, Err(_))) => match raw.trim().parse::<f64>().ok().and_then(Money::round_f64) {
+            Some(p) => {
+                problems.push(format!("price '{}' rounded to {}", raw, p));
+                p
+            }
+            None => {
+                problems.push(format!("price '{}' is not a number, read as 0.00", raw));
+                Money::default()
+            }
+        },
+        None => {
+            problems.push("no price, read as 0.00".to_string());
+            Money::default()
+        }
+    };
+    if get(0).is_none_or(|p| p.trim().is_empty()) {
+        problems.push("no product name".to_string());
+    }
+
+    let extra = |own: String| std::iter::once(own).chain(overflow.iter().cloned()).filter(|e| !e.is_empty()).collect::<Vec<_>>().join(";");
+
+    // Layouts since categories only differ in how many columns follow, so missing trailing
+    // fields read as empty.
+    let row = if layout != Layout::Legacy {
+        let id = get(5).and_then(|v| v.parse().ok()).unwrap_or(0);
+        Row {
+            id,
+            product: field(0),
+            category: field(1),
+            price,
+            url: field(3),
+            timestamp: field(4),
+            currency: field(6),
+            store: field(7),
+            notes: field(8),
+            tags: parse_tags(get(9).unwrap_or("")),
+            quantity: get(10).and_then(|q| q.parse().ok()),
+            unit: field(11),
+            target: get(12).and_then(|t| t.parse().ok()),
+            shipping: get(13).and_then(|s| s.parse().ok()).unwrap_or_default(),
+            status: Status::parse(get(14).unwrap_or("")),
+            archived: get(15).is_some_and(|a| a.trim() == "1" || a.trim().eq_ignore_ascii_case("true")),
+            source: get(16).filter(|s| !s.is_empty()).unwrap_or(SOURCE_MANUAL).to_string(),
+            original_price: get(17).and_then(|p| p.parse().ok()),
+            priority: get(18).and_then(|p| p.trim().parse().ok()).unwrap_or(0),
+            extra: extra(field(19)),
+            sku: field(20),
+            purchased_at: field(21),
+        }
+    } else {
+        Row {
+            product: field(0),
+            price,
+            url: field(2),
+            timestamp: field(3),
+            source: SOURCE_MANUAL.to_string(),
+            extra: extra(String::new()),
+            ..Default::default()
+        }
+    };
+    (row, problems)
+}
+
+/// A line of the file that couldn't be read as written: a record that needed made-up fields, or
+/// a header repeated below the first, as where two files were joined.
+#[derive(Clone, Debug)]
+pub struct Issue {
+    /// Line of the file the record starts on.
+    pub line: u64,
+    /// The record as CSV text, as near to how it appears in the file as it can be rebuilt.
+    pub raw: String,
+    pub problems: Vec<String>,
+}
+
+impl Issue {
+    pub fn new(rec: &StringRecord, problems: Vec<String>) -> Self {
+        let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(Vec::new());
+        let raw = wtr.write_record(rec).ok().and_then(|_| wtr.into_inner().ok()).map_or_else(
+            || rec.iter().collect::<Vec<_>>().join(","),
+            |bytes| String::from_utf8_lossy(&bytes).trim_end().to_string(),
+        );
+        Issue { line: rec.position().map_or(0, |p| p.line()), raw, problems }
+    }
+}
+
+/// CSV fields for `r` in `HEADER` order.
+pub fn record(r: &Row, id: u64) -> [String; HEADER.len()] {
+    [
+        r.product.clone(),
+        r.category.clone(),
+        r.price.to_string(),
+        r.url.clone(),
+        r.timestamp.clone(),
+        id.to_string(),
+        r.currency.clone(),
+        r.store.clone(),
+        r.notes.clone(),
+        r.tags.join(";"),
+        r.quantity.map(|q| q.to_string()).unwrap_or_default(),
+        r.unit.clone(),
+        r.target.map(|t| t.to_string()).unwrap_or_default(),
+        r.shipping.to_string(),
+        r.status.as_str().to_string(),
+        if r.archived { "1" } else { "" }.to_string(),
+        r.source.clone(),
+        r.original_price.map(|p| p.to_string()).unwrap_or_default(),
+        r.priority.to_string(),
+        r.extra.clone(),
+        r.sku.clone(),
+        r.purchased_at.clone(),
+    ]
+}
+
+/// Split a `;`- or `,`-separated tag list, trimming each tag and dropping empty and repeated ones.
+pub fn parse_tags(s: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for t in s.split([';', ',']).map(str::trim).filter(|t| !t.is_empty()) {
+        if !tags.iter().any(|have| matches_ignore_case(have, t)) {
+            tags.push(t.to_string());
+        }
+    }
+    tags
+}
+
+/// Lower-cased host of `url` without a leading `www.`, so `www.amazon.de` and `amazon.de` agree.
+pub fn url_domain(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url.trim()).ok()?;
+    Some(normalize_domain(parsed.host_str()?))
+}
+
+pub fn normalize_domain(host: &str) -> String {
+    let host = host.trim().to_ascii_lowercase();
+    host.strip_prefix("www.").map(str::to_string).unwrap_or(host)
+}
@@ -0,0 +1,104 @@
+//! Text search over entries, shared by `search` and `delete --match`, plus a typo-tolerant
+//! ranking of product names for `search --fuzzy`.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use regex::{Regex, RegexBuilder};
+
+use crate::{text, Failure, Row};
+
+/// Fuzzy matches scoring below this are too different to be worth showing.
+const MIN_FUZZY_SCORE: f64 = 0.3;
+
+/// Text fields a search can look at.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum SearchField {
+    Product,
+    Category,
+    Store,
+    Notes,
+}
+
+impl SearchField {
+    const ALL: [SearchField; 4] = [SearchField::Product, SearchField::Category, SearchField::Store, SearchField::Notes];
+
+    fn text(self, r: &Row) -> &str {
+        match self {
+            SearchField::Product => &r.product,
+            SearchField::Category => &r.category,
+            SearchField::Store => &r.store,
+            SearchField::Notes => &r.notes,
+        }
+    }
+}
+
+/// A case-insensitive query compiled once: a plain substring, or a regular expression.
+pub struct Matcher {
+    re: Regex,
+    fields: Vec<SearchField>,
+}
+
+impl Matcher {
+    /// `fields` empty means all of them.
+    pub fn new(query: &str, regex: bool, fields: &[SearchField]) -> Result<Self> {
+        if query.trim().is_empty() {
+            return Err(Failure::InvalidInput("Search query must not be empty".to_string()).into());
+        }
+        let pattern = if regex { text::nfc(query) } else { regex::escape(&text::nfc(query.trim())) };
+        let re = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| {
+                // Syntax errors render as several lines pointing at the column; the last one says what is wrong.
+                let msg = e.to_string();
+                let reason = msg.lines().last().unwrap_or_default().trim_start_matches("error: ").to_string();
+                Failure::InvalidInput(format!("Invalid pattern '{}': {}", query, reason))
+            })?;
+        let fields = if fields.is_empty() { SearchField::ALL.to_vec() } else { fields.to_vec() };
+        Ok(Matcher { re, fields })
+    }
+
+    pub fn matches(&self, r: &Row) -> bool {
+        self.fields.iter().any(|f| self.re.is_match(&text::nfc(f.text(r))))
+    }
+
+    /// Matching rows with their 0-based position in `rows`.
+    pub fn find<'a>(&self, rows: &'a [Row]) -> Vec<(usize, &'a Row)> {
+        rows.iter().enumerate().filter(|(_, r)| self.matches(r)).collect()
+    }
+}
+
+/// Trigrams of `s` lower-cased and padded, so word starts and ends count too.
+fn trigrams(s: &str) -> Vec<[char; 3]> {
+    let padded: Vec<char> = format!("  {} ", text::fold(s)).chars().collect();
+    let mut grams: Vec<[char; 3]> = padded.windows(3).map(|w| [w[0], w[1], w[2]]).collect();
+    grams.sort_unstable();
+    grams.dedup();
+    grams
+}
+
+/// Trigram (Sørensen–Dice) similarity of two names, from 0 (nothing shared) to 1 (same trigrams).
+/// An empty name scores 0.
+fn similarity(query: &[[char; 3]], name: &str) -> f64 {
+    if name.trim().is_empty() || query.is_empty() {
+        return 0.0;
+    }
+    let grams = trigrams(name);
+    let shared = query.iter().filter(|g| grams.binary_search(g).is_ok()).count();
+    2.0 * shared as f64 / (query.len() + grams.len()) as f64
+}
+
+/// Rows whose product name resembles `query`, best first, at most `limit` of them, each with its
+/// 0-based position in `rows` and its score.
+pub fn fuzzy<'a>(rows: &'a [Row], query: &str, limit: usize) -> Vec<(usize, &'a Row, f64)> {
+    let query = trigrams(query);
+    let mut hits: Vec<(usize, &Row, f64)> = rows
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (i, r, similarity(&query, &r.product)))
+        .filter(|(_, _, score)| *score >= MIN_FUZZY_SCORE)
+        .collect();
+    hits.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal));
+    hits.truncate(limit);
+    hits
+}
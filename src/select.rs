@@ -0,0 +1,54 @@
+//! Entry selections such as `1,3,5-8` or `all`, as typed in the delete picker and given to
+//! `delete --index`.
+
+/// The numbers a selection picked, in ascending order without repeats, and one message per
+/// token that could not be used.
+#[derive(Debug, Default, PartialEq)]
+pub struct Selection {
+    pub picked: Vec<u64>,
+    pub errors: Vec<String>,
+}
+
+/// Parses comma- or space-separated numbers, `a-b` ranges and `all` against the numbers in
+/// `valid`. A bad token is reported by name and skipped; the rest of the selection still counts.
+/// Ranges skip numbers missing from `valid`, so gaps left by deleted ids don't break `1-10`,
+/// but both ends must lie within the smallest and largest valid number.
+pub fn parse(input: &str, valid: &[u64]) -> Selection {
+    let mut sel = Selection::default();
+    let (lo, hi) = (valid.iter().min().copied().unwrap_or(0), valid.iter().max().copied().unwrap_or(0));
+    for token in input.split([',', ' ', '\t']).map(str::trim).filter(|t| !t.is_empty()) {
+        if token.eq_ignore_ascii_case("all") {
+            sel.picked.extend(valid);
+            continue;
+        }
+        let number = |s: &str| s.trim().trim_start_matches('#').parse::<u64>().ok();
+        let (start, end) = match token.split_once('-') {
+            Some((a, b)) => match (number(a), number(b)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => {
+                    sel.errors.push(format!("'{}': not a number or range", token));
+                    continue;
+                }
+            },
+            None => match number(token) {
+                Some(n) => (n, n),
+                None => {
+                    sel.errors.push(format!("'{}': not a number or range", token));
+                    continue;
+                }
+            },
+        };
+        if start > end {
+            sel.errors.push(format!("'{}': range runs backwards", token));
+        } else if start < lo || end > hi {
+            sel.errors.push(format!("'{}': outside {}-{}", token, lo, hi));
+        } else if start == end && !valid.contains(&start) {
+            sel.errors.push(format!("'{}': no such entry", token));
+        } else {
+            sel.picked.extend(valid.iter().filter(|n| (start..=end).contains(*n)));
+        }
+    }
+    sel.picked.sort_unstable();
+    sel.picked.dedup();
+    sel
+}
@@ -0,0 +1,315 @@
+//! SQLite storage. Each row keeps the CSV fields as text, so both backends read and write
+//! entries identically, plus a few derived columns that let filters run as SQL.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use chrono::DateTime;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, Transaction};
+
+use crate::category;
+use crate::filter::Filters;
+use crate::ids;
+use crate::row::{parse_record, record, Layout, Row, HEADER};
+use crate::store::{Changes, PriceStore};
+
+/// Stored in `PRAGMA user_version` and raised whenever the table changes, so older versions
+/// of pricepeek refuse files they would misread.
+const SCHEMA_VERSION: i64 = 1;
+
+/// Rows `stream` reads per query.
+const PAGE: i64 = 1000;
+
+/// Key in `meta` of the highest id ever given out, so deleted rows' ids aren't given out again.
+const HIGHEST_ID: &str = "highest_id";
+
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+/// The schema version of the open database, refusing one from a newer pricepeek.
+fn schema_version(conn: &Connection, path: &str) -> Result<i64> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |r| r.get(0)).with_context(|| format!("Read {}", path))?;
+    if version > SCHEMA_VERSION {
+        bail!("Can't read {}: it was written by a newer version of pricepeek (schema {}); upgrade pricepeek to open it", path, version);
+    }
+    Ok(version)
+}
+
+/// The CSV columns, quoted for SQL, in `HEADER` order.
+fn columns() -> String {
+    HEADER.iter().map(|c| format!("\"{}\"", c)).collect::<Vec<_>>().join(", ")
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<Self> {
+        if let Some(dir) = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Create directory {} for database {}", dir.display(), path))?;
+        }
+        let conn = Connection::open(path).with_context(|| format!("Open {}", path))?;
+        let version = schema_version(&conn, path)?;
+        let fields: Vec<String> = HEADER
+            .iter()
+            .map(|c| if *c == "id" { "\"id\" INTEGER NOT NULL UNIQUE".to_string() } else { format!("\"{}\" TEXT NOT NULL", c) })
+            .collect();
+        // `pos` keeps insertion order; the rest after the CSV fields exist for filtering. `meta`
+        // holds `HIGHEST_ID`.
+        conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS prices (
+                pos INTEGER PRIMARY KEY,
+                {},
+                price_cents INTEGER NOT NULL,
+                time_ms INTEGER,
+                is_archived INTEGER NOT NULL,
+                category_key TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS prices_category ON prices (category_key);
+            CREATE INDEX IF NOT EXISTS prices_time ON prices (time_ms);
+            CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value INTEGER NOT NULL);",
+            fields.join(",\n")
+        ))
+        .with_context(|| format!("Set up {}", path))?;
+        if version < SCHEMA_VERSION {
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION).with_context(|| format!("Set up {}", path))?;
+        }
+        Ok(SqliteStore { conn })
+    }
+
+    /// Open the existing database at `path` without creating, setting up or upgrading
+    /// anything; SQLite itself then refuses every write.
+    pub fn open_read_only(path: &str) -> Result<Self> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).with_context(|| format!("Open {}", path))?;
+        schema_version(&conn, path)?;
+        let tables: i64 = conn
+            .query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'prices'", [], |r| r.get(0))
+            .with_context(|| format!("Read {}", path))?;
+        if tables == 0 {
+            bail!("Can't read {}: it has no prices table", path);
+        }
+        Ok(SqliteStore { conn })
+    }
+
+    fn select(&self, condition: &str, values: Vec<Value>) -> Result<Vec<Row>> {
+        let sql = format!("SELECT {} FROM prices WHERE {} ORDER BY pos", selected_columns(), condition);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(values), from_sql)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Up to `PAGE` rows stored after position `after`, each with its position.
+    fn page(&self, after: i64) -> Result<Vec<(i64, Row)>> {
+        let sql = format!("SELECT {}, pos FROM prices WHERE pos > ? ORDER BY pos LIMIT ?", selected_columns());
+        let mut stmt = self.conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map([after, PAGE], |row| Ok((row.get(HEADER.len())?, from_sql(row)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// The CSV columns as `select` reads them, with ids as text like the other fields.
+fn selected_columns() -> String {
+    columns().replace("\"id\"", "CAST(\"id\" AS TEXT)")
+}
+
+/// The row in the leading `selected_columns` of a query result.
+fn from_sql(row: &rusqlite::Row) -> rusqlite::Result<Row> {
+    let fields: Vec<String> = (0..HEADER.len()).map(|i| row.get(i)).collect::<rusqlite::Result<_>>()?;
+    Ok(parse_record(&csv::StringRecord::from(fields), Layout::CURRENT).0)
+}
+
+/// The first id above those in use, in `rows` and ever given out.
+fn next_id(tx: &Transaction, rows: &[Row]) -> Result<u64> {
+    Ok(highest_id(tx)?.max(ids::highest(rows)) + 1)
+}
+
+/// The highest id stored or recorded as given out.
+fn highest_id(conn: &Connection) -> Result<u64> {
+    let stored: i64 = conn.query_row(
+        "SELECT MAX(COALESCE((SELECT MAX(\"id\") FROM prices), 0), COALESCE((SELECT value FROM meta WHERE key = ?), 0))",
+        [HIGHEST_ID],
+        |r| r.get(0),
+    )?;
+    Ok(stored as u64)
+}
+
+/// Store `rows`, giving those without an id the next ones never given out, and record the
+/// highest id.
+fn insert(tx: &Transaction, rows: &[Row]) -> Result<()> {
+    let mut next = next_id(tx, rows)?;
+    let placeholders = vec!["?"; HEADER.len() + 4].join(", ");
+    let mut stmt = tx.prepare(&format!(
+        "INSERT INTO prices ({}, price_cents, time_ms, is_archived, category_key) VALUES ({})",
+        columns(),
+        placeholders
+    ))?;
+    for r in rows {
+        let id = if r.id == 0 {
+            next += 1;
+            next - 1
+        } else {
+            r.id
+        };
+        stmt.execute(params_from_iter(values(r, id)))?;
+    }
+    tx.execute(
+        "INSERT INTO meta (key, value) VALUES (?1, ?2) ON CONFLICT (key) DO UPDATE SET value = MAX(value, excluded.value)",
+        params![HIGHEST_ID, (next - 1) as i64],
+    )?;
+    Ok(())
+}
+
+/// The CSV fields of `r` followed by the derived filter columns.
+fn values(r: &Row, id: u64) -> Vec<Value> {
+    let mut values: Vec<Value> = record(r, id).into_iter().map(Value::Text).collect();
+    values.push(Value::Integer(r.price.cents()));
+    values.push(DateTime::parse_from_rfc3339(&r.timestamp).map_or(Value::Null, |t| Value::Integer(t.timestamp_millis())));
+    values.push(Value::Integer(r.archived as i64));
+    values.push(Value::Text(category::key(&r.category)));
+    values
+}
+
+fn update(tx: &Transaction, rows: &[Row]) -> Result<()> {
+    let assignments: Vec<String> = HEADER
+        .iter()
+        .map(|c| format!("\"{}\" = ?", c))
+        .chain(["price_cents = ?", "time_ms = ?", "is_archived = ?", "category_key = ?"].map(String::from))
+        .collect();
+    let mut stmt = tx.prepare(&format!("UPDATE prices SET {} WHERE \"id\" = ?", assignments.join(", ")))?;
+    for r in rows {
+        let mut values = values(r, r.id);
+        values.push(Value::Integer(r.id as i64));
+        stmt.execute(params_from_iter(values))?;
+    }
+    Ok(())
+}
+
+fn delete(tx: &Transaction, ids: &[u64]) -> Result<usize> {
+    let mut stmt = tx.prepare("DELETE FROM prices WHERE \"id\" = ?")?;
+    let mut removed = 0;
+    for id in ids {
+        removed += stmt.execute([*id as i64])?;
+    }
+    Ok(removed)
+}
+
+/// A condition selecting at least the rows `filters` keeps, so `Filters::apply` only has to
+/// settle what SQL can't: excluded categories, stores, tags, custom fields and case folding
+/// beyond what `category_key` holds. Rows with unreadable times always pass, so `apply` can
+/// still count them.
+fn condition(filters: &Filters) -> (String, Vec<Value>) {
+    let mut parts: Vec<String> = vec!["1".to_string()];
+    let mut values: Vec<Value> = Vec::new();
+    if filters.archived {
+        parts.push("is_archived = 1".to_string());
+    } else if !filters.all {
+        parts.push("is_archived = 0".to_string());
+    }
+    if let Some(status) = filters.status {
+        parts.push("\"status\" = ?".to_string());
+        values.push(Value::Text(status.as_str().to_string()));
+    }
+    if let Some(min) = filters.min_price {
+        parts.push("price_cents >= ?".to_string());
+        values.push(Value::Integer(min.cents()));
+    }
+    if let Some(max) = filters.max_price {
+        parts.push("price_cents <= ?".to_string());
+        values.push(Value::Integer(max.cents()));
+    }
+    // Whole milliseconds: a time at or after `since` never truncates to before it.
+    if let Some(since) = filters.since {
+        parts.push("(time_ms IS NULL OR time_ms >= ?)".to_string());
+        values.push(Value::Integer(since.timestamp_millis()));
+    }
+    if let Some(until) = filters.until {
+        parts.push("(time_ms IS NULL OR time_ms <= ?)".to_string());
+        values.push(Value::Integer(until.timestamp_millis()));
+    }
+    if let Some(c) = filters.category.as_ref().filter(|c| !c.includes().is_empty()) {
+        let mut any = Vec::new();
+        for include in c.includes() {
+            let key = category::key(include);
+            any.push("category_key = ?".to_string());
+            values.push(Value::Text(key.clone()));
+            if !filters.exact_category {
+                any.push("category_key LIKE ? ESCAPE '\\'".to_string());
+                let escaped = key.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+                values.push(Value::Text(format!("{}/%", escaped)));
+            }
+        }
+        parts.push(format!("({})", any.join(" OR ")));
+    }
+    (parts.join(" AND "), values)
+}
+
+impl PriceStore for SqliteStore {
+    fn all(&self) -> Result<Vec<Row>> {
+        self.select("1", Vec::new())
+    }
+
+    fn filter(&self, filters: &Filters) -> Result<Vec<Row>> {
+        let (condition, values) = condition(filters);
+        Ok(filters.apply(self.select(&condition, values)?))
+    }
+
+    /// Rows are read a page at a time, so only one page is held in memory.
+    fn stream(&self) -> Result<Box<dyn Iterator<Item = Result<Row>> + '_>> {
+        let mut after = 0;
+        let mut page = Vec::new().into_iter();
+        let mut done = false;
+        Ok(Box::new(std::iter::from_fn(move || loop {
+            if let Some((pos, row)) = page.next() {
+                after = pos;
+                return Some(Ok(row));
+            }
+            if done {
+                return None;
+            }
+            match self.page(after) {
+                Ok(rows) => {
+                    done = (rows.len() as i64) < PAGE;
+                    page = rows.into_iter();
+                }
+                Err(e) => {
+                    done = true;
+                    return Some(Err(e));
+                }
+            }
+        })))
+    }
+
+    fn append(&self, rows: &[Row]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        insert(&tx, rows)?;
+        Ok(tx.commit()?)
+    }
+
+    fn delete(&self, ids: &[u64]) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let removed = delete(&tx, ids)?;
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    fn overwrite(&self, rows: &[Row]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM prices", [])?;
+        insert(&tx, rows)?;
+        Ok(tx.commit()?)
+    }
+
+    /// Only the removed, rewritten and new rows are touched, in one transaction.
+    fn commit(&self, rows: &[Row], changes: &Changes) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        delete(&tx, &changes.removed.iter().map(|r| r.id).collect::<Vec<_>>())?;
+        let updated: Vec<Row> = changes.updated.iter().map(|(_, new)| new.clone()).collect();
+        update(&tx, &updated)?;
+        let new: Vec<Row> = rows.iter().filter(|r| r.id == 0).cloned().collect();
+        insert(&tx, &new)?;
+        Ok(tx.commit()?)
+    }
+
+    fn highest_id(&self) -> Result<Option<u64>> {
+        Ok(Some(highest_id(&self.conn)?))
+    }
+}
@@ -0,0 +1,443 @@
+//! Where the rows are kept: the CSV file, a directory of per-category CSV files, an SQLite
+//! database for large collections, or memory.
+//! Commands and the menu only talk to `PriceStore`, so they work the same on any of them.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::crypt::Cipher;
+use crate::csvfile::CsvStore;
+use crate::dirstore::DirStore;
+use crate::filter::Filters;
+use crate::row::{Issue, Row};
+use crate::sqlite::SqliteStore;
+use crate::Failure;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Backend {
+    Csv,
+    Sqlite,
+    /// A directory with one CSV file per category.
+    Dir,
+}
+
+impl Backend {
+    /// A directory for an existing one or a path ending in `/`, SQLite for `.sqlite`,
+    /// `.sqlite3` and `.db` files, CSV for anything else.
+    pub fn for_path(db: &str) -> Backend {
+        if std::path::Path::new(db).is_dir() || db.ends_with(std::path::is_separator) {
+            return Backend::Dir;
+        }
+        let ext = std::path::Path::new(db).extension().map(|e| e.to_string_lossy().to_lowercase());
+        match ext.as_deref() {
+            Some("sqlite" | "sqlite3" | "db") => Backend::Sqlite,
+            _ => Backend::Csv,
+        }
+    }
+}
+
+/// Open (creating it if needed) the database at `db`, with `backend` or the one its extension
+/// implies, encrypted with `cipher` when one is given. Only single CSV files can be encrypted.
+pub fn open(db: &str, backend: Option<Backend>, cipher: Option<Rc<Cipher>>) -> Result<Box<dyn PriceStore>> {
+    Ok(match (backend.unwrap_or_else(|| Backend::for_path(db)), cipher) {
+        (Backend::Csv, None) => Box::new(CsvStore::open(db)?),
+        (Backend::Csv, Some(cipher)) => Box::new(CsvStore::encrypted(db, cipher)?),
+        (Backend::Sqlite, None) => Box::new(SqliteStore::open(db)?),
+        (Backend::Dir, None) => Box::new(DirStore::open(db)?),
+        (Backend::Sqlite | Backend::Dir, Some(_)) => {
+            return Err(Failure::InvalidInput(format!("{} isn't a single CSV file; only those can be encrypted", db)).into())
+        }
+    })
+}
+
+/// Open the database at `db` for reading only: nothing is created, set up or upgraded, and
+/// every change is refused. A database that doesn't exist reads as empty.
+pub fn open_read_only(db: &str, backend: Option<Backend>, cipher: Option<Rc<Cipher>>) -> Result<Box<dyn PriceStore>> {
+    let inner: Box<dyn PriceStore> = if !std::path::Path::new(db).exists() {
+        Box::new(MemoryStore::default())
+    } else {
+        match backend.unwrap_or_else(|| Backend::for_path(db)) {
+            Backend::Csv => open(db, Some(Backend::Csv), cipher)?,
+            Backend::Sqlite => Box::new(SqliteStore::open_read_only(db)?),
+            Backend::Dir => open(db, Some(Backend::Dir), cipher)?,
+        }
+    };
+    Ok(Box::new(ReadOnlyStore { inner, db: db.to_string() }))
+}
+
+pub trait PriceStore {
+    /// Every row in stored order, each with an id.
+    fn all(&self) -> Result<Vec<Row>>;
+
+    /// The rows `filters` selects. Backends that can narrow the rows down before loading them
+    /// override this; the result is the same either way.
+    fn filter(&self, filters: &Filters) -> Result<Vec<Row>> {
+        Ok(filters.apply(self.all()?))
+    }
+
+    /// Every row, one at a time, for exports that shouldn't hold all of them in memory.
+    fn stream(&self) -> Result<Box<dyn Iterator<Item = Result<Row>> + '_>> {
+        Ok(Box::new(self.all()?.into_iter().map(Ok)))
+    }
+
+    /// Add `rows` after the existing ones, giving each the next free id.
+    fn append(&self, rows: &[Row]) -> Result<()>;
+
+    /// Remove the rows with these ids and return how many there were.
+    fn delete(&self, ids: &[u64]) -> Result<usize> {
+        let rows = self.all()?;
+        let (removed, kept): (Vec<Row>, Vec<Row>) = rows.into_iter().partition(|r| ids.contains(&r.id));
+        if !removed.is_empty() {
+            self.overwrite(&kept)?;
+        }
+        Ok(removed.len())
+    }
+
+    /// Replace every row with `rows`; rows without an id get the next free ones.
+    fn overwrite(&self, rows: &[Row]) -> Result<()>;
+
+    /// Lines the last `all`, `filter` or `stream` could only load by making up fields, such as
+    /// an unreadable price, or had to skip. Backends that can't hold such lines have none.
+    fn issues(&self) -> Vec<Issue> {
+        Vec::new()
+    }
+
+    /// A description of the stored layout when it predates the current one; rewriting every
+    /// row with `overwrite` upgrades it.
+    fn outdated(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// The highest id given out so far, for backends whose appended rows always come last in
+    /// `all` with the ids up to this one, so what an append stored can be told without reading
+    /// the rows back; `None` for the others.
+    fn highest_id(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Save the outcome of a change: `rows` is the complete new contents and `changes` what
+    /// was removed or rewritten to get there. Rows in `rows` without an id are new. Backends
+    /// that can delete and update single rows only touch those.
+    fn commit(&self, rows: &[Row], _changes: &Changes) -> Result<()> {
+        self.overwrite(rows)
+    }
+}
+
+/// Rows a mutating operation removes or rewrites, used for reporting and dry runs.
+#[derive(Default)]
+pub struct Changes {
+    pub removed: Vec<Row>,
+    pub updated: Vec<(Row, Row)>,
+}
+
+/// Remove the rows at `positions` (0-based): the rows left and the removal to commit.
+pub fn remove_at(rows: Vec<Row>, positions: &HashSet<usize>) -> (Vec<Row>, Changes) {
+    let (removed, kept): (Vec<_>, Vec<_>) = rows.into_iter().enumerate().partition(|(i, _)| positions.contains(i));
+    let kept = kept.into_iter().map(|(_, r)| r).collect();
+    (kept, Changes { removed: removed.into_iter().map(|(_, r)| r).collect(), ..Default::default() })
+}
+
+/// A store that passes reads through and fails every change.
+pub struct ReadOnlyStore {
+    inner: Box<dyn PriceStore>,
+    db: String,
+}
+
+impl ReadOnlyStore {
+    fn refuse(&self) -> anyhow::Error {
+        Failure::InvalidInput(format!("{} is open read-only; nothing was changed", self.db)).into()
+    }
+}
+
+impl PriceStore for ReadOnlyStore {
+    fn all(&self) -> Result<Vec<Row>> {
+        self.inner.all()
+    }
+
+    fn filter(&self, filters: &Filters) -> Result<Vec<Row>> {
+        self.inner.filter(filters)
+    }
+
+    fn stream(&self) -> Result<Box<dyn Iterator<Item = Result<Row>> + '_>> {
+        self.inner.stream()
+    }
+
+    fn append(&self, _rows: &[Row]) -> Result<()> {
+        Err(self.refuse())
+    }
+
+    fn delete(&self, _ids: &[u64]) -> Result<usize> {
+        Err(self.refuse())
+    }
+
+    fn overwrite(&self, _rows: &[Row]) -> Result<()> {
+        Err(self.refuse())
+    }
+
+    fn issues(&self) -> Vec<Issue> {
+        self.inner.issues()
+    }
+
+    fn outdated(&self) -> Result<Option<String>> {
+        self.inner.outdated()
+    }
+
+    fn highest_id(&self) -> Result<Option<u64>> {
+        self.inner.highest_id()
+    }
+
+    fn commit(&self, _rows: &[Row], _changes: &Changes) -> Result<()> {
+        Err(self.refuse())
+    }
+}
+
+/// Rows held in memory only: what a database opened read-only holds before it exists, and a
+/// backend for tests and for embedding without a file.
+#[derive(Default)]
+pub struct MemoryStore {
+    rows: RefCell<Vec<Row>>,
+    /// The highest id given out, so deleted rows' ids aren't given out again.
+    highest: Cell<u64>,
+}
+
+impl MemoryStore {
+    pub fn new(rows: Vec<Row>) -> Self {
+        let rows = with_ids(rows, 1);
+        MemoryStore { highest: Cell::new(crate::ids::highest(&rows)), rows: RefCell::new(rows) }
+    }
+
+    /// Store `rows` in place of the current ones, giving those without an id the next ones never
+    /// given out.
+    fn keep(&self, rows: Vec<Row>) {
+        let rows = with_ids(rows, self.highest.get() + 1);
+        self.highest.set(self.highest.get().max(crate::ids::highest(&rows)));
+        self.rows.replace(rows);
+    }
+}
+
+/// `rows` with the ones lacking an id numbered from after the highest of `rows` and `next`.
+pub(crate) fn with_ids(mut rows: Vec<Row>, next: u64) -> Vec<Row> {
+    let next = rows.iter().map(|r| r.id + 1).max().unwrap_or(1).max(next);
+    for (id, r) in (next..).zip(rows.iter_mut().filter(|r| r.id == 0)) {
+        r.id = id;
+    }
+    rows
+}
+
+impl PriceStore for MemoryStore {
+    fn all(&self) -> Result<Vec<Row>> {
+        Ok(self.rows.borrow().clone())
+    }
+
+    fn append(&self, rows: &[Row]) -> Result<()> {
+        let have = self.rows.borrow().clone();
+        self.keep(have.into_iter().chain(rows.iter().map(|r| Row { id: 0, ..r.clone() })).collect());
+        Ok(())
+    }
+
+    fn overwrite(&self, rows: &[Row]) -> Result<()> {
+        self.keep(rows.to_vec());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::category::CategoryFilter;
+    use crate::row::{record, SOURCE_MANUAL};
+
+    type Named = (&'static str, Box<dyn PriceStore>);
+
+    /// An empty store of every backend, named, in a temporary directory that must outlive them.
+    fn backends() -> (tempfile::TempDir, Vec<Named>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let stores: Vec<Named> = vec![
+            ("csv", open(&path("prices.csv"), Some(Backend::Csv), None).unwrap()),
+            ("dir", open(&path("prices.d"), Some(Backend::Dir), None).unwrap()),
+            ("sqlite", open(&path("prices.sqlite"), Some(Backend::Sqlite), None).unwrap()),
+            ("memory", Box::new(MemoryStore::default())),
+        ];
+        (dir, stores)
+    }
+
+    fn row(product: &str, category: &str, price: &str) -> Row {
+        Row {
+            product: product.to_string(),
+            category: category.to_string(),
+            price: price.parse().unwrap(),
+            timestamp: "2024-01-02T03:04:05Z".to_string(),
+            source: SOURCE_MANUAL.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Rows of every kind the backends have to keep apart: subcategories, archived rows and
+    /// every optional field set.
+    fn sample() -> Vec<Row> {
+        let full = Row {
+            currency: "EUR".to_string(),
+            url: "https://shop.example/tea".to_string(),
+            store: "Corner Shop".to_string(),
+            notes: "green, loose\nleaf".to_string(),
+            tags: vec!["tea".to_string(), "organic".to_string()],
+            quantity: Some(0.25),
+            unit: "kg".to_string(),
+            target: Some("4.5".parse().unwrap()),
+            shipping: "1.95".parse().unwrap(),
+            status: crate::row::Status::InStock,
+            original_price: Some("6.99".parse().unwrap()),
+            priority: 2,
+            extra: "size=L".to_string(),
+            sku: "T-1".to_string(),
+            purchased_at: "2024-01-03T00:00:00Z".to_string(),
+            ..row("Tea", "Food/Drinks", "5.49")
+        };
+        vec![row("Milk", "food", "1.19"), full, row("Crisps", "food/snacks", "0.1795"), row("Ball", "Toys", "9.99"), Row {
+            archived: true,
+            ..row("Old bread", "food", "2.49")
+        }]
+    }
+
+    /// The CSV fields of `rows`, which compare every field.
+    fn records(rows: &[Row]) -> Vec<[String; crate::row::HEADER.len()]> {
+        rows.iter().map(|r| record(r, r.id)).collect()
+    }
+
+    #[test]
+    fn removing_positions_keeps_the_order_of_both_halves() {
+        let rows = sample();
+        let positions: HashSet<usize> = [3, 0, 4].into();
+        let (kept, changes) = remove_at(rows.clone(), &positions);
+        assert_eq!(records(&kept), records(&[rows[1].clone(), rows[2].clone()]));
+        assert_eq!(records(&changes.removed), records(&[rows[0].clone(), rows[3].clone(), rows[4].clone()]));
+        assert!(changes.updated.is_empty());
+    }
+
+    #[test]
+    fn appended_rows_get_the_next_ids_and_keep_every_field() {
+        let (_dir, stores) = backends();
+        for (name, store) in &stores {
+            store.append(&sample()).unwrap();
+            store.append(&[row("Rice", "food", "3")]).unwrap();
+            let rows = store.all().unwrap();
+            let expected: Vec<Row> =
+                sample().into_iter().chain([row("Rice", "food", "3")]).zip(1..).map(|(r, id)| Row { id, ..r }).collect();
+            assert_eq!(records(&rows), records(&expected), "{}", name);
+            let streamed: Vec<Row> = store.stream().unwrap().collect::<Result<_>>().unwrap();
+            assert_eq!(records(&streamed), records(&rows), "{}", name);
+        }
+    }
+
+    #[test]
+    fn overwrite_replaces_every_row_and_keeps_their_ids() {
+        let (_dir, stores) = backends();
+        for (name, store) in &stores {
+            store.append(&sample()).unwrap();
+            let mut rows = store.all().unwrap();
+            rows.retain(|r| r.id != 2);
+            rows[0].price = "1.29".parse().unwrap();
+            store.overwrite(&rows).unwrap();
+            assert_eq!(records(&store.all().unwrap()), records(&rows), "{}", name);
+            store.overwrite(&[]).unwrap();
+            assert!(store.all().unwrap().is_empty(), "{}", name);
+        }
+    }
+
+    fn ids(store: &dyn PriceStore) -> Vec<u64> {
+        store.all().unwrap().iter().map(|r| r.id).collect()
+    }
+
+    #[test]
+    fn ids_of_deleted_rows_are_not_given_out_again() {
+        let (_dir, stores) = backends();
+        for (name, store) in &stores {
+            store.append(&sample()[..3]).unwrap();
+            store.delete(&[3]).unwrap();
+            store.append(&[row("Rice", "food", "3")]).unwrap();
+            assert_eq!(ids(store.as_ref()), [1, 2, 4], "{}", name);
+            store.delete(&[4]).unwrap();
+            let mut rows = store.all().unwrap();
+            rows.push(row("Salt", "food", "0.5"));
+            store.overwrite(&rows).unwrap();
+            assert_eq!(ids(store.as_ref()), [1, 2, 5], "{}", name);
+            let rows = store.all().unwrap();
+            store.commit(&[rows[0].clone(), row("Tea", "food", "2")], &Changes { removed: rows[1..].to_vec(), ..Default::default() }).unwrap();
+            assert_eq!(ids(store.as_ref()), [1, 6], "{}", name);
+            store.overwrite(&[]).unwrap();
+            store.append(&[row("Rice", "food", "3")]).unwrap();
+            assert_eq!(ids(store.as_ref()), [7], "{}", name);
+        }
+    }
+
+    #[test]
+    fn the_highest_id_given_out_survives_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, backend) in [("prices.csv", Backend::Csv), ("prices.d", Backend::Dir), ("prices.sqlite", Backend::Sqlite)] {
+            let path = dir.path().join(name).to_string_lossy().into_owned();
+            let store = open(&path, Some(backend), None).unwrap();
+            store.append(&sample()[..3]).unwrap();
+            store.delete(&[2, 3]).unwrap();
+            drop(store);
+            let store = open(&path, Some(backend), None).unwrap();
+            store.append(&[row("Rice", "food", "3")]).unwrap();
+            assert_eq!(ids(store.as_ref()), [1, 4], "{}", name);
+        }
+    }
+
+    #[test]
+    fn delete_removes_only_the_given_ids() {
+        let (_dir, stores) = backends();
+        for (name, store) in &stores {
+            store.append(&sample()).unwrap();
+            assert_eq!(store.delete(&[2, 4, 99]).unwrap(), 2, "{}", name);
+            let ids: Vec<u64> = store.all().unwrap().iter().map(|r| r.id).collect();
+            assert_eq!(ids, [1, 3, 5], "{}", name);
+            assert_eq!(store.delete(&[99]).unwrap(), 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn filter_keeps_what_the_filters_select() {
+        let (_dir, stores) = backends();
+        let food = Filters { category: Some(CategoryFilter::parse("FOOD/").unwrap()), ..Default::default() };
+        let cases = [
+            (food.clone(), vec!["Milk", "Tea", "Crisps"]),
+            (Filters { exact_category: true, ..food.clone() }, vec!["Milk"]),
+            (Filters { all: true, ..food }, vec!["Milk", "Tea", "Crisps", "Old bread"]),
+            (Filters { category: Some(CategoryFilter::parse("!food").unwrap()), ..Default::default() }, vec!["Ball"]),
+            (Filters { min_price: Some("1.19".parse().unwrap()), max_price: Some("5.49".parse().unwrap()), ..Default::default() }, vec![
+                "Milk", "Tea",
+            ]),
+            (Filters { archived: true, ..Default::default() }, vec!["Old bread"]),
+        ];
+        for (name, store) in &stores {
+            store.append(&sample()).unwrap();
+            for (filters, expected) in &cases {
+                let kept = store.filter(filters).unwrap();
+                assert_eq!(kept.iter().map(|r| r.product.as_str()).collect::<Vec<_>>(), *expected, "{}", name);
+                assert_eq!(records(&kept), records(&filters.apply(store.all().unwrap())), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn commit_saves_removals_rewrites_and_new_rows() {
+        let (_dir, stores) = backends();
+        for (name, store) in &stores {
+            store.append(&sample()).unwrap();
+            let before = store.all().unwrap();
+            let rewritten = Row { price: "5.99".parse().unwrap(), ..before[1].clone() };
+            let rows: Vec<Row> = [rewritten.clone()].into_iter().chain(before[2..].iter().cloned()).chain([row("Rice", "food", "3")]).collect();
+            let changes = Changes { removed: vec![before[0].clone()], updated: vec![(before[1].clone(), rewritten)] };
+            store.commit(&rows, &changes).unwrap();
+            let expected: Vec<Row> = rows.iter().map(|r| Row { id: if r.id == 0 { 6 } else { r.id }, ..r.clone() }).collect();
+            assert_eq!(records(&store.all().unwrap()), records(&expected), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Column-aligned table output for listings.
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::io::{self, IsTerminal};
+
+use crate::config::Config;
+use crate::{age_days, format_price, format_time, relative_age, Money, Row, Status, HEADER};
+use pricepeek::text;
+
+const UNIT_PRICE_HEADER: &str = "unit price";
+/// Short availability markers; unknown stays blank.
+fn stock_marker(status: Status) -> &'static str {
+    match status {
+        Status::InStock => "in",
+        Status::OutOfStock => "out",
+        Status::Unknown => "",
+    }
+}
+
+/// Marks rows whose price is at or below their target.
+const TARGET_HIT: &str = "✓ ";
+/// Follows the timestamp of rows older than `stale_after_days`.
+const STALE_MARK: &str = "*";
+
+const GREEN: &str = "\x1b[32m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+const MIN_URL_WIDTH: usize = 12;
+
+pub struct TableOpts {
+    /// Emit ANSI colors.
+    pub color: bool,
+    /// Terminal width used to truncate URLs; `None` never truncates.
+    pub width: Option<usize>,
+    /// Printed in front of prices without a currency code.
+    pub currency: String,
+    /// Rows older than this many days get `STALE_MARK`; `None` marks nothing.
+    pub stale_after_days: Option<u32>,
+    /// Show timestamps as ages such as "2 h ago".
+    pub relative_time: bool,
+    /// strftime format for timestamps that aren't shown as ages.
+    pub time_format: String,
+    /// Show timestamps in UTC rather than local time.
+    pub utc: bool,
+}
+
+impl TableOpts {
+    /// Colors and truncation only when stdout is a terminal and `NO_COLOR` is unset.
+    /// `full` disables URL truncation.
+    pub fn detect(full: bool, cfg: &Config) -> Self {
+        let tty = io::stdout().is_terminal();
+        let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        let width = if tty && !full {
+            terminal_size::terminal_size().map(|(w, _)| w.0 as usize)
+        } else {
+            None
+        };
+        TableOpts {
+            color: tty && !no_color,
+            width,
+            currency: cfg.currency_symbol.clone(),
+            stale_after_days: cfg.stale_after_days,
+            relative_time: false,
+            time_format: cfg.time_format.clone(),
+            utc: cfg.utc,
+        }
+    }
+}
+
+/// `s` followed by spaces up to `width` terminal columns.
+fn pad(s: &str, width: usize) -> String {
+    format!("{}{}", s, " ".repeat(width.saturating_sub(text::width(s))))
+}
+
+/// `s` preceded by spaces up to `width` terminal columns.
+fn pad_left(s: &str, width: usize) -> String {
+    format!("{}{}", " ".repeat(width.saturating_sub(text::width(s))), s)
+}
+
+/// Rows whose landed price (price plus shipping) is the lowest within their (case-insensitive)
+/// category and currency. Out-of-stock, archived and purchased rows are neither marked nor
+/// compared against.
+fn cheapest_flags(rows: &[Row]) -> Vec<bool> {
+    let buyable = |r: &Row| r.status != Status::OutOfStock && !r.archived && !r.purchased();
+    // Lowest landed price per category and currency, found in one pass so long listings
+    // don't compare every row with every other.
+    let keys: Vec<(String, &str)> = rows.iter().map(|r| (text::fold(&r.category), r.currency.as_str())).collect();
+    let mut lowest: HashMap<&(String, &str), Money> = HashMap::new();
+    for (r, key) in rows.iter().zip(&keys).filter(|(r, _)| buyable(r)) {
+        let low = lowest.entry(key).or_insert(r.landed());
+        *low = (*low).min(r.landed());
+    }
+    rows.iter().zip(&keys).map(|(r, key)| buyable(r) && lowest.get(key) == Some(&r.landed())).collect()
+}
+
+/// Render the table, one line per row plus a header, each ending in a newline.
+pub fn render_table(rows: &[Row], opts: &TableOpts) -> String {
+    let mut out = String::new();
+    let prices: Vec<String> = rows.iter().map(|r| format_price(r.price.to_f64(), &r.currency, &opts.currency)).collect();
+    let unit_prices: Vec<String> = rows
+        .iter()
+        .map(|r| r.unit_price().map_or(String::new(), |(p, u)| format!("{}/{}", format_price(p, &r.currency, &opts.currency), u)))
+        .collect();
+    let targets: Vec<String> = rows
+        .iter()
+        .map(|r| match r.target {
+            Some(t) => {
+                let hit = if r.under_target() { TARGET_HIT } else { "" };
+                format!("{}{}", hit, format_price(t.to_f64(), &r.currency, &opts.currency))
+            }
+            None => String::new(),
+        })
+        .collect();
+    let shipping: Vec<String> = rows
+        .iter()
+        .map(|r| if r.shipping == Default::default() { String::new() } else { format_price(r.shipping.to_f64(), &r.currency, &opts.currency) })
+        .collect();
+    let cheapest = cheapest_flags(rows);
+    let timestamps: Vec<String> = rows
+        .iter()
+        .map(|r| {
+            let stale = opts.stale_after_days.is_some_and(|d| age_days(&r.timestamp).is_some_and(|age| age > i64::from(d)));
+            let shown = if opts.relative_time {
+                relative_age(&r.timestamp)
+            } else {
+                format_time(&r.timestamp, &opts.time_format, opts.utc)
+            };
+            let shown = shown.unwrap_or_else(|| r.timestamp.clone());
+            if stale { format!("{}{}", shown, STALE_MARK) } else { shown }
+        })
+        .collect();
+
+    let ids: Vec<String> = rows.iter().map(|r| r.id.to_string()).collect();
+    let width = |header: &str, cells: &mut dyn Iterator<Item = usize>| cells.fold(header.len(), usize::max);
+    let w_id = width(HEADER[5], &mut ids.iter().map(|i| i.len()));
+    let products: Vec<String> =
+        rows.iter().map(|r| if r.archived { format!("{} (archived)", r.product) } else { r.product.clone() }).collect();
+    let w_product = width(HEADER[0], &mut products.iter().map(|p| text::width(p)));
+    let w_category = width(HEADER[1], &mut rows.iter().map(|r| text::width(&r.category)));
+    let w_price = width(HEADER[2], &mut prices.iter().map(|p| text::width(p)));
+    let w_unit = width(UNIT_PRICE_HEADER, &mut unit_prices.iter().map(|p| text::width(p)));
+    let w_stock = width("stock", &mut rows.iter().map(|r| stock_marker(r.status).len()));
+    let discounts: Vec<String> = rows.iter().map(|r| r.discount().map_or(String::new(), |d| format!("-{:.0}%", d))).collect();
+    let w_discount = width("sale", &mut discounts.iter().map(|d| d.len()));
+    let w_shipping = width(HEADER[13], &mut shipping.iter().map(|s| text::width(s)));
+    let w_target = width(HEADER[12], &mut targets.iter().map(|t| text::width(t)));
+    let w_store = width(HEADER[7], &mut rows.iter().map(|r| text::width(&r.store)));
+    let mut w_url = width(HEADER[3], &mut rows.iter().map(|r| text::width(&r.url)));
+    let w_timestamp = width(HEADER[4], &mut timestamps.iter().map(|t| text::width(t)));
+
+    if let Some(term) = opts.width {
+        let fixed = w_id + w_product + w_category + w_price + w_discount + w_shipping + w_unit + w_target + w_stock + w_store + w_timestamp + 11 * 2;
+        w_url = w_url.min(term.saturating_sub(fixed).max(MIN_URL_WIDTH));
+    }
+
+    let header = format!(
+        "{:>w_id$}  {}  {}  {:>w_price$}  {:>w_discount$}  {:>w_shipping$}  {:>w_unit$}  {:>w_target$}  {}  {}  {}  {}",
+        HEADER[5],
+        pad(HEADER[0], w_product),
+        pad(HEADER[1], w_category),
+        HEADER[2],
+        "sale",
+        HEADER[13],
+        UNIT_PRICE_HEADER,
+        HEADER[12],
+        pad("stock", w_stock),
+        pad(HEADER[7], w_store),
+        pad(HEADER[3], w_url),
+        HEADER[4],
+    );
+    let _ = writeln!(out, "{}", header.trim_end());
+
+    let cells = rows.iter().zip(&ids).zip(&products).zip(&prices).zip(&discounts).zip(&shipping).zip(&unit_prices).zip(&targets);
+    for (((((((((r, id), product), price), discount), shipping), unit_price), target), is_cheapest), timestamp) in
+        cells.zip(cheapest).zip(&timestamps)
+    {
+        let price = pad_left(price, w_price);
+        let (price, timestamp) = if opts.color {
+            let price = if is_cheapest { format!("{}{}{}", GREEN, price, RESET) } else { price };
+            (price, format!("{}{}{}", DIM, timestamp, RESET))
+        } else {
+            (price, timestamp.clone())
+        };
+        let line = format!(
+            "{:>w_id$}  {}  {}  {}  {:>w_discount$}  {}  {}  {}  {}  {}  {}  {}",
+            id,
+            pad(product, w_product),
+            pad(&r.category, w_category),
+            price,
+            discount,
+            pad_left(shipping, w_shipping),
+            pad_left(unit_price, w_unit),
+            pad_left(target, w_target),
+            pad(stock_marker(r.status), w_stock),
+            pad(&r.store, w_store),
+            pad(&text::truncate(&r.url, w_url), w_url),
+            timestamp,
+        );
+        let _ = writeln!(out, "{}", line.trim_end());
+    }
+    out
+}
+
+/// Plain aligned columns, two spaces apart: columns before `first_numeric` are left-aligned,
+/// the rest right-aligned. The first line is the header.
+pub fn render_columns(lines: &[Vec<String>], first_numeric: usize) -> String {
+    let columns = lines.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> =
+        (0..columns).map(|c| lines.iter().filter_map(|l| l.get(c)).map(|s| text::width(s)).max().unwrap_or(0)).collect();
+    let mut out = String::new();
+    for line in lines {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(c, (cell, w))| if c < first_numeric { pad(cell, *w) } else { pad_left(cell, *w) })
+            .collect();
+        let _ = writeln!(out, "{}", cells.join("  ").trim_end());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(width: Option<usize>) -> TableOpts {
+        TableOpts {
+            color: false,
+            width,
+            currency: String::new(),
+            stale_after_days: None,
+            relative_time: false,
+            time_format: "%Y-%m-%d".to_string(),
+            utc: true,
+        }
+    }
+
+    fn row(id: u64, product: &str, url: &str) -> Row {
+        Row {
+            id,
+            product: product.to_string(),
+            category: "drinks".to_string(),
+            price: "3.5".parse().unwrap(),
+            url: url.to_string(),
+            timestamp: "2024-01-02T00:00:00Z".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Terminal column at which `needle` starts in `line`.
+    fn column(line: &str, needle: &str) -> usize {
+        text::width(&line[..line.find(needle).unwrap_or_else(|| panic!("{:?} not in {:?}", needle, line))])
+    }
+
+    #[test]
+    fn wide_and_combining_text_keeps_the_columns_aligned() {
+        let rows = [row(1, "Green tea", ""), row(2, "日本茶", ""), row(3, "Thé glacé\u{301}", ""), row(4, "🍵 Matcha", "")];
+        let table = render_table(&rows, &opts(None));
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(column(lines[0], "category"), column(lines[1], "drinks"));
+        for line in &lines[1..] {
+            assert_eq!(column(line, "drinks"), column(lines[1], "drinks"), "{}", table);
+            assert_eq!(column(line, "3.50"), column(lines[1], "3.50"), "{}", table);
+        }
+    }
+
+    #[test]
+    fn wide_urls_are_cut_to_the_column_width() {
+        let url = "https://例え.jp/お茶/日本茶/緑茶のページ";
+        let rows = [row(1, "日本茶", url), row(2, "Tea", "https://shop.example/tea")];
+        let table = render_table(&rows, &opts(Some(40)));
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[1].contains("https://例…   2024") && !lines[1].contains(url), "{}", table);
+        assert_eq!(column(lines[1], "2024-01-02"), column(lines[2], "2024-01-02"), "{}", table);
+        assert_eq!(column(lines[0], "timestamp"), column(lines[1], "2024-01-02"), "{}", table);
+    }
+
+    #[test]
+    fn columns_pad_by_terminal_width() {
+        let lines = [
+            vec!["product".to_string(), "price".to_string()],
+            vec!["日本茶".to_string(), "3.50".to_string()],
+            vec!["🍵".to_string(), "１２".to_string()],
+        ];
+        assert_eq!(render_columns(&lines, 1), "product  price\n日本茶    3.50\n🍵        １２\n");
+    }
+}
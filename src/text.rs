@@ -0,0 +1,112 @@
+//! Comparison of names, categories and other free text, independent of case and of how
+//! accented letters were typed.
+
+use unicode_normalization::UnicodeNormalization;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Comparison key: trimmed, NFC-normalized and Unicode case-folded, so "Café", "CAFÉ " and a
+/// decomposed "cafe\u{301}" agree, as do "Straße" and "STRASSE".
+pub fn fold(s: &str) -> String {
+    let composed: String = s.trim().nfc().collect();
+    caseless::default_case_fold_str(&composed).nfc().collect()
+}
+
+pub fn matches_ignore_case(a: &str, b: &str) -> bool {
+    fold(a) == fold(b)
+}
+
+/// NFC form of `s`, for matching text that may mix composed and decomposed accents.
+pub fn nfc(s: &str) -> String {
+    s.nfc().collect()
+}
+
+/// Terminal columns `s` takes up: wide CJK characters and most emoji take two, combining
+/// marks none.
+pub fn width(s: &str) -> usize {
+    s.width()
+}
+
+/// `s` cut to at most `max` columns, ending in `…` when anything was cut. Combining marks stay
+/// with the letter before them.
+pub fn truncate(s: &str, max: usize) -> String {
+    if width(s) <= max {
+        return s.to_string();
+    }
+    let room = max.saturating_sub(1);
+    let mut out = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w > room {
+            break;
+        }
+        out.push(c);
+        used += w;
+    }
+    out.push('…');
+    out
+}
+
+/// `s` with surrounding whitespace removed and every inner run of whitespace turned into one space.
+pub fn squeeze(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Each word capitalized and the rest of it lower-cased: "usb-c cable" becomes "Usb-C Cable".
+/// A letter following another letter, a digit or an apostrophe stays lower-case, so "kid's" keeps its `s`.
+pub fn title_case(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut in_word = false;
+    for c in s.chars() {
+        if in_word {
+            out.extend(c.to_lowercase());
+        } else {
+            out.extend(c.to_uppercase());
+        }
+        in_word = c.is_alphanumeric() || c == '\'' || c == '\u{2019}';
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_and_accent_forms_match() {
+        assert!(matches_ignore_case("Café", " CAFÉ "));
+        assert!(matches_ignore_case("Café", "cafe\u{301}"));
+        assert!(matches_ignore_case("Straße", "STRASSE"));
+        assert!(matches_ignore_case("ΣΟΦΙΑ", "σοφια"));
+        assert!(matches_ignore_case("日本茶", "日本茶 "));
+        assert!(!matches_ignore_case("Café", "Cafe"));
+        assert_eq!(nfc("cafe\u{301}"), "café");
+    }
+
+    #[test]
+    fn width_counts_terminal_columns() {
+        assert_eq!(width("tea"), 3);
+        assert_eq!(width("日本茶"), 6);
+        assert_eq!(width("cafe\u{301}"), 4);
+        assert_eq!(width("👍"), 2);
+        assert_eq!(width("ｔｅａ"), 6);
+    }
+
+    #[test]
+    fn truncate_cuts_at_terminal_columns() {
+        assert_eq!(truncate("日本茶", 6), "日本茶");
+        assert_eq!(truncate("日本茶です", 6), "日本…");
+        // A wide character that would straddle the limit is left out whole.
+        assert_eq!(truncate("日本茶です", 5), "日本…");
+        assert_eq!(truncate("cafe\u{301} au lait", 5), "cafe\u{301}…");
+        assert_eq!(truncate("👍👍👍", 4), "👍…");
+        assert_eq!(truncate("abc", 0), "…");
+        assert!(width(&truncate("Grüner Tee 緑茶 🍵 extra", 12)) <= 12);
+    }
+
+    #[test]
+    fn title_case_keeps_non_ascii_letters() {
+        assert_eq!(title_case("éclair au café"), "Éclair Au Café");
+        assert_eq!(title_case("ÖL-FILTER"), "Öl-Filter");
+    }
+}
@@ -0,0 +1,142 @@
+//! Checks applied to new entries by both the menu and the `add` command.
+
+use anyhow::Result;
+
+use crate::money::Money;
+use crate::filter::parse_price;
+use crate::Failure;
+
+/// Outcome of checking a URL.
+pub enum UrlCheck {
+    /// Empty, or a valid http(s) URL.
+    Valid(String),
+    /// Valid once `https://` is prepended; the caller should confirm the fix.
+    MissingScheme(String),
+}
+
+fn invalid(msg: String) -> anyhow::Error {
+    Failure::InvalidInput(msg).into()
+}
+
+pub fn product(s: &str) -> Result<String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(invalid("Product name must not be empty".to_string()));
+    }
+    Ok(s.to_string())
+}
+
+/// Prices must not be negative unless `allow_negative` is set, e.g. for refunds.
+pub fn price(s: &str, allow_negative: bool) -> Result<Money> {
+    let p = parse_price(s)?;
+    if p < Money::default() && !allow_negative {
+        return Err(invalid(format!("Price {} is negative; pass --allow-negative for refunds", p)));
+    }
+    Ok(p)
+}
+
+pub fn is_web_url(s: &str) -> bool {
+    url::Url::parse(s).is_ok_and(|u| matches!(u.scheme(), "http" | "https") && u.host().is_some())
+}
+
+pub fn url(s: &str) -> Result<UrlCheck> {
+    let s = s.trim();
+    if s.is_empty() || is_web_url(s) {
+        return Ok(UrlCheck::Valid(s.to_string()));
+    }
+    let fixed = format!("https://{}", s);
+    if !s.contains("://") && is_web_url(&fixed) {
+        return Ok(UrlCheck::MissingScheme(fixed));
+    }
+    Err(invalid(format!("'{}' is not a valid http(s) URL", s)))
+}
+
+/// `url` with the query parameters named in `strip` removed (a trailing `*` matches any suffix,
+/// so `utm_*` covers `utm_source`), the host lower-cased and trailing slashes dropped. `None`
+/// when it isn't a valid http(s) URL, so callers keep the original.
+pub fn normalize_url(url: &str, strip: &[String]) -> Option<String> {
+    let url = url.trim();
+    if !is_web_url(url) {
+        return None;
+    }
+    let mut u = url::Url::parse(url).ok()?;
+    let stripped = |key: &str| {
+        strip.iter().any(|p| match p.strip_suffix('*') {
+            Some(prefix) => key.to_lowercase().starts_with(&prefix.to_lowercase()),
+            None => key.eq_ignore_ascii_case(p),
+        })
+    };
+    let kept: Vec<(String, String)> = u.query_pairs().filter(|(k, _)| !stripped(k)).map(|(k, v)| (k.into_owned(), v.into_owned())).collect();
+    if kept.is_empty() {
+        u.set_query(None);
+    } else if kept.len() != u.query_pairs().count() {
+        u.query_pairs_mut().clear().extend_pairs(kept);
+    }
+    let path = u.path().trim_end_matches('/').to_string();
+    u.set_path(&path);
+    let text = u.to_string();
+    // The root path always serializes as "/"; drop it when nothing follows.
+    Some(match text.strip_suffix('/') {
+        Some(bare) if u.path() == "/" => bare.to_string(),
+        _ => text,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The message of an `InvalidInput` error; panics on success or any other error.
+    fn rejected<T>(result: Result<T>) -> String {
+        match result.err().and_then(|e| e.downcast::<Failure>().ok()) {
+            Some(Failure::InvalidInput(msg)) => msg,
+            other => panic!("expected invalid input, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn product_names_are_trimmed_and_must_not_be_empty() {
+        assert_eq!(product("  Oat milk \t").unwrap(), "Oat milk");
+        assert_eq!(rejected(product("")), "Product name must not be empty");
+        rejected(product(" \t "));
+    }
+
+    #[test]
+    fn prices_parse_and_negative_ones_need_allowing() {
+        assert_eq!(price(" 19.99 ", false).unwrap().to_string(), "19.99");
+        assert_eq!(price("2,5", false).unwrap().to_string(), "2.50");
+        assert_eq!(price("0", false).unwrap(), Money::default());
+        assert_eq!(price("0.1795", false).unwrap().to_string(), "0.1795");
+        assert_eq!(price("1.50000", false).unwrap().to_string(), "1.50");
+        assert_eq!(rejected(price("-3", false)), "Price -3.00 is negative; pass --allow-negative for refunds");
+        assert_eq!(price("-3", true).unwrap().to_string(), "-3.00");
+        assert_eq!(rejected(price("1.23456", false)), "Invalid price '1.23456': more than four decimal places");
+        assert_eq!(rejected(price("abc", false)), "Invalid price 'abc': not a number");
+        rejected(price("", false));
+        rejected(price("1.2.3", false));
+    }
+
+    #[test]
+    fn urls_without_a_scheme_are_offered_a_fix() {
+        assert!(matches!(url("").unwrap(), UrlCheck::Valid(u) if u.is_empty()));
+        assert!(matches!(url(" https://shop.example/p/1 ").unwrap(), UrlCheck::Valid(u) if u == "https://shop.example/p/1"));
+        assert!(matches!(url("http://shop.example").unwrap(), UrlCheck::Valid(_)));
+        assert!(matches!(url("shop.example/p/1").unwrap(), UrlCheck::MissingScheme(u) if u == "https://shop.example/p/1"));
+        assert_eq!(rejected(url("ftp://shop.example/p/1")), "'ftp://shop.example/p/1' is not a valid http(s) URL");
+        rejected(url("not a url"));
+        rejected(url("https://"));
+    }
+
+    #[test]
+    fn normalize_url_strips_tracking_and_trailing_slashes() {
+        let strip = ["utm_*".to_string(), "ref".to_string()];
+        let normalize = |u: &str| normalize_url(u, &strip);
+        assert_eq!(normalize("https://Shop.Example/p/1/?utm_source=x&UTM_Medium=y&id=5&Ref=z").as_deref(), Some("https://shop.example/p/1?id=5"));
+        assert_eq!(normalize("https://shop.example/?utm_source=x").as_deref(), Some("https://shop.example"));
+        assert_eq!(normalize(" https://shop.example/p/1 ").as_deref(), Some("https://shop.example/p/1"));
+        assert_eq!(normalize("https://shop.example/p?reference=1").as_deref(), Some("https://shop.example/p?reference=1"));
+        assert_eq!(normalize_url("https://shop.example/p?utm_source=x", &[]).as_deref(), Some("https://shop.example/p?utm_source=x"));
+        assert_eq!(normalize("shop.example/p"), None);
+        assert_eq!(normalize(""), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! `pricepeek watch`: redraw the listing whenever the database file changes.
+
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+
+use crate::table::{self, TableOpts};
+use crate::{lock, Ctx, Filters};
+
+/// Events arriving within this window after a change are folded into one redraw,
+/// since a single rewrite shows up as several filesystem events.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+enum Signal {
+    Changed,
+    Quit,
+}
+
+pub fn run(ctx: &Ctx, filters: &Filters) -> Result<()> {
+    let db = Path::new(ctx.db);
+    let file_name = db.file_name().map(|n| n.to_os_string());
+    // Watch the directory rather than the file, so rewrites that replace the file are seen.
+    let dir = match db.parent().filter(|d| !d.as_os_str().is_empty()) {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let quit_tx = tx.clone();
+    ctrlc::set_handler(move || {
+        let _ = quit_tx.send(Signal::Quit);
+    })
+    .context("Install Ctrl-C handler")?;
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            // Our own redraw reads the file, which would otherwise trigger another redraw.
+            if event.kind.is_access() {
+                return;
+            }
+            if event.paths.iter().any(|p| p.file_name().map(|n| n.to_os_string()) == file_name) {
+                let _ = tx.send(Signal::Changed);
+            }
+        }
+    })?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Watch {}", dir.display()))?;
+
+    redraw(ctx, filters)?;
+    while let Ok(signal) = rx.recv() {
+        if let Signal::Quit = signal {
+            break;
+        }
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(Signal::Changed) => continue,
+                Ok(Signal::Quit) => return Ok(()),
+                Err(_) => break,
+            }
+        }
+        redraw(ctx, filters)?;
+    }
+    Ok(())
+}
+
+fn redraw(ctx: &Ctx, filters: &Filters) -> Result<()> {
+    if io::stdout().is_terminal() {
+        print!("\x1b[2J\x1b[H");
+    }
+    if !ctx.quiet {
+        println!("Watching {} (Ctrl-C to exit)\n", ctx.db);
+    }
+    let rows = {
+        let _lock = if ctx.read_only { None } else { Some(lock::shared(ctx.db)?) };
+        ctx.store.filter(filters)?
+    };
+    if rows.is_empty() {
+        println!("No entries.");
+    } else {
+        print!("{}", table::render_table(&rows, &TableOpts::detect(false, ctx.cfg)));
+    }
+    Ok(())
+}
@@ -0,0 +1,320 @@
+//! Runs the `pricepeek` binary against databases in temporary directories.
+
+use std::path::Path;
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use predicates::str::contains;
+use tempfile::TempDir;
+
+/// `pricepeek` with its database at `db` and a config directory of its own in `dir`, so the
+/// user's settings don't leak into the run.
+fn pricepeek(dir: &Path, db: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("pricepeek").unwrap();
+    cmd.env("PRICEPEEK_DB", db)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("HOME", dir)
+        .env_remove("PRICEPEEK_PASSPHRASE");
+    cmd
+}
+
+/// A temporary directory with a database holding two entries.
+fn sample() -> (TempDir, std::path::PathBuf) {
+    let dir = TempDir::new().unwrap();
+    let db = dir.path().join("prices.csv");
+    for (product, price) in [("Milk", "1.19"), ("Bread", "2.49")] {
+        pricepeek(dir.path(), &db)
+            .args(["add", "--product", product, "--category", "food", "--price", price])
+            .assert()
+            .success();
+    }
+    (dir, db)
+}
+
+#[test]
+fn exit_code_is_0_on_success_and_quiet_prints_only_data() {
+    let (dir, db) = sample();
+    pricepeek(dir.path(), &db)
+        .args(["--quiet", "cheapest", "--category", "food"])
+        .assert()
+        .code(0)
+        .stdout(contains("Milk"))
+        .stderr("");
+}
+
+#[test]
+fn exit_code_is_1_when_nothing_matches() {
+    let (dir, db) = sample();
+    pricepeek(dir.path(), &db).args(["cheapest", "--category", "toys"]).assert().code(1);
+    pricepeek(dir.path(), &db).args(["delete", "--id", "99", "--yes"]).assert().code(1);
+}
+
+#[test]
+fn exit_code_is_2_for_invalid_input() {
+    let (dir, db) = sample();
+    pricepeek(dir.path(), &db).args(["add", "--product", "Tea", "--price", "abc"]).assert().code(2);
+    pricepeek(dir.path(), &db).args(["add", "--product", " ", "--price", "1"]).assert().code(2);
+    pricepeek(dir.path(), &db).args(["list", "--since", "7é"]).assert().code(2).stderr(contains("Invalid date '7é'"));
+}
+
+#[test]
+fn exit_code_is_3_when_a_file_cant_be_written() {
+    let (dir, db) = sample();
+    let out = dir.path().join("missing").join("out.csv");
+    pricepeek(dir.path(), &db).args(["export", "-o"]).arg(&out).assert().code(3);
+}
+
+#[test]
+fn a_category_filter_includes_subcategories_and_the_tree_counts_them() {
+    let (dir, db) = sample();
+    for (product, category) in [("Crisps", "Food/Snacks"), ("Juice", "food/drinks/"), ("Seafood", "seafood"), ("Ball", "toys")] {
+        pricepeek(dir.path(), &db).args(["add", "--product", product, "--category", category, "--price", "2"]).assert().success();
+    }
+    let list = |args: &[&str]| {
+        let out = pricepeek(dir.path(), &db).args(["--quiet", "list"]).args(args).assert().success().get_output().stdout.clone();
+        let text = String::from_utf8(out).unwrap();
+        ["Milk", "Bread", "Crisps", "Juice", "Seafood", "Ball"].into_iter().filter(|p| text.contains(p)).collect::<Vec<_>>()
+    };
+    assert_eq!(list(&["--category", "FOOD/"]), ["Milk", "Bread", "Crisps", "Juice"]);
+    assert_eq!(list(&["--category", "food", "--exact-category"]), ["Milk", "Bread"]);
+    assert_eq!(list(&["--category", "food/snacks"]), ["Crisps"]);
+    assert_eq!(list(&["--category", "food,!food/drinks"]), ["Milk", "Bread", "Crisps"]);
+    pricepeek(dir.path(), &db)
+        .args(["categories", "--tree"])
+        .assert()
+        .success()
+        .stdout("food (4)\n  drinks (1)\n  Snacks (1)\nseafood (1)\ntoys (1)\n");
+}
+
+#[test]
+fn spread_shows_unreadable_dates_as_written_and_footnotes_single_observations() {
+    let dir = TempDir::new().unwrap();
+    let db = dir.path().join("prices.csv");
+    std::fs::write(
+        &db,
+        "product,category,price,url,timestamp\n\
+         Mouse,tech,20,,2024-01-01T00:00:00Z\nmouse,tech,25,,yesterday\nMOUSE,tech,30,,\n\
+         Lamp,home,10,,not a date\nLamp,home,12,,also bad\nDesk,home,100,,2024-01-01T00:00:00Z\n",
+    )
+    .unwrap();
+    let table = "product  entries  lowest  lowest seen  highest  highest seen  spread      %\n\
+                 Mouse          3   20.00   2024-01-01    30.00       unknown   10.00  50.0%\n\
+                 Lamp           2   10.00   not a date    12.00      also bad    2.00  20.0%\n";
+    pricepeek(dir.path(), &db)
+        .arg("spread")
+        .assert()
+        .success()
+        .stdout(contains("Most expensive: #6 | Desk"))
+        .stdout(contains(format!("\n\n{}1 products with a single observation not shown.\n", table)));
+    pricepeek(dir.path(), &db).args(["--quiet", "spread"]).assert().success().stdout(contains(table).and(contains("single").not()));
+}
+
+#[test]
+fn a_price_and_shipping_too_large_to_add_up_are_refused_and_saturate_when_read() {
+    let (dir, db) = sample();
+    let huge = ["add", "--product", "Yacht", "--price", "922337203685477", "--shipping", "922337203685477"];
+    pricepeek(dir.path(), &db).args(huge).assert().code(2).stderr(contains("plus shipping 922337203685477.00 is too large"));
+
+    let db = dir.path().join("huge.csv");
+    std::fs::write(
+        &db,
+        "product,category,price,url,timestamp,id,currency,store,notes,tags,quantity,unit,target,shipping\n\
+         Yacht,toys,922337203685477,,2024-01-01T00:00:00Z,1,,,,,,,,922337203685477\n\
+         Yacht,toys,-922337203685477,,2024-01-02T00:00:00Z,2,,,,,,,,\n",
+    )
+    .unwrap();
+    pricepeek(dir.path(), &db).arg("cheapest").assert().success().stdout(contains("Yacht"));
+    pricepeek(dir.path(), &db).arg("list").assert().success().stdout(contains("Yacht"));
+    pricepeek(dir.path(), &db).arg("spread").assert().success().stdout(contains("2024-01-01  922337203685477.62"));
+}
+
+#[test]
+fn restore_counts_the_differences_and_quiet_keeps_them_to_itself() {
+    let (dir, db) = sample();
+    let copy = dir.path().join("copy.csv");
+    std::fs::copy(&db, &copy).unwrap();
+    pricepeek(dir.path(), &db).args(["add", "--product", "Tea", "--category", "food", "--price", "3"]).assert().success();
+    pricepeek(dir.path(), &db).args(["delete", "--id", "1", "--yes"]).assert().success();
+    let copy = copy.to_string_lossy().into_owned();
+    pricepeek(dir.path(), &db)
+        .args(["--dry-run", "restore", &copy, "--yes"])
+        .assert()
+        .success()
+        .stdout(contains("compared with the current 2, restoring brings back 1, removes 1 and changes 0."));
+    pricepeek(dir.path(), &db).args(["--quiet", "restore", &copy, "--yes"]).assert().success().stdout("");
+    pricepeek(dir.path(), &db).args(["--quiet", "list", "--format", "ndjson"]).assert().success().stdout(contains("Milk").and(contains("Tea").not()));
+}
+
+#[test]
+fn list_streams_a_hundred_thousand_rows_through_the_filters() {
+    let dir = TempDir::new().unwrap();
+    let db = dir.path().join("prices.csv");
+    let mut text = String::from("product,category,price,url,timestamp,id\n");
+    for id in 1..=100_000 {
+        let category = if id % 2 == 0 { "toys" } else { "food" };
+        text.push_str(&format!("Item {},{},1.50,,2024-01-01T00:00:00Z,{}\n", id, category, id));
+    }
+    std::fs::write(&db, text).unwrap();
+    let assert = pricepeek(dir.path(), &db).args(["list", "--format", "ndjson", "--category", "toys"]).assert().success().stderr("");
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).into_owned();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 50_000);
+    assert!(lines[0].contains("\"id\":2,") && lines[49_999].contains("\"id\":100000,"), "{}\n{}", lines[0], lines[49_999]);
+}
+
+#[test]
+fn dry_run_leaves_the_database_alone() {
+    let (dir, db) = sample();
+    let before = std::fs::read(&db).unwrap();
+    let dry_run = |args: &[&str]| pricepeek(dir.path(), &db).arg("--dry-run").args(args).assert().success();
+    dry_run(&["add", "--product", "Tea", "--price", "3"]).stdout(contains("would add: #new | Tea"));
+    dry_run(&["update", "Milk", "1.29"]).stdout(contains("would add 1 rows, keep 2 unchanged"));
+    dry_run(&["delete", "--id", "1", "--yes"]).stdout(contains("would delete: #1 | Milk"));
+    dry_run(&["set-priority", "2", "3"]).stdout(contains("would rewrite 1 rows"));
+    pricepeek(dir.path(), &db)
+        .args(["--dry-run", "add", "--stdin"])
+        .write_stdin("Tea,food,3,\n")
+        .assert()
+        .success()
+        .stdout(contains("would add 1 rows"));
+    assert_eq!(std::fs::read(&db).unwrap(), before);
+    pricepeek(dir.path(), &db).args(["log"]).assert().stdout(contains("added Bread")).stdout(contains("Tea").not());
+    assert!(dir.path().join("prices.log.jsonl").is_file());
+}
+
+#[test]
+fn an_outlier_is_compared_with_the_median_in_currency_units() {
+    let (dir, db) = sample();
+    for price in ["19.99", "20.50"] {
+        pricepeek(dir.path(), &db).args(["add", "--product", "Mouse", "--price", price]).assert().success();
+    }
+    pricepeek(dir.path(), &db)
+        .args(["add", "--product", "Mouse", "--price", "1999", "--strict"])
+        .assert()
+        .code(2)
+        .stderr(contains("1999.00 is 98.7 times more than the median 20.25 of 2 earlier prices"));
+}
+
+#[test]
+fn prices_within_the_anomaly_factor_or_without_earlier_ones_are_accepted() {
+    let (dir, db) = sample();
+    let strict_add = |product: &str, price: &str| {
+        pricepeek(dir.path(), &db).args(["add", "--strict", "--product", product, "--price", price]).assert()
+    };
+    // No earlier price to compare with.
+    strict_add("Cable", "1999").success();
+    // Milk has one earlier price, 1.19, and the default factor is 5; rejected prices aren't saved.
+    strict_add("Milk", "6").code(2).stderr(contains("6.00 is 5.0 times more than the median 1.19 of 1 earlier prices"));
+    strict_add("Milk", "0.23").code(2).stderr(contains("0.23 is 5.2 times less than the median 1.19"));
+    strict_add("Milk", "5.90").success();
+    strict_add("Bread", "0.50").success();
+    // Another currency has no baseline of its own.
+    pricepeek(dir.path(), &db)
+        .args(["add", "--strict", "--product", "Milk", "--price", "30", "--currency", "SEK"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn an_anomaly_factor_of_1_turns_the_check_off() {
+    let (dir, db) = sample();
+    let config = dir.path().join("config").join("pricepeek");
+    std::fs::create_dir_all(&config).unwrap();
+    std::fs::write(config.join("config.toml"), "anomaly_factor = 1\n").unwrap();
+    pricepeek(dir.path(), &db).args(["add", "--strict", "--product", "Milk", "--price", "500"]).assert().success();
+}
+
+/// The contents and modification time of `path`, to tell whether anything wrote to it.
+fn snapshot(path: &Path) -> (Vec<u8>, std::time::SystemTime) {
+    (std::fs::read(path).unwrap(), std::fs::metadata(path).unwrap().modified().unwrap())
+}
+
+#[test]
+fn read_only_refuses_every_command_that_writes() {
+    let (dir, db) = sample();
+    let before = snapshot(&db);
+    let other = dir.path().join("other.csv").to_string_lossy().into_owned();
+    let commands: &[&[&str]] = &[
+        &["add", "--product", "Tea", "--price", "3"],
+        &["add", "--stdin"],
+        &["update", "Milk", "1.29"],
+        &["delete", "--id", "1", "--yes"],
+        &["edit", "1"],
+        &["touch", "1"],
+        &["clear"],
+        &["migrate", &other],
+        &["encrypt"],
+        &["decrypt"],
+        &["adjust", "--add", "1", "--yes"],
+        &["target", "set", "Milk", "1"],
+        &["target", "clear", "Milk"],
+        &["archive", "1"],
+        &["unarchive", "1"],
+        &["set-status", "1", "out_of_stock"],
+        &["set-priority", "1", "3"],
+        &["mark-purchased", "1"],
+        &["restore", &other, "--yes"],
+        &["backup", "now"],
+        &["alias", "add", "Milch", "Milk"],
+        &["alias", "remove", "Milch"],
+        &["basket", "add", "1"],
+        &["basket", "remove", "1"],
+        &["basket", "clear"],
+        &["tidy"],
+        &["normalize-urls"],
+        &["dedupe", "--yes"],
+        &["category", "rename", "food", "groceries", "--yes"],
+        &["recategorize"],
+    ];
+    for args in commands {
+        pricepeek(dir.path(), &db)
+            .arg("--read-only")
+            .args(*args)
+            .write_stdin("Tea,food,3,\n")
+            .assert()
+            .code(2)
+            .stderr(contains("isn't available").and(contains("is open read-only")));
+    }
+    assert_eq!(snapshot(&db), before);
+    assert!(!Path::new(&other).exists());
+}
+
+#[test]
+fn read_only_still_reads_and_exports_elsewhere() {
+    let (dir, db) = sample();
+    let before = snapshot(&db);
+    let out = dir.path().join("out.csv");
+    let read_only = |args: &[&str]| pricepeek(dir.path(), &db).arg("--read-only").args(args).assert();
+    read_only(&["list"]).success().stdout(contains("Bread"));
+    read_only(&["cheapest"]).success().stdout(contains("Milk"));
+    read_only(&["search", "bread"]).success();
+    read_only(&["stats"]).success();
+    read_only(&["export", "-o", &out.to_string_lossy()]).success();
+    read_only(&["export", "-o", &db.to_string_lossy()]).code(2);
+    // `watch` only reads; it runs until stopped.
+    pricepeek(dir.path(), &db)
+        .args(["--read-only", "watch"])
+        .timeout(std::time::Duration::from_secs(1))
+        .assert()
+        .interrupted()
+        .stdout(contains("Watching"));
+    assert!(std::fs::read_to_string(&out).unwrap().contains("Bread"));
+    assert_eq!(snapshot(&db), before);
+}
+
+#[test]
+fn read_only_menu_turns_off_changes_and_writes_nothing() {
+    let (dir, db) = sample();
+    let before = snapshot(&db);
+    let out = dir.path().join("out.csv").to_string_lossy().into_owned();
+    // Every option in turn: add, list, cheapest, export (to another file, then over the
+    // database), delete, show, target, search, edit, update and exit.
+    let script = format!("1\n2\n\n3\n\n\n\n\n4\ny\n{}\n\n4\ny\n{}\n\n5\n6\n1\n7\n8\nmilk\n9\n10\n11\n", out, db.display());
+    let assert = pricepeek(dir.path(), &db).arg("--read-only").write_stdin(script).assert().success();
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout).into_owned();
+    for word in ["add", "delete", "target", "edit", "update"] {
+        assert!(stdout.contains(&format!("'{}' is turned off", word)), "{} wasn't refused:\n{}", word, stdout);
+    }
+    assert!(stdout.contains("Exported to") && stdout.contains("export to another file"), "{}", stdout);
+    assert_eq!(snapshot(&db), before);
+}